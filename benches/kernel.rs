@@ -0,0 +1,56 @@
+//! compares nearest center search by blocks with [CenterBlock] against a scan with [Distance::eval].
+//!
+//! run with : cargo bench --bench kernel (add --features simdeez_f to compare with simd distances)
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use rand::distributions::{Distribution, Uniform};
+use rand_xoshiro::rand_core::SeedableRng;
+use rand_xoshiro::Xoshiro256PlusPlus;
+
+use anndists::dist::*;
+
+use coreset::kernel::{CenterBlock, POINT_BLOCK};
+
+// data uniform in [0, 255]^dim as pixels
+fn generate(n: usize, dim: usize, rng: &mut Xoshiro256PlusPlus) -> Vec<Vec<f32>> {
+    let unif = Uniform::<f32>::new(0., 255.);
+    (0..n)
+        .map(|_| (0..dim).map(|_| unif.sample(rng)).collect())
+        .collect()
+}
+
+fn scan<Dist: Distance<f32>>(point: &[f32], centers: &[Vec<f32>], distance: &Dist) -> (usize, f32) {
+    let mut best = (usize::MAX, f32::INFINITY);
+    for (rank, c) in centers.iter().enumerate() {
+        let d = distance.eval(point, c);
+        if d < best.1 {
+            best = (rank, d);
+        }
+    }
+    best
+}
+
+fn bench_nearest(c: &mut Criterion) {
+    let mut rng = Xoshiro256PlusPlus::seed_from_u64(4577);
+    let dim = 784;
+    let points = generate(16 * POINT_BLOCK, dim, &mut rng);
+    let mut group = c.benchmark_group("nearest_l2");
+    for nb_centers in [32, 128, 512] {
+        let centers = generate(nb_centers, dim, &mut rng);
+        let block = CenterBlock::<f32, DistL2>::new(centers.clone());
+        group.bench_with_input(BenchmarkId::new("eval_scan", nb_centers), &centers, |b, centers| {
+            b.iter(|| {
+                let nearest: Vec<(usize, f32)> = points.iter().map(|p| scan(p, centers, &DistL2)).collect();
+                black_box(nearest)
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("center_block", nb_centers), &block, |b, block| {
+            b.iter(|| black_box(block.nearest_block(&points, &DistL2)))
+        });
+    }
+    group.finish();
+} // end of bench_nearest
+
+criterion_group!(benches, bench_nearest);
+criterion_main!(benches);
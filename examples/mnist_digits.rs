@@ -62,15 +62,15 @@ fn marrupaxton<Dist: Distance<f32> + Sync + Send + Clone>(
     //
     let mpalgo = MettuPlaxton::<f32, Dist>::new(images, distance);
     let alfa = 1.;
-    let mut facilities = mpalgo.construct_centers(alfa);
+    let mut facilities = mpalgo.construct_centers(alfa).unwrap();
     //
-    let (entropies, labels_distribution) = facilities.dispatch_labels(images, labels, None);
+    let (entropies, labels_distribution) = facilities.dispatch_labels(images, labels, None).unwrap();
     //
     let nb_facility = facilities.len();
     for i in 0..nb_facility {
-        let facility = facilities.get_facility(i).unwrap();
+        let facility = facilities.get_cloned_facility(i).unwrap();
         log::info!("\n\n facility : {:?}, entropy : {:.3e}", i, entropies[i]);
-        facility.read().log();
+        facility.log();
         let map = &labels_distribution[i];
         for (key, val) in map.iter() {
             println!("key: {key} val: {val}");
@@ -93,7 +93,7 @@ fn bmor<Dist: Distance<f32> + Sync + Send + Clone>(
     // we increase a little coefficients to get more facilities
     let beta = 2.2;
     let gamma = 2.2;
-    let mut bmor_algo = Bmor::new(10, 70000, beta, gamma, distance);
+    let mut bmor_algo = Bmor::new(10, 70000, beta, gamma, distance, CostFunction::KMedian);
     //
     let ids = (0..images.len()).collect::<Vec<usize>>();
     let res = bmor_algo.process_data(images, &ids);
@@ -105,15 +105,15 @@ fn bmor<Dist: Distance<f32> + Sync + Send + Clone>(
     // do we ask for a supplementary contraction pass
     let contraction = false;
     //============================
-    let mut facilities = bmor_algo.end_data(contraction);
+    let mut facilities = bmor_algo.end_data(contraction).unwrap();
     //
-    let (entropies, labels_distribution) = facilities.dispatch_labels(images, labels, None);
+    let (entropies, labels_distribution) = facilities.dispatch_labels(images, labels, None).unwrap();
     //
     let nb_facility = facilities.len();
     for i in 0..nb_facility {
-        let facility = facilities.get_facility(i).unwrap();
+        let facility = facilities.get_cloned_facility(i).unwrap();
         log::info!("\n\n facility : {:?}, entropy : {:.3e}", i, entropies[i]);
-        facility.read().log();
+        facility.log();
         let map = &labels_distribution[i];
         for (key, val) in map.iter() {
             println!("key: {key} val: {val}");
@@ -37,15 +37,15 @@ fn marrupaxton<Dist: Distance<f32> + Sync + Send + Clone>(
     //
     let mpalgo = MettuPlaxton::<f32, Dist>::new(images, distance);
     let alfa = 0.75;
-    let mut facilities = mpalgo.construct_centers(alfa);
+    let mut facilities = mpalgo.construct_centers(alfa).unwrap();
     //
-    let (entropies, labels_distribution) = facilities.dispatch_labels(images, labels, None);
+    let (entropies, labels_distribution) = facilities.dispatch_labels(images, labels, None).unwrap();
     //
     let nb_facility = facilities.len();
     for i in 0..nb_facility {
-        let facility = facilities.get_facility(i).unwrap();
+        let facility = facilities.get_cloned_facility(i).unwrap();
         log::info!("\n\n facility : {:?}, entropy : {:.3e}", i, entropies[i]);
-        facility.read().log();
+        facility.log();
         let map = &labels_distribution[i];
         for (key, val) in map.iter() {
             println!("key: {key} val: {val}");
@@ -68,7 +68,8 @@ fn bmor<Dist: Distance<f32> + Sync + Send + Clone>(
     // if beta increases , upper bound on cost increases faster so the number of phases decreases
     let beta = 2.;
     let gamma = 2.;
-    let mut bmor_algo: Bmor<usize, f32, Dist> = Bmor::new(10, 70000, beta, gamma, distance);
+    let mut bmor_algo: Bmor<usize, f32, Dist> =
+        Bmor::new(10, 70000, beta, gamma, distance, CostFunction::KMedian);
     //
     let ids = (0..images.len()).collect::<Vec<usize>>();
     let res = bmor_algo.process_data(images, &ids);
@@ -78,15 +79,15 @@ fn bmor<Dist: Distance<f32> + Sync + Send + Clone>(
     //
     // do we ask for a supplementary contraction pass
     let contraction = false;
-    let mut facilities = bmor_algo.end_data(contraction);
+    let mut facilities = bmor_algo.end_data(contraction).unwrap();
     //
-    let (entropies, labels_distribution) = facilities.dispatch_labels(images, labels, None);
+    let (entropies, labels_distribution) = facilities.dispatch_labels(images, labels, None).unwrap();
     //
     let nb_facility = facilities.len();
     for i in 0..nb_facility {
-        let facility = facilities.get_facility(i).unwrap();
+        let facility = facilities.get_cloned_facility(i).unwrap();
         log::info!("\n\n facility : {:?}, entropy : {:.3e}", i, entropies[i]);
-        facility.read().log();
+        facility.log();
         let map = &labels_distribution[i];
         for (key, val) in map.iter() {
             println!("key: {key} val: {val}");
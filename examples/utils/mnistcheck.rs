@@ -199,7 +199,14 @@ pub fn coreset1<Dist: Distance<f32> + Sync + Send + Clone>(
     let beta = 2.;
     let gamma = 2.;
     let k = 10; // as we have 10 classes, but this gives a lower bound
-    let mut core1 = Coreset1::new(k, images.len(), beta, gamma, distance.clone());
+    let mut core1 = Coreset1::new(
+        k,
+        images.len(),
+        beta,
+        gamma,
+        distance.clone(),
+        CostFunction::KMedian,
+    );
     //
     let res = core1.make_coreset(&producer, 0.11);
     if res.is_err() {
@@ -218,8 +225,8 @@ pub fn coreset1<Dist: Distance<f32> + Sync + Send + Clone>(
             log::info!("\n\n doing kmedoid clustering using L1");
             log::info!("===================================");
             let nb_cluster = 10;
-            let mut kmedoids = Kmedoid::new(&coreset, nb_cluster);
-            kmedoids.compute_medians(25);
+            let mut kmedoids = Kmedoid::new(&coreset, nb_cluster).unwrap();
+            kmedoids.compute_medians(25, InitMethod::MaxCost).unwrap();
             let clusters = kmedoids.get_clusters();
             let mut centers = Vec::<Vec<f32>>::with_capacity(nb_cluster);
             for c in clusters {
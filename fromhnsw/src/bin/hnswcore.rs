@@ -167,7 +167,11 @@ fn parse_coreset_cmd(matches: &ArgMatches) -> Result<CoresetParams, anyhow::Erro
 /// The cuurent function dispatch to u16, u32, u64, i32, i64, f32 and f64 according to typename.
 /// For another type, the functio is easily modifiable.  
 /// The only constraints on T comes from hnsw and is T: 'static + Clone + Sized + Send + Sync + std::fmt::Debug
-pub fn get_datamap(directory: String, basename: String, typename: &str) -> anyhow::Result<DataMap> {
+pub fn get_datamap(
+    directory: String,
+    basename: String,
+    typename: &str,
+) -> Result<DataMap, CoresetError> {
     //
     let datamap = match typename {
         "u16" => get_typed_datamap::<u16>(directory, basename),
@@ -182,7 +186,10 @@ pub fn get_datamap(directory: String, basename: String, typename: &str) -> anyho
                 "get_datamap : unimplemented type, type received : {}",
                 typename
             );
-            std::panic!("get_datamap : unimplemented type");
+            return Err(CoresetError::InvalidParameter(format!(
+                "get_datamap : unimplemented type {}",
+                typename
+            )));
         }
     };
     //
@@ -268,7 +275,14 @@ where
     // now do we have only coreset computation or also clusterization
     if coreparams.get_cluster() == 0 {
         let k = 10; // as we have 10 classes, but this gives a lower bound
-        let mut core1 = Coreset1::<usize, T, Dist>::new(k, nb_data, beta, gamma, distance.clone());
+        let mut core1 = Coreset1::<usize, T, Dist>::new(
+            k,
+            nb_data,
+            beta,
+            gamma,
+            distance.clone(),
+            CostFunction::KMedian,
+        );
         //
         let res = core1.make_coreset(&iter_producer, freduc);
         if res.is_err() {
@@ -283,10 +297,22 @@ where
         // we must do coreset + clusterization
         let bmor_arg = BmorArg::new(nb_data, beta, gamma);
         let nb_max_kmedoid_iter = 15;
-        let mut clustercoreset =
-            ClusterCoreset::<usize, T>::new(coreparams.get_cluster(), freduc, bmor_arg);
-        clustercoreset.compute(distance.clone(), nb_max_kmedoid_iter, &iter_producer);
-        clustercoreset.dispatch(&distance, &iter_producer);
+        let mut clustercoreset = ClusterCoreset::<usize, T>::new(
+            coreparams.get_cluster(),
+            freduc,
+            bmor_arg,
+            CostFunction::KMedian,
+        );
+        let compute_res =
+            clustercoreset.compute(distance.clone(), nb_max_kmedoid_iter, &iter_producer);
+        if compute_res.is_err() {
+            log::error!("error occurred computing coreset clustering");
+        } else {
+            let dispatch_res = clustercoreset.dispatch(&distance, &iter_producer);
+            if dispatch_res.is_err() {
+                log::error!("error occurred dispatching data to clusters");
+            }
+        }
     }
     // dump a csv with membership.
     //
@@ -4,7 +4,7 @@ use log;
 
 use std::path::Path;
 
-use anyhow;
+use coreset::error::CoresetError;
 
 use hnsw_rs::datamap::*;
 
@@ -12,7 +12,7 @@ use hnsw_rs::datamap::*;
 pub fn get_typed_datamap<T: 'static + std::fmt::Debug>(
     directory: String,
     basename: String,
-) -> anyhow::Result<DataMap> {
+) -> Result<DataMap, CoresetError> {
     //
     let path = Path::new(&directory);
     let res = DataMap::from_hnswdump::<T>(path, &basename);
@@ -22,6 +22,10 @@ pub fn get_typed_datamap<T: 'static + std::fmt::Debug>(
             directory,
             basename
         );
+        return Err(CoresetError::MissingData(format!(
+            "could not get datamap from hnsw, directory {}, basename : {}",
+            directory, basename
+        )));
     }
     let datamap = res.unwrap();
     let t_name = datamap.get_data_typename();
@@ -32,7 +36,12 @@ pub fn get_typed_datamap<T: 'static + std::fmt::Debug>(
             "bad type name. registered type name : {}, you asked for {}",
             t_name,
             std::any::type_name::<T>().to_string()
-        )
+        );
+        return Err(CoresetError::Incompatible(format!(
+            "bad type name. registered type name : {}, you asked for {}",
+            t_name,
+            std::any::type_name::<T>()
+        )));
     }
     //
     Ok(datamap)
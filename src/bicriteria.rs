@@ -0,0 +1,467 @@
+//! Bicriteria (alfa, beta) approximations used as input to sensitivity sampling.
+//!
+//! Sensitivity sampling ([Coreset1](crate::sensitivity::Coreset1)) needs a set of facilities whose cost is within a constant factor (alfa)
+//! of the optimal clustering cost, the number of facilities being allowed to exceed k by a factor (beta).
+//! The trait [BicriteriaApprox] abstracts the construction of these facilities so that coreset quality can be compared across approximations:
+//!   - [Bmor](crate::bmor::Bmor), streaming, one pass on data.
+//!   - [MettuPlaxtonApprox] and [WeightedMettuPlaxtonApprox] wrapping algorithms of module [imp](crate::imp). They load all data in memory.
+//!   - [KmeansPlusPlus] the D^z seeding of Arthur and Vassilvitskii, one pass on data for each center.
+//!
+
+use rand::distributions::{Distribution, Uniform};
+use rand_xoshiro::rand_core::SeedableRng;
+use rand_xoshiro::Xoshiro256PlusPlus;
+
+use anndists::dist::*;
+
+use crate::bmor::*;
+use crate::error::CoresetError;
+use crate::facility::*;
+use crate::imp::*;
+use crate::makeiter::*;
+
+/// An algorithm providing facilities (with respect to which sensitivities are computed) from weighted data given by a [MakeIter].
+/// Items are (DataId, weight, data vector), unweighted data can be adapted with [UnitWeight].  
+/// Facilities must be data points. Their weight and cost need not be consistent as they are recomputed by the coreset construction.
+pub trait BicriteriaApprox<DataId, T: Send + Sync + Clone, Dist: Distance<T>> {
+    /// computes facilities for data provided by iter_producer.
+    fn build_facilities<IterProducer>(
+        &mut self,
+        iter_producer: &IterProducer,
+    ) -> Result<Facilities<DataId, T, Dist>, CoresetError>
+    where
+        IterProducer: MakeIter<Item = (DataId, f64, Vec<T>)>;
+
+    /// sets the seed of random generators used by the algorithm. Default does nothing, for deterministic algorithms.
+    fn set_seed(&mut self, _seed: u64) {}
+}
+
+impl<DataId, T, Dist> BicriteriaApprox<DataId, T, Dist> for Bmor<DataId, T, Dist>
+where
+    DataId: std::fmt::Debug + Clone + Send + Sync,
+    T: Send + Sync + Clone,
+    Dist: Distance<T> + Clone + Send + Sync,
+{
+    /// one streaming pass of bmor, data are processed by blocks.
+    fn build_facilities<IterProducer>(
+        &mut self,
+        iter_producer: &IterProducer,
+    ) -> Result<Facilities<DataId, T, Dist>, CoresetError>
+    where
+        IterProducer: MakeIter<Item = (DataId, f64, Vec<T>)>,
+    {
+        // TODO: adapt bufsize to memory/cpu
+        let bufsize: usize = 50000;
+        let mut datas = Vec::<(f64, Vec<T>, DataId)>::with_capacity(bufsize);
+        //
+        let mut iter = iter_producer.makeiter();
+        loop {
+            datas.extend(iter.by_ref().take(bufsize).map(|(id, w, d)| (w, d, id)));
+            if datas.is_empty() {
+                break;
+            }
+            let weighted_data: Vec<(f64, &Vec<T>, DataId)> =
+                datas.iter().map(|d| (d.0, &d.1, d.2.clone())).collect();
+            self.process_weighted_data(&weighted_data)?;
+            self.log();
+            datas.clear();
+        }
+        //
+        self.end_data(false)
+    } // end of build_facilities
+
+    fn set_seed(&mut self, seed: u64) {
+        Bmor::set_seed(self, seed);
+    }
+} // end of impl BicriteriaApprox for Bmor
+
+//==================================================================================
+
+// load all data in memory for algorithms that are not streaming
+fn collect_data<DataId, T, IterProducer>(
+    iter_producer: &IterProducer,
+) -> (Vec<DataId>, Vec<f32>, Vec<Vec<T>>)
+where
+    IterProducer: MakeIter<Item = (DataId, f64, Vec<T>)>,
+{
+    let mut ids = Vec::<DataId>::new();
+    let mut weights = Vec::<f32>::new();
+    let mut datas = Vec::<Vec<T>>::new();
+    for (id, weight, data) in iter_producer.makeiter() {
+        ids.push(id);
+        weights.push(weight as f32);
+        datas.push(data);
+    }
+    log::debug!("collect_data loaded {} data", datas.len());
+    (ids, weights, datas)
+}
+
+// Mettu-Plaxton algorithms identify facilities by rank in data, we go back to DataId
+fn rank_to_id<DataId, T, Dist>(
+    facilities: &Facilities<usize, T, Dist>,
+    ids: &[DataId],
+) -> Facilities<DataId, T, Dist>
+where
+    DataId: std::fmt::Debug + Clone + Send + Sync,
+    T: Send + Sync + Clone,
+    Dist: Distance<T> + Clone + Send + Sync,
+{
+    let mut with_ids = Facilities::<DataId, T, Dist>::new(
+        facilities.len(),
+        facilities.get_distance().clone(),
+        facilities.get_cost_function(),
+    );
+    with_ids.set_index(facilities.get_index_arg());
+    for f in facilities.to_facility_vec() {
+        let mut facility = Facility::new(ids[f.get_dataid()].clone(), f.get_position());
+        facility.insert(f.get_weight(), 0.);
+        with_ids.insert(facility);
+    }
+    with_ids
+} // end of rank_to_id
+
+/// Wraps [MettuPlaxton] as a [BicriteriaApprox]. **All data are loaded in memory**.  
+/// This algorithm supposes uniform weights, data with weights different from 1. are rejected (see [WeightedMettuPlaxtonApprox]).
+pub struct MettuPlaxtonApprox<Dist> {
+    // drives the number of facilities created, see [MettuPlaxton::construct_centers]
+    alfa: f32,
+    //
+    distance: Dist,
+    // seed passed to MettuPlaxton
+    seed: u64,
+}
+
+impl<Dist> MettuPlaxtonApprox<Dist> {
+    /// alfa is the argument of [MettuPlaxton::construct_centers()]
+    pub fn new(alfa: f32, distance: Dist) -> Self {
+        MettuPlaxtonApprox {
+            alfa,
+            distance,
+            seed: 123,
+        }
+    }
+}
+
+impl<DataId, T, Dist> BicriteriaApprox<DataId, T, Dist> for MettuPlaxtonApprox<Dist>
+where
+    DataId: std::fmt::Debug + Clone + Send + Sync,
+    T: Send + Sync + Clone,
+    Dist: Distance<T> + Clone + Send + Sync,
+{
+    fn build_facilities<IterProducer>(
+        &mut self,
+        iter_producer: &IterProducer,
+    ) -> Result<Facilities<DataId, T, Dist>, CoresetError>
+    where
+        IterProducer: MakeIter<Item = (DataId, f64, Vec<T>)>,
+    {
+        let (ids, weights, datas) = collect_data(iter_producer);
+        if datas.len() < 2 {
+            return Err(CoresetError::NotEnoughData(
+                "MettuPlaxtonApprox needs at least 2 data".to_string(),
+            ));
+        }
+        if weights.iter().any(|w| *w != 1.) {
+            return Err(CoresetError::InvalidParameter(
+                "MettuPlaxtonApprox needs unit weights, use WeightedMettuPlaxtonApprox".to_string(),
+            ));
+        }
+        let mut mp = MettuPlaxton::new(&datas, self.distance.clone());
+        mp.set_seed(self.seed);
+        let facilities = mp.construct_centers(self.alfa)?;
+        log::info!("MettuPlaxtonApprox nb facilities : {}", facilities.len());
+        Ok(rank_to_id(&facilities, &ids))
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+} // end of impl BicriteriaApprox for MettuPlaxtonApprox
+
+/// Wraps [WeightedMettuPlaxton] as a [BicriteriaApprox].
+/// **All data are loaded in memory and the complexity is O(n²)**, so it is only adapted to small data sets.
+pub struct WeightedMettuPlaxtonApprox<Dist> {
+    // drives the number of facilities created, see [WeightedMettuPlaxton::construct_centers]
+    alfa: f32,
+    //
+    distance: Dist,
+}
+
+impl<Dist> WeightedMettuPlaxtonApprox<Dist> {
+    /// alfa is the argument of [WeightedMettuPlaxton::construct_centers()]. 0.5 is a good guess.
+    pub fn new(alfa: f32, distance: Dist) -> Self {
+        WeightedMettuPlaxtonApprox { alfa, distance }
+    }
+}
+
+impl<DataId, T, Dist> BicriteriaApprox<DataId, T, Dist> for WeightedMettuPlaxtonApprox<Dist>
+where
+    DataId: std::fmt::Debug + Clone + Send + Sync,
+    T: Send + Sync + Clone + 'static,
+    Dist: Distance<T> + Clone + Send + Sync + 'static,
+{
+    fn build_facilities<IterProducer>(
+        &mut self,
+        iter_producer: &IterProducer,
+    ) -> Result<Facilities<DataId, T, Dist>, CoresetError>
+    where
+        IterProducer: MakeIter<Item = (DataId, f64, Vec<T>)>,
+    {
+        let (ids, weights, datas) = collect_data(iter_producer);
+        if datas.len() < 2 {
+            return Err(CoresetError::NotEnoughData(
+                "WeightedMettuPlaxtonApprox needs at least 2 data".to_string(),
+            ));
+        }
+        let wmp = WeightedMettuPlaxton::new(&datas, &weights, self.distance.clone());
+        let facilities = wmp.construct_centers(self.alfa)?;
+        log::info!(
+            "WeightedMettuPlaxtonApprox nb facilities : {}",
+            facilities.len()
+        );
+        Ok(rank_to_id(&facilities, &ids))
+    }
+} // end of impl BicriteriaApprox for WeightedMettuPlaxtonApprox
+
+//==================================================================================
+
+// default seed of KmeansPlusPlus sampling
+const KMEANSPP_DEFAULT_SEED: u64 = 4664397;
+
+#[cfg_attr(doc, katexit::katexit)]
+/// k-means++ seeding (Arthur Vassilvitskii 2007) generalized to cost $dist^z$:
+/// each new center is sampled with probability proportional to the weighted cost of a point to its nearest center already chosen.
+/// The sampling is done by weighted reservoir sampling so each center needs one pass on data, and a last pass dispatches data
+/// to facilities. Asking for more centers than k (say k * log(k)) gives a bicriteria approximation with a better cost.  
+/// Each point keeps its nearest center found so far, so a pass only computes distances to the last center sampled
+/// and the whole seeding costs O(n * nb_centers) distances. **This needs a buffer of O(n) (rank, distance) couples**.
+pub struct KmeansPlusPlus<Dist> {
+    // number of centers to sample
+    nb_centers: usize,
+    //
+    distance: Dist,
+    //
+    cost_fn: CostFunction,
+    // seed of the sampling random generator, reset at each construction
+    seed: u64,
+}
+
+impl<Dist> KmeansPlusPlus<Dist> {
+    /// - nb_centers : number of centers (facilities) to sample
+    /// - cost_fn : [CostFunction::KMeans] gives the original k-means++ seeding
+    ///
+    /// The default seed (4664397) can be changed by [set_seed](BicriteriaApprox::set_seed()),
+    /// [Coreset1::set_seed()](crate::sensitivity::Coreset1::set_seed()) sets a seed derived from its own.
+    pub fn new(nb_centers: usize, distance: Dist, cost_fn: CostFunction) -> Self {
+        KmeansPlusPlus {
+            nb_centers,
+            distance,
+            cost_fn,
+            seed: KMEANSPP_DEFAULT_SEED,
+        }
+    }
+
+    /// returns the seed of the sampling random generator
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+impl<DataId, T, Dist> BicriteriaApprox<DataId, T, Dist> for KmeansPlusPlus<Dist>
+where
+    DataId: std::fmt::Debug + Clone + Send + Sync,
+    T: Send + Sync + Clone,
+    Dist: Distance<T> + Clone + Send + Sync,
+{
+    fn build_facilities<IterProducer>(
+        &mut self,
+        iter_producer: &IterProducer,
+    ) -> Result<Facilities<DataId, T, Dist>, CoresetError>
+    where
+        IterProducer: MakeIter<Item = (DataId, f64, Vec<T>)>,
+    {
+        //
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(self.seed);
+        let unif = Uniform::<f64>::new(0., 1.);
+        let mut centers = Vec::<(DataId, Vec<T>)>::with_capacity(self.nb_centers);
+        // for each point, rank of its nearest center and distance to it
+        let mut nearest = Vec::<(usize, f32)>::new();
+        while centers.len() < self.nb_centers {
+            // weighted reservoir sampling of size 1, first center is sampled uniformly
+            let mut total_cost = 0.0f64;
+            let mut chosen: Option<(DataId, Vec<T>)> = None;
+            let last = centers.len().checked_sub(1);
+            for (i, (id, weight, data)) in iter_producer.makeiter().enumerate() {
+                let cost = match last {
+                    None => weight,
+                    Some(last) => {
+                        let dist = self.distance.eval(&centers[last].1, &data);
+                        if i == nearest.len() {
+                            nearest.push((last, dist));
+                        } else if dist < nearest[i].1 {
+                            nearest[i] = (last, dist);
+                        }
+                        weight * self.cost_fn.eval(nearest[i].1) as f64
+                    }
+                };
+                if cost <= 0. {
+                    continue;
+                }
+                total_cost += cost;
+                if unif.sample(&mut rng) * total_cost < cost {
+                    chosen = Some((id, data));
+                }
+            }
+            match chosen {
+                Some(center) => centers.push(center),
+                // all data are centers
+                None => break,
+            }
+            log::debug!(
+                "KmeansPlusPlus center {}, cost before sampling : {:.3e}",
+                centers.len(),
+                total_cost
+            );
+        }
+        if centers.is_empty() {
+            return Err(CoresetError::NotEnoughData(
+                "KmeansPlusPlus got no data".to_string(),
+            ));
+        }
+        //
+        let mut facilities =
+            Facilities::<DataId, T, Dist>::new(centers.len(), self.distance.clone(), self.cost_fn);
+        for (id, data) in &centers {
+            facilities.insert(Facility::new(id.clone(), data));
+        }
+        // dispatch pass to get weights and costs of facilities, only the last center sampled is new
+        let last = centers.len() - 1;
+        for (i, (_, weight, data)) in iter_producer.makeiter().enumerate() {
+            let dist = self.distance.eval(&centers[last].1, &data);
+            let (rank, dist) = match nearest.get(i) {
+                Some(&(rank, dist_rank)) if dist_rank <= dist => (rank, dist_rank),
+                _ => (last, dist),
+            };
+            facilities.insert_point(rank, dist, weight);
+        }
+        facilities.log(0);
+        //
+        Ok(facilities)
+    } // end of build_facilities
+
+    fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+} // end of impl BicriteriaApprox for KmeansPlusPlus
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::sensitivity::*;
+    use rand_distr::Normal;
+
+    fn log_init_test() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    struct DataForIter {
+        datas: Vec<Vec<f32>>,
+    }
+
+    impl MakeIter for DataForIter {
+        type Item = (usize, Vec<f32>);
+        //
+        fn makeiter(&self) -> impl Iterator<Item = Self::Item> {
+            self.datas.iter().cloned().enumerate()
+        }
+    }
+
+    // nbdata points around 5 gaussian centers
+    fn generate_data(nbdata: usize) -> DataForIter {
+        let dim = 10;
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(1454691);
+        let unif = Uniform::<f32>::new(-20., 20.);
+        let centers: Vec<Vec<f32>> = (0..5)
+            .map(|_| (0..dim).map(|_| unif.sample(&mut rng)).collect())
+            .collect();
+        let normal = Normal::<f32>::new(0., 1.).unwrap();
+        let datas: Vec<Vec<f32>> = (0..nbdata)
+            .map(|i| {
+                centers[i % 5]
+                    .iter()
+                    .map(|x| x + normal.sample(&mut rng))
+                    .collect()
+            })
+            .collect();
+        DataForIter { datas }
+    } // end of generate_data
+
+    #[test]
+    fn test_coreset_kmeanspp() {
+        log_init_test();
+        //
+        let nbdata = 10000;
+        let iter_producer = generate_data(nbdata);
+        //
+        let approx = KmeansPlusPlus::new(20, DistL2, CostFunction::KMeans);
+        let mut coreset1 = Coreset1::with_approx(approx, CostFunction::KMeans);
+        let coreset = coreset1.make_coreset(&iter_producer, 0.1).unwrap();
+        assert_eq!(coreset1.get_nb_data(), nbdata);
+        let total_weight: f64 = coreset.get_items().map(|(_, w)| *w).sum();
+        log::info!(
+            "coreset size : {}, total weight : {:.3e}",
+            coreset.get_nb_points(),
+            total_weight
+        );
+        assert!((total_weight / nbdata as f64 - 1.).abs() < 0.1);
+        // facilities only depend on the seed
+        let mut approx = KmeansPlusPlus::new(20, DistL2, CostFunction::KMeans);
+        let unit = UnitWeight::new(&iter_producer);
+        let facilities: Facilities<usize, f32, DistL2> = approx.build_facilities(&unit).unwrap();
+        let facilities_2: Facilities<usize, f32, DistL2> = approx.build_facilities(&unit).unwrap();
+        assert_eq!(facilities.len(), 20);
+        assert!((facilities.get_weight() - nbdata as f64).abs() < 1.0e-6);
+        for i in 0..facilities.len() {
+            assert_eq!(facilities.get_dataid(i), facilities_2.get_dataid(i));
+        }
+        BicriteriaApprox::<usize, f32, DistL2>::set_seed(&mut approx, 7451);
+        assert_eq!(approx.get_seed(), 7451);
+        let facilities_3: Facilities<usize, f32, DistL2> = approx.build_facilities(&unit).unwrap();
+        assert!((0..facilities.len()).any(|i| facilities.get_dataid(i) != facilities_3.get_dataid(i)));
+    } // end of test_coreset_kmeanspp
+
+    #[test]
+    fn test_coreset_mettu_plaxton() {
+        log_init_test();
+        // Mettu-Plaxton approximations load all data and the weighted one is quadratic, data are kept small
+        let nbdata = 2000;
+        let fraction = 0.25;
+        let iter_producer = generate_data(nbdata);
+        //
+        let check = |coreset: CoreSet<usize, f32, DistL2>, name: &str| {
+            let total_weight: f64 = coreset.get_items().map(|(_, w)| *w).sum();
+            log::info!(
+                "{} coreset size : {}, total weight : {:.3e}",
+                name,
+                coreset.get_nb_points(),
+                total_weight
+            );
+            assert!(coreset.get_nb_points() > 0);
+            assert!(coreset.get_nb_points() <= (fraction * nbdata as f64) as usize);
+            assert!((total_weight / nbdata as f64 - 1.).abs() < 0.1);
+        };
+        //
+        let approx = MettuPlaxtonApprox::new(0.5, DistL2);
+        let mut coreset1 = Coreset1::with_approx(approx, CostFunction::KMedian);
+        let coreset = coreset1.make_coreset(&iter_producer, fraction).unwrap();
+        assert_eq!(coreset1.get_nb_data(), nbdata);
+        check(coreset, "MettuPlaxtonApprox");
+        //
+        let approx = WeightedMettuPlaxtonApprox::new(0.5, DistL2);
+        let mut coreset1 = Coreset1::with_approx(approx, CostFunction::KMedian);
+        let coreset = coreset1.make_coreset(&iter_producer, fraction).unwrap();
+        assert_eq!(coreset1.get_nb_data(), nbdata);
+        check(coreset, "WeightedMettuPlaxtonApprox");
+    } // end of test_coreset_mettu_plaxton
+} // end of mod tests
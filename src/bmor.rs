@@ -9,21 +9,26 @@
 //!
 //! We do not constrain the clustering output to be exactly some value k but let the number of clusters be
 //! the result of the main algorithms.   
+//! Exactly k centers can be obtained at end of stream with [end_data_k](Bmor::end_data_k()).
 //!   
 //! **Bmor algorithm dispatch points on the fly so it computes an upper bound of the cost**.  
 //! **But it is possible to [dispatch_data](crate::facility::Facilities::dispatch_data()) explicitly**
 //!
 //! This algorithm can process mnist fashion data in 1 second on a i9 laptop (without requiring heavy multithreading)
 //!
+//! The state of a running stream can be saved with [checkpoint](Bmor::checkpoint()) and resumed with [restore](Bmor::restore()).
+//! For drifting data, weights of facilities can decay exponentially with [set_decay](Bmor::set_decay()).
 //!
 
+use std::collections::HashMap;
 use std::marker::PhantomData;
 
-use parking_lot::RwLock;
-use std::cell::RefCell;
-use std::sync::Arc;
+use std::cell::{Cell, RefCell};
+use std::time::{Duration, Instant, SystemTime};
 
-use anyhow::anyhow;
+use std::io::{Read, Write};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use rand::distributions::{Distribution, Uniform};
 use rand_xoshiro::rand_core::SeedableRng;
@@ -31,7 +36,36 @@ use rand_xoshiro::Xoshiro256PlusPlus;
 
 use anndists::dist::*;
 
+use crate::error::CoresetError;
 use crate::facility::*;
+use crate::pivot::PivotIndexArg;
+use crate::seeding::derive_seed;
+use crate::sensitivity::CoreSet;
+use crate::wkmedian::{InitMethod, Kmedoid, KmedoidOptimization};
+
+// seed of random generator if none is given by Bmor::set_seed
+const BMOR_DEFAULT_SEED: u64 = 1454691;
+// number of iterations of the kmedoid reduction in Bmor::end_data_k
+const BMOR_KMEDOID_NB_ITER: usize = 20;
+// with decay, facilities whose weight falls below are dropped
+const DECAY_MIN_WEIGHT: f64 = 1.0e-2;
+
+/// Exponential decay of weights and costs of facilities in [Bmor], see [Bmor::set_decay()].
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Decay {
+    /// weights and costs are multiplied by the factor (in ]0, 1]) at each new data point
+    PerPoint(f64),
+    /// weights and costs are multiplied by factor^s where s is the number of seconds elapsed since the previous block of data
+    PerSecond(f64),
+}
+
+impl Decay {
+    fn get_factor(&self) -> f64 {
+        match self {
+            Decay::PerPoint(f) | Decay::PerSecond(f) => *f,
+        }
+    }
+}
 
 /// This structure stores the state of Bmor algorithm through iterations.
 /// In particular it stores allocated facilities.
@@ -67,6 +101,7 @@ impl<
         Dist: Distance<T> + Clone + Sync + Send,
     > BmorState<DataId, T, Dist>
 {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         k: usize,
         nbdata: usize,
@@ -75,10 +110,12 @@ impl<
         upper_cost: f64,
         facility_bound: usize,
         distance: Dist,
+        cost_fn: CostFunction,
+        seed: u64,
     ) -> Self {
-        let centers = Facilities::<DataId, T, Dist>::new(alloc_size, distance);
+        let centers = Facilities::<DataId, T, Dist>::new(alloc_size, distance, cost_fn);
         let unif = Uniform::<f64>::new(0., 1.);
-        let rng = Xoshiro256PlusPlus::seed_from_u64(1454691);
+        let rng = Xoshiro256PlusPlus::seed_from_u64(seed);
         let oneplogn = (1 + nbdata.ilog2()) as usize * k;
         let li = 1.0f64;
         //
@@ -147,12 +184,8 @@ impl<
         self.total_cost
     }
 
-    /// get nearest center/facility of a point, its rank and distance to facility
-    #[allow(clippy::type_complexity)]
-    pub fn get_nearest_center(
-        &self,
-        point: &[T],
-    ) -> Option<(&Arc<RwLock<Facility<DataId, T>>>, usize, f32)>
+    /// get nearest center/facility of a point, returns its rank and distance to facility
+    pub fn get_nearest_center(&self, point: &[T]) -> Option<(usize, f32)>
     where
         T: Send + Sync,
         Dist: Sync,
@@ -164,32 +197,26 @@ impl<
             return None;
         }
         // get nearest facilty
-        let (rank, dist) = self.centers.get_nearest_facility(point, false).unwrap();
-        //
-        return Some((self.centers.get_facility(rank).unwrap(), rank, dist));
+        self.centers.get_nearest_facility(point, false).ok()
     } // end of get_nearest_center
 
     /// insert into an already existing facility
     /// return true if all is OK, false if costs or number of facilities got too large
-    fn update(&mut self, rank_id: DataId, point: &[T], weight: f64) -> bool {
+    fn update(&mut self, rank_id: DataId, point: &[T], weight: f64) -> Result<bool, CoresetError> {
         //
         log::trace!("in BmorState::update rank_id: {:?}", rank_id);
         //
-        let dist_to_nearest: f32;
-        let nearest_facility: Arc<RwLock<Facility<DataId, T>>>;
-        {
-            let nearest_facility_res = self.get_nearest_center(point);
-            if nearest_facility_res.is_none() {
-                log::error!("internal error, update did not find nearest facility");
-                std::process::exit(1);
-            }
-            let nearest_center = nearest_facility_res.unwrap();
-            dist_to_nearest = nearest_center.2;
-            nearest_facility = nearest_center.0.clone();
-        }
+        let (nearest_facility, dist_to_nearest) = self.get_nearest_center(point).ok_or_else(|| {
+            log::error!("internal error, update did not find nearest facility");
+            CoresetError::AlgorithmFailure(format!(
+                "BmorState::update did not find nearest facility of {:?}",
+                rank_id
+            ))
+        })?;
+        let cost_to_nearest = self.centers.get_cost_function().eval(dist_to_nearest);
         // take into account f factor
         if self.get_unif_sample()
-            < (weight * dist_to_nearest as f64 * self.oneplogn as f64 / self.li)
+            < (weight * cost_to_nearest as f64 * self.oneplogn as f64 / self.li)
         {
             // we create a new facility. No cost increment
             let mut new_f = Facility::<DataId, T>::new(rank_id, point);
@@ -198,8 +225,8 @@ impl<
             // log::debug!("in BmorState::update  creating new facility around {}, nb_facilities : {}", rank_id, self.centers.len());
         } else {
             // log::debug!("in BmorState::update rank_id: {:?}, inserting in old facility dist : {:.3e}", rank_id, dist_to_nearest);
-            nearest_facility.write().insert(weight, dist_to_nearest);
-            self.total_cost += weight.abs() * dist_to_nearest as f64;
+            self.centers.insert_point(nearest_facility, dist_to_nearest, weight);
+            self.total_cost += weight.abs() * cost_to_nearest as f64;
         }
         // we increments weight monitoring and number of insertions
         self.absolute_weight += weight.abs();
@@ -210,12 +237,20 @@ impl<
                 log::debug!("constraint violation");
                 self.log();
             }
-            false
+            Ok(false)
         } else {
-            true
+            Ok(true)
         }
     } // end of update
 
+    // multiplies weights and costs by factor, facilities becoming negligible are dropped (lazily, see Facilities::decay).
+    // total cost and weight decay as the facilities so the phase cost bound applies to the decayed cost.
+    pub(crate) fn decay(&mut self, factor: f64) {
+        let (dropped_weight, dropped_cost) = self.centers.decay(factor, DECAY_MIN_WEIGHT);
+        self.absolute_weight = (self.absolute_weight * factor - dropped_weight).max(0.);
+        self.total_cost = (self.total_cost * factor - dropped_cost).max(0.);
+    }
+
     // reinitialization. (upper cost rescaling)
     pub(crate) fn reinit(&mut self, beta: f64) {
         self.phase += 1;
@@ -247,6 +282,36 @@ impl<
     }
 } // end of impl block BmorState
 
+// The serialized image of a Bmor instance and its state. Facilities are stored as a list of Facility,
+// the distance is not stored and must be given back at restoration.
+#[derive(Serialize, Deserialize)]
+struct BmorCheckpoint<DataId, T: Send + Sync + Clone> {
+    k: usize,
+    nbdata_expected: usize,
+    beta: f64,
+    gamma: f64,
+    oneplogn: usize,
+    phase: usize,
+    li: f64,
+    phase_cost_upper: f64,
+    facility_bound: usize,
+    cost_fn: CostFunction,
+    // facilities with weights and costs divided by the decay scale
+    facilities: Vec<Facility<DataId, T>>,
+    scale: f64,
+    facilities_weight: f64,
+    facilities_cost: f64,
+    absolute_weight: f64,
+    total_cost: f64,
+    nb_inserted: usize,
+    seed: u64,
+    rng: Xoshiro256PlusPlus,
+    index_arg: Option<PivotIndexArg>,
+    decay: Option<Decay>,
+    // wall clock time of last block (since unix epoch), for decay per second
+    last_block: Option<Duration>,
+} // end of struct BmorCheckpoint
+
 #[cfg_attr(doc, katexit::katexit)]
 /// This structure gathers all parameters defining Bmor algorithm.  
 /// The algorithm do iterations with at each step an acceptable upper bound cost and upper bound on number
@@ -279,6 +344,12 @@ pub struct Bmor<DataId, T: Send + Sync + Clone, Dist: Distance<T>> {
     gamma: f64,
     //
     distance: Dist,
+    // seed of random generator, see set_seed
+    seed: u64,
+    // optional decay of facility weights, see set_decay
+    decay: Option<Decay>,
+    // time of previous block of data, for Decay::PerSecond
+    last_block: Cell<Option<Instant>>,
     // store computation state
     state: RefCell<BmorState<DataId, T, Dist>>,
     //
@@ -295,7 +366,7 @@ where
     ///     [process_weighted_data](Self::process_weighted_data())) the number of expected data can be larger than the length or arguments passed to these methods.
     /// - beta : upper cost multiplicative factor
     /// - gamma : slackness factor for number facilities upper bound.
-    /// - end_step : if true a second step is done to further reduce the number of facilities.
+    /// - cost_fn : the cost function. [CostFunction::KMedian] is the original setting, [CostFunction::KMeans] uses squared distances as in the paper.
     ///         
     pub fn new(
         k_arg: usize,
@@ -303,6 +374,7 @@ where
         beta: f64,
         gamma: f64,
         distance: Dist,
+        cost_fn: CostFunction,
     ) -> Self {
         // We restrict k to be adjusted to nbdata_expected to avoid k too large compared to nb_data !
         let k = if k_arg > (nbdata_expected as f64).sqrt().trunc() as usize {
@@ -324,6 +396,8 @@ where
             upper_cost,
             nb_centers_bound,
             distance.clone(),
+            cost_fn,
+            BMOR_DEFAULT_SEED,
         );
         //
         Bmor {
@@ -332,11 +406,85 @@ where
             beta,
             gamma,
             distance,
+            seed: BMOR_DEFAULT_SEED,
+            decay: None,
+            last_block: Cell::new(None),
             state: RefCell::new(state),
             _t: PhantomData::<T>,
         }
     }
 
+    /// sets the seed of the random generator used to open facilities (default is 1454691) and resets the generator.  
+    /// It must be called before processing data. With a given seed results are reproducible.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.state.get_mut().rng = Xoshiro256PlusPlus::seed_from_u64(seed);
+    }
+
+    /// returns the seed of the random generator
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// sets (or removes with None) a pivot index on facilities to accelerate the search of the nearest facility of each data,
+    /// see [pivot](crate::pivot). The distance must satisfy the triangle inequality.  
+    /// Default is no index, useful when the number of facilities reaches hundreds.
+    pub fn set_facility_index(&mut self, arg: Option<PivotIndexArg>) {
+        self.state.get_mut().centers.set_index(arg);
+    }
+
+    #[cfg_attr(doc, katexit::katexit)]
+    /// sets (or removes with None) an exponential decay of weights and costs of facilities so that old clusters fade and new ones open
+    /// when data drift. It must be called before processing data.  
+    /// The total cost compared to the phase cost bound decays as facility costs, and facilities whose weight falls below 0.01 are dropped,
+    /// freeing room under the facility bound.  
+    /// A decay step costs O(1) : weights share a common scale factor, facilities are renormalized and faded ones dropped
+    /// only when the scale has been halved.
+    /// With [Decay::PerPoint] and factor $\lambda$, the total weight is bounded by $1/(1-\lambda)$, so the facility bound and the facility opening factor
+    /// are computed with $ \min(nbdata, 1/(1-\lambda))$ expected data.  
+    /// With [Decay::PerSecond] decay is applied at each call to [process_data](Self::process_data()) or [process_weighted_data](Self::process_weighted_data()).  
+    /// A decay is not applied when merging other instances nor in the final contraction.
+    pub fn set_decay(&mut self, decay: Option<Decay>) -> Result<(), CoresetError> {
+        if let Some(d) = decay {
+            let factor = d.get_factor();
+            if !(factor > 0. && factor <= 1.) {
+                return Err(CoresetError::InvalidParameter(format!(
+                    "Bmor::set_decay, factor must be in ]0, 1], got {}",
+                    factor
+                )));
+            }
+        }
+        let state = self.state.get_mut();
+        if state.nb_inserted > 0 {
+            return Err(CoresetError::InvalidParameter(
+                "Bmor::set_decay must be called before processing data".to_string(),
+            ));
+        }
+        let nbdata = match decay {
+            Some(Decay::PerPoint(factor)) if factor < 1. => {
+                let nb_effective = (1. / (1. - factor)).ceil().min(usize::MAX as f64) as usize;
+                self.nbdata_expected.min(nb_effective.max(2))
+            }
+            _ => self.nbdata_expected,
+        };
+        let oneplogn = 1 + nbdata.ilog2() as usize;
+        state.oneplogn = oneplogn * self.k;
+        state.facility_bound = ((self.gamma - 1.) * oneplogn as f64 * self.k as f64).trunc() as usize;
+        log::info!(
+            "Bmor::set_decay {:?}, facility bound : {}",
+            decay,
+            state.facility_bound
+        );
+        self.decay = decay;
+        self.last_block.set(None);
+        Ok(())
+    } // end of set_decay
+
+    /// returns the decay of facility weights if any
+    pub fn get_decay(&self) -> Option<Decay> {
+        self.decay
+    }
+
     /// return expected number of facilities (clusters)
     pub fn get_k(&self) -> usize {
         self.k
@@ -352,16 +500,29 @@ where
         self.gamma
     }
 
+    /// get cost function
+    pub fn get_cost_function(&self) -> CostFunction {
+        self.state.borrow().get_facilities().get_cost_function()
+    }
+
     /// treat unweighted data.
     /// **This method can be called many times in case of data streaming, passing data by blocks**.  
     /// It returns the number of facilities created up to this call.
     /// id are data id (anything identifying data point)
-    pub fn process_data(&mut self, data: &[Vec<T>], id: &[DataId]) -> anyhow::Result<usize> {
+    pub fn process_data(&mut self, data: &[Vec<T>], id: &[DataId]) -> Result<usize, CoresetError> {
         //
+        if data.len() != id.len() {
+            return Err(CoresetError::InvalidParameter(format!(
+                "Bmor::process_data got {} data and {} ids",
+                data.len(),
+                id.len()
+            )));
+        }
         let weighted_data: Vec<(f64, &Vec<T>, DataId)> = (0..data.len())
             .map(|i| (1., &data[i], id[i].clone()))
             .collect();
-        self.process_weighted_block(&weighted_data);
+        self.decay_elapsed();
+        self.process_weighted_block(&weighted_data, false)?;
         //
         let state = self.state.borrow();
         state.log();
@@ -377,7 +538,7 @@ where
     /// This method returns the facilities created.
     /// if contraction flag is set to true, a final pass of the bmor algorithm will be used to try to reduce the
     /// number of facilities created by previous call to [process_data](Self::process_data()) or [process_weighted_data](Self::process_weighted_data())
-    pub fn end_data(&self, contraction: bool) -> Facilities<DataId, T, Dist> {
+    pub fn end_data(&self, contraction: bool) -> Result<Facilities<DataId, T, Dist>, CoresetError> {
         let facilities = match contraction {
             false => {
                 let facilities_ret = self.state.borrow().get_facilities().clone();
@@ -387,19 +548,14 @@ where
             true => {
                 log::info!("\n\n bmor doing final bmor pass ...");
                 // note that state_2 is not saved anywhere, but this last step is easy to do by hans as the caller has the facilties.
-                let res = self.bmor_contraction();
-                if res.is_err() {
-                    std::panic!("bmor_contraction failed");
-                }
-                //
-                let state_2 = res.unwrap();
+                let state_2 = self.bmor_contraction()?;
                 state_2.log();
                 //
                 let facilities = state_2.get_facilities();
                 facilities.clone()
             }
         };
-        facilities
+        Ok(facilities)
     } // end of end_data
 
     /// treat data with weights attached.
@@ -409,9 +565,10 @@ where
     pub fn process_weighted_data(
         &self,
         weighted_data: &[(f64, &Vec<T>, DataId)],
-    ) -> anyhow::Result<usize> {
+    ) -> Result<usize, CoresetError> {
         //
-        self.process_weighted_block(weighted_data);
+        self.decay_elapsed();
+        self.process_weighted_block(weighted_data, false)?;
         //
         let state = self.state.borrow();
         //
@@ -425,7 +582,7 @@ where
 
     // We recur (once) to reduce number of facilities. To go from $1 + k * logn$ to $1 + k * log(log(n))$
     // (We tried to reduce with imp algo but not better)
-    pub(crate) fn bmor_contraction(&self) -> anyhow::Result<BmorState<DataId, T, Dist>> {
+    pub(crate) fn bmor_contraction(&self) -> Result<BmorState<DataId, T, Dist>, CoresetError> {
         //
         log::info!("\n bmor recurring");
         // extract weighted data
@@ -458,18 +615,18 @@ where
                 "reducing number of facilities: setting expected nb data : {:?}",
                 nb_expected_data
             );
-            let bmor_algo_2: Bmor<DataId, T, Dist> = Bmor::new(
+            let mut bmor_algo_2: Bmor<DataId, T, Dist> = Bmor::new(
                 self.get_k(),
                 nb_expected_data,
                 self.get_beta(),
                 self.get_gamma(),
                 self.distance.clone(),
+                self.get_cost_function(),
             );
+            bmor_algo_2.set_seed(derive_seed(self.seed, 1));
+            bmor_algo_2.set_facility_index(self.state.borrow().centers.get_index_arg());
             //
-            let res = bmor_algo_2.process_weighted_data(&weighted_data);
-            if res.is_err() {
-                return Err(anyhow!("constraction failed"));
-            }
+            bmor_algo_2.process_weighted_data(&weighted_data)?;
             let state_2 = bmor_algo_2.state.borrow();
             state_2.get_facilities().log(0);
             Ok(state_2.clone())
@@ -484,7 +641,12 @@ where
     // This method is the real working method.
     // It inserts data, update state, and drive recurrence
     // args is a vecotr of triplets (weight, data, data_id)
-    fn process_weighted_block(&self, data: &[(f64, &Vec<T>, DataId)]) {
+    // recycled is true when data are facilities of a previous phase (or of a merged instance), they are not subject to per point decay.
+    fn process_weighted_block(
+        &self,
+        data: &[(f64, &Vec<T>, DataId)],
+        recycled: bool,
+    ) -> Result<(), CoresetError> {
         //
         log::debug!(
             "entering process_weighted_block, phase : {:?}, nb data : {}",
@@ -492,10 +654,17 @@ where
             data.len()
         );
         //
+        let point_decay = match self.decay {
+            Some(Decay::PerPoint(factor)) if !recycled && factor < 1. => Some(factor),
+            _ => None,
+        };
         for d in data {
+            if let Some(factor) = point_decay {
+                self.state.borrow_mut().decay(factor);
+            }
             // TODO: now we use rank as rank_id (sufficicent for ordered ids)
             log::trace!("treating rank_id : {:?}, weight : {:.4e}", d.2, d.0);
-            let add_res = self.add_data(d.2.clone(), d.1, d.0);
+            let add_res = self.add_data(d.2.clone(), d.1, d.0)?;
             if !add_res {
                 // allocate new state
                 log::debug!(
@@ -503,35 +672,45 @@ where
                     self.state.borrow().get_facilities().len()
                 );
                 // recycle facilitites in process adding them
-                let weighted_data: Vec<(f64, Vec<T>, DataId)> = self
-                    .state
-                    .borrow()
-                    .centers
-                    .get_vec()
-                    .iter()
-                    .map(|f| {
-                        (
-                            f.read().get_weight(),
-                            f.read().get_position().clone(),
-                            f.read().get_dataid(),
-                        )
-                    })
-                    .collect();
-                assert!(!weighted_data.is_empty());
+                let weighted_data: Vec<(f64, Vec<T>, DataId)> =
+                    self.state.borrow().centers.into_weighted_data();
+                if weighted_data.is_empty() {
+                    return Err(CoresetError::AlgorithmFailure(
+                        "Bmor::process_weighted_block, no facility to recycle at end of phase".to_string(),
+                    ));
+                }
                 let weighted_ref_data: Vec<(f64, &Vec<T>, DataId)> = weighted_data
                     .iter()
                     .map(|wd| (wd.0, &wd.1, wd.2.clone()))
                     .collect();
-                assert!(!weighted_ref_data.is_empty());
                 self.state.borrow_mut().reinit(self.beta);
-                self.process_weighted_block(&weighted_ref_data);
+                self.process_weighted_block(&weighted_ref_data, true)?;
             }
         }
+        Ok(())
     } // end of process_weighted_block
 
+    // applies decay per second for the time elapsed since previous block
+    fn decay_elapsed(&self) {
+        if let Some(Decay::PerSecond(factor)) = self.decay {
+            let now = Instant::now();
+            if let Some(last) = self.last_block.replace(Some(now)) {
+                let factor = factor.powf(now.duration_since(last).as_secs_f64());
+                if factor < 1. {
+                    self.state.borrow_mut().decay(factor);
+                }
+            }
+        }
+    } // end of decay_elapsed
+
     // This function return true except if we got beyond bound for cost or number of facilities
     // The data added can be a facility extracted during a preceding phase
-    pub(crate) fn add_data(&self, rank_id: DataId, data: &[T], weight: f64) -> bool {
+    pub(crate) fn add_data(
+        &self,
+        rank_id: DataId,
+        data: &[T],
+        weight: f64,
+    ) -> Result<bool, CoresetError> {
         //
         let mut state = self.state.borrow_mut();
         let facilities = state.get_mut_facilities();
@@ -548,7 +727,7 @@ where
             // we update global state here in facility creation case
             state.nb_inserted += 1;
             state.absolute_weight += weight;
-            return true;
+            return Ok(true);
         }
         // we already have a facility we update state
         state.update(rank_id, data, weight)
@@ -557,4 +736,508 @@ where
     pub fn log(&self) {
         self.state.borrow().log();
     }
+
+    #[cfg_attr(doc, katexit::katexit)]
+    /// merges into self the facilities computed by another instance on another part of the data.  
+    /// This way a stream can be split in shards, each shard summarized by its own Bmor (in another thread or process, see [checkpoint](Self::checkpoint())),
+    /// and the summaries combined at the end.  
+    /// Both instances should have been allocated with the same k, beta, gamma and with nbdata_expected set to the **whole** data size.  
+    ///
+    /// The cost upper bound and facility cost factor of self are raised to those of other if other went through more phases,
+    /// then the facilities of other are reinserted as weighted points, as is done when Bmor recycles its facilities at a phase change.  
+    /// Let $C_a$ (resp. $C_b$) be the cost of dispatching shard a (resp. b) to its facilities and $C_{ab}$ the cost of inserting
+    /// facilities of b into a. By the triangle inequality the merged facilities dispatch the union of shards with a cost at most $ C_a + C_b + C_{ab}$,
+    /// and as each term is controlled by its phase bound the merged summary keeps the guarantees of a single stream up to a constant factor.  
+    /// The merged state accounts for $C_b$ in its cost so that phase changes are triggered as in a single stream.
+    ///
+    /// It returns the number of facilities after merge.
+    pub fn merge(&self, other: &Bmor<DataId, T, Dist>) -> Result<usize, CoresetError> {
+        //
+        if self.get_cost_function() != other.get_cost_function() {
+            return Err(CoresetError::Incompatible(
+                "Bmor::merge, instances do not have the same cost function".to_string(),
+            ));
+        }
+        let (weighted_data, other_cost) = {
+            let other_state = other.state.borrow();
+            log::info!(
+                "Bmor::merge, phase : {}, receiving nb facilities : {:?}, phase of other : {}",
+                self.state.borrow().get_phase(),
+                other_state.get_facilities().len(),
+                other_state.get_phase()
+            );
+            (
+                other_state.get_facilities().into_weighted_data(),
+                other_state.get_cost(),
+            )
+        };
+        if weighted_data.is_empty() {
+            return Ok(self.state.borrow().get_facilities().len());
+        }
+        {
+            let other_state = other.state.borrow();
+            let mut state = self.state.borrow_mut();
+            if other_state.li > state.li {
+                state.li = other_state.li;
+                state.phase_cost_upper = other_state.phase_cost_upper;
+                state.phase = other_state.phase;
+            }
+            state.total_cost += other_cost;
+        }
+        let weighted_ref_data: Vec<(f64, &Vec<T>, DataId)> = weighted_data
+            .iter()
+            .map(|wd| (wd.0, &wd.1, wd.2.clone()))
+            .collect();
+        self.process_weighted_block(&weighted_ref_data, true)?;
+        //
+        let state = self.state.borrow();
+        state.log();
+        Ok(state.get_facilities().len())
+    } // end of merge
+
+    /// dumps (with bincode) parameters and the whole state of the algorithm (phase, cost bounds, facilities with their decay scale,
+    /// random generator, time of last block) to writer.  
+    /// The stream can be resumed from the dump with [restore](Self::restore()) and will give the same results as an uninterrupted run.
+    /// The distance is not dumped.
+    pub fn checkpoint<W: Write>(&self, writer: W) -> Result<(), CoresetError>
+    where
+        DataId: Serialize,
+        T: Serialize,
+    {
+        let state = self.state.borrow();
+        let (facilities, scale, facilities_weight, facilities_cost) = state.centers.to_raw_parts();
+        // Instant cannot be saved, we go through wall clock time
+        let last_block = self.last_block.get().and_then(|last| {
+            SystemTime::now()
+                .checked_sub(last.elapsed())?
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .ok()
+        });
+        let checkpoint = BmorCheckpoint {
+            k: self.k,
+            nbdata_expected: self.nbdata_expected,
+            beta: self.beta,
+            gamma: self.gamma,
+            oneplogn: state.oneplogn,
+            phase: state.phase,
+            li: state.li,
+            phase_cost_upper: state.phase_cost_upper,
+            facility_bound: state.facility_bound,
+            cost_fn: state.centers.get_cost_function(),
+            facilities,
+            scale,
+            facilities_weight,
+            facilities_cost,
+            absolute_weight: state.absolute_weight,
+            total_cost: state.total_cost,
+            nb_inserted: state.nb_inserted,
+            seed: self.seed,
+            rng: state.rng.clone(),
+            index_arg: state.centers.get_index_arg(),
+            decay: self.decay,
+            last_block,
+        };
+        bincode::serialize_into(writer, &checkpoint)?;
+        log::info!(
+            "Bmor::checkpoint done, phase : {}, nb facilities : {}, nb inserted : {}",
+            state.phase,
+            state.centers.len(),
+            state.nb_inserted
+        );
+        Ok(())
+    } // end of checkpoint
+
+    /// reloads a Bmor instance from a dump produced by [checkpoint](Self::checkpoint()).
+    /// The distance must be the one used before the checkpoint.  
+    /// The returned instance can go on with [process_data](Self::process_data()) and [end_data](Self::end_data()).
+    pub fn restore<R: Read>(reader: R, distance: Dist) -> Result<Self, CoresetError>
+    where
+        DataId: DeserializeOwned,
+        T: DeserializeOwned,
+    {
+        let checkpoint: BmorCheckpoint<DataId, T> = bincode::deserialize_from(reader)?;
+        //
+        let mut centers = Facilities::<DataId, T, Dist>::new(
+            checkpoint.facility_bound,
+            distance.clone(),
+            checkpoint.cost_fn,
+        );
+        centers.set_index(checkpoint.index_arg);
+        centers.from_raw_parts(
+            checkpoint.facilities,
+            checkpoint.scale,
+            checkpoint.facilities_weight,
+            checkpoint.facilities_cost,
+        );
+        // time elapsed since last block, checkpoint and restore included, is taken into account by decay per second
+        let last_block = checkpoint.last_block.and_then(|last| {
+            let elapsed = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .ok()?
+                .checked_sub(last)?;
+            Instant::now().checked_sub(elapsed)
+        });
+        let state = BmorState {
+            oneplogn: checkpoint.oneplogn,
+            phase: checkpoint.phase,
+            li: checkpoint.li,
+            phase_cost_upper: checkpoint.phase_cost_upper,
+            facility_bound: checkpoint.facility_bound,
+            centers,
+            absolute_weight: checkpoint.absolute_weight,
+            total_cost: checkpoint.total_cost,
+            nb_inserted: checkpoint.nb_inserted,
+            rng: checkpoint.rng,
+            unif: Uniform::<f64>::new(0., 1.),
+        };
+        log::info!(
+            "Bmor::restore done, phase : {}, nb facilities : {}, nb inserted : {}",
+            state.phase,
+            state.centers.len(),
+            state.nb_inserted
+        );
+        //
+        Ok(Bmor {
+            k: checkpoint.k,
+            nbdata_expected: checkpoint.nbdata_expected,
+            beta: checkpoint.beta,
+            gamma: checkpoint.gamma,
+            distance,
+            seed: checkpoint.seed,
+            decay: checkpoint.decay,
+            last_block: Cell::new(last_block),
+            state: RefCell::new(state),
+            _t: PhantomData::<T>,
+        })
+    } // end of restore
+} // end of impl block Bmor
+
+impl<DataId, T: Send + Sync + Clone, Dist> Bmor<DataId, T, Dist>
+where
+    Dist: Distance<T> + Clone + Sync + Send + 'static,
+    DataId: std::fmt::Debug + Clone + Send + Sync + Eq + std::hash::Hash + Default,
+    T: std::fmt::Debug + 'static,
+{
+    /// declares end of streaming data and returns exactly k facilities (or all facilities if there are less than k).  
+    /// The weighted facilities (see [into_weighted_data](Facilities::into_weighted_data())) are clustered with the weighted
+    /// k-medoid solver [Kmedoid], the k medoids become the returned facilities, and the weights of the old facilities are dispatched to them.  
+    /// Costs of returned facilities are the costs of dispatching old facilities with their weights,
+    /// the cost of the data can be computed with a second pass [dispatch_data](crate::facility::Facilities::dispatch_data()).  
+    /// This gives a one pass streaming k-median (or k-means depending on the cost function).
+    pub fn end_data_k(&self, k: usize) -> Result<Facilities<DataId, T, Dist>, CoresetError> {
+        if k == 0 {
+            return Err(CoresetError::InvalidParameter(
+                "Bmor::end_data_k, k must be positive".to_string(),
+            ));
+        }
+        let state = self.state.borrow();
+        let facilities = state.get_facilities();
+        if facilities.len() <= k {
+            log::info!(
+                "Bmor::end_data_k, only {} facilities for k = {}",
+                facilities.len(),
+                k
+            );
+            return Ok(facilities.clone());
+        }
+        let cost_fn = facilities.get_cost_function();
+        let weighted_data = facilities.into_weighted_data();
+        let id_weight_map: HashMap<DataId, f64> = weighted_data
+            .iter()
+            .map(|(w, _, id)| (id.clone(), *w))
+            .collect();
+        if id_weight_map.len() != weighted_data.len() {
+            return Err(CoresetError::DuplicateId(
+                "Bmor::end_data_k, facilities do not have distinct ids".to_string(),
+            ));
+        }
+        let datas_wid: Vec<(DataId, Vec<T>)> = weighted_data
+            .iter()
+            .map(|(_, v, id)| (id.clone(), v.clone()))
+            .collect();
+        let coreset = CoreSet::new(id_weight_map, Some(datas_wid), self.distance.clone(), cost_fn)?;
+        //
+        let mut kmedoids = Kmedoid::new(&coreset, k)?;
+        kmedoids.set_seed(derive_seed(self.seed, 2));
+        kmedoids.set_optimization(KmedoidOptimization::AlternateThenSwap);
+        let (nb_iter, cost) = kmedoids.compute_medians(BMOR_KMEDOID_NB_ITER, InitMethod::MaxCost)?;
+        log::info!(
+            "Bmor::end_data_k, reduced {} facilities to {}, kmedoid nb iter : {}, cost : {:.3e}",
+            weighted_data.len(),
+            k,
+            nb_iter,
+            cost
+        );
+        // medoids are facilities, find their rank
+        let rank_of: HashMap<DataId, usize> = weighted_data
+            .iter()
+            .enumerate()
+            .map(|(rank, (_, _, id))| (id.clone(), rank))
+            .collect();
+        let mut reduced = Facilities::<DataId, T, Dist>::new(k, self.distance.clone(), cost_fn);
+        reduced.set_index(facilities.get_index_arg());
+        for m in 0..k {
+            let id = kmedoids.get_center_id(m)?;
+            let rank = rank_of.get(&id).ok_or_else(|| {
+                CoresetError::AlgorithmFailure(format!(
+                    "Bmor::end_data_k, medoid {:?} is not a facility",
+                    id
+                ))
+            })?;
+            reduced.insert(Facility::new(id, &weighted_data[*rank].1));
+        }
+        let positions: Vec<&Vec<T>> = weighted_data.iter().map(|(_, v, _)| v).collect();
+        reduced.dispatch_block(&positions, |i| weighted_data[i].0)?;
+        reduced.log(0);
+        //
+        Ok(reduced)
+    } // end of end_data_k
 } // end of impl block Bmor
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rand_distr::Normal;
+
+    fn log_init_test() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    // generate data around nbcluster gaussian centers
+    fn generate_clustered_data(nbdata: usize, nbcluster: usize) -> Vec<Vec<f32>> {
+        generate_clustered_data_seed(nbdata, nbcluster, 1454691)
+    }
+
+    fn generate_clustered_data_seed(nbdata: usize, nbcluster: usize, seed: u64) -> Vec<Vec<f32>> {
+        let dim = 10;
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(seed);
+        let unif = Uniform::<f32>::new(-20., 20.);
+        let centers: Vec<Vec<f32>> = (0..nbcluster)
+            .map(|_| (0..dim).map(|_| unif.sample(&mut rng)).collect())
+            .collect();
+        let normal = Normal::<f32>::new(0., 1.).unwrap();
+        (0..nbdata)
+            .map(|i| {
+                let c = &centers[i % nbcluster];
+                c.iter().map(|x| x + normal.sample(&mut rng)).collect()
+            })
+            .collect()
+    } // end of generate_clustered_data_seed
+
+    #[test]
+    fn test_bmor_checkpoint() {
+        log_init_test();
+        //
+        let nbdata = 4000;
+        let data = generate_clustered_data(nbdata, 5);
+        let ids: Vec<usize> = (0..nbdata).collect();
+        let half = nbdata / 2;
+        // plain k-median, and k-means with a pivot index and a decay whose lazy scale is pending at checkpoint
+        let configs = [
+            (CostFunction::KMedian, None, None),
+            (
+                CostFunction::KMeans,
+                Some(Decay::PerPoint(0.999)),
+                Some(PivotIndexArg::new(4, 8)),
+            ),
+        ];
+        for (cost_fn, decay, index_arg) in configs {
+            let make_bmor = || {
+                let mut bmor: Bmor<usize, f32, DistL2> =
+                    Bmor::new(5, nbdata, 2., 2., DistL2, cost_fn);
+                bmor.set_decay(decay).unwrap();
+                bmor.set_facility_index(index_arg);
+                bmor
+            };
+            // uninterrupted run
+            let mut bmor = make_bmor();
+            bmor.process_data(&data[..half], &ids[..half]).unwrap();
+            bmor.process_data(&data[half..], &ids[half..]).unwrap();
+            let facilities = bmor.end_data(false).unwrap();
+            // interrupted run
+            let mut bmor_1 = make_bmor();
+            bmor_1.process_data(&data[..half], &ids[..half]).unwrap();
+            if decay.is_some() {
+                let scale = bmor_1.state.borrow().centers.to_raw_parts().1;
+                log::info!("scale at checkpoint : {:.3e}", scale);
+            }
+            let mut dump = Vec::<u8>::new();
+            bmor_1.checkpoint(&mut dump).unwrap();
+            drop(bmor_1);
+            let mut bmor_2: Bmor<usize, f32, DistL2> = Bmor::restore(dump.as_slice(), DistL2).unwrap();
+            assert_eq!(bmor_2.get_cost_function(), cost_fn);
+            assert_eq!(bmor_2.get_decay(), decay);
+            assert_eq!(bmor_2.state.borrow().centers.get_index_arg(), index_arg);
+            bmor_2.process_data(&data[half..], &ids[half..]).unwrap();
+            let facilities_2 = bmor_2.end_data(false).unwrap();
+            //
+            assert_eq!(facilities.len(), facilities_2.len());
+            for i in 0..facilities.len() {
+                let f = facilities.get_cloned_facility(i).unwrap();
+                let f_2 = facilities_2.get_cloned_facility(i).unwrap();
+                assert_eq!(f.get_dataid(), f_2.get_dataid());
+                assert_eq!(f.get_weight(), f_2.get_weight());
+                assert_eq!(f.get_cost(), f_2.get_cost());
+            }
+            assert_eq!(facilities.get_weight(), facilities_2.get_weight());
+        }
+    } // end of test_bmor_checkpoint
+
+    #[test]
+    fn test_bmor_merge() {
+        log_init_test();
+        //
+        let nbdata = 6000;
+        let nbshard = 3;
+        let data = generate_clustered_data(nbdata, 5);
+        let ids: Vec<usize> = (0..nbdata).collect();
+        let data_ref: Vec<&Vec<f32>> = data.iter().collect();
+        // single stream
+        let mut bmor: Bmor<usize, f32, DistL2> =
+            Bmor::new(5, nbdata, 2., 2., DistL2, CostFunction::KMedian);
+        bmor.process_data(&data, &ids).unwrap();
+        let mut facilities = bmor.end_data(false).unwrap();
+        let single_cost = facilities.dispatch_data(&data_ref, &ids, None).unwrap();
+        // shards, each summarized in its thread
+        let shard_size = nbdata / nbshard;
+        let shards: Vec<Bmor<usize, f32, DistL2>> = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..nbshard)
+                .map(|i| {
+                    let (data, ids) = (&data, &ids);
+                    s.spawn(move || {
+                        let range = i * shard_size..(i + 1) * shard_size;
+                        let mut bmor_i =
+                            Bmor::new(5, nbdata, 2., 2., DistL2, CostFunction::KMedian);
+                        bmor_i
+                            .process_data(&data[range.clone()], &ids[range])
+                            .unwrap();
+                        bmor_i
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        for shard in &shards[1..] {
+            shards[0].merge(shard).unwrap();
+        }
+        let mut merged = shards[0].end_data(false).unwrap();
+        assert!((merged.get_weight() - nbdata as f64).abs() < 1.0e-5);
+        let merged_cost = merged.dispatch_data(&data_ref, &ids, None).unwrap();
+        log::info!(
+            "single stream cost : {:.3e}, merged shards cost : {:.3e}",
+            single_cost,
+            merged_cost
+        );
+        assert!(merged_cost <= 2. * single_cost);
+    } // end of test_bmor_merge
+
+    #[test]
+    fn test_bmor_end_data_k() {
+        log_init_test();
+        //
+        let nbdata = 6000;
+        let data = generate_clustered_data(nbdata, 5);
+        let ids: Vec<usize> = (0..nbdata).collect();
+        let data_ref: Vec<&Vec<f32>> = data.iter().collect();
+        let mut bmor: Bmor<usize, f32, DistL2> =
+            Bmor::new(5, nbdata, 2., 2., DistL2, CostFunction::KMedian);
+        bmor.process_data(&data, &ids).unwrap();
+        assert!(bmor.end_data(false).unwrap().len() > 5);
+        let facilities = bmor.end_data_k(5).unwrap();
+        assert_eq!(facilities.len(), 5);
+        // weights of all data are kept
+        assert!((facilities.get_weight() - nbdata as f64).abs() < 1.0e-5);
+        // reproducible
+        let facilities_2 = bmor.end_data_k(5).unwrap();
+        for i in 0..5 {
+            assert_eq!(facilities.get_center(i), facilities_2.get_center(i));
+        }
+        // one center by cluster: mean distance to center is about sqrt(dim) for gaussian clusters of unit variance
+        let mut facilities = facilities;
+        let cost = facilities.dispatch_data(&data_ref, &ids, None).unwrap();
+        log::info!("end_data_k mean cost : {:.3e}", cost / nbdata as f64);
+        assert!(cost / (nbdata as f64) < 2. * (10f64).sqrt());
+        // asking more centers than facilities returns all facilities
+        let nb_facility = bmor.end_data(false).unwrap().len();
+        assert_eq!(bmor.end_data_k(nb_facility + 1).unwrap().len(), nb_facility);
+        assert!(bmor.end_data_k(0).is_err());
+    } // end of test_bmor_end_data_k
+
+    #[test]
+    fn test_bmor_decay() {
+        log_init_test();
+        //
+        let nbdata = 5000;
+        // the stream drifts from old clusters to new ones
+        let old_data = generate_clustered_data_seed(nbdata, 5, 1454691);
+        let new_data = generate_clustered_data_seed(nbdata, 5, 4537);
+        let mut bmor: Bmor<usize, f32, DistL2> =
+            Bmor::new(5, 2 * nbdata, 2., 2., DistL2, CostFunction::KMedian);
+        assert!(bmor.set_decay(Some(Decay::PerPoint(1.5))).is_err());
+        let factor = 0.999;
+        bmor.set_decay(Some(Decay::PerPoint(factor))).unwrap();
+        assert_eq!(bmor.get_decay(), Some(Decay::PerPoint(factor)));
+        let ids: Vec<usize> = (0..nbdata).collect();
+        bmor.process_data(&old_data, &ids).unwrap();
+        let ids: Vec<usize> = (nbdata..2 * nbdata).collect();
+        bmor.process_data(&new_data, &ids).unwrap();
+        assert!(bmor.set_decay(None).is_err());
+        //
+        let facilities = bmor.end_data(false).unwrap();
+        let total_weight = facilities.get_weight();
+        log::info!(
+            "decay nb facilities : {}, total weight : {:.3e}",
+            facilities.len(),
+            total_weight
+        );
+        assert!(total_weight <= 1. / (1. - factor) + 1.);
+        // almost all weight is on facilities near recent data
+        let near_weight: f64 = facilities
+            .into_weighted_data()
+            .iter()
+            .filter(|(_, position, _)| {
+                new_data[..500]
+                    .iter()
+                    .any(|p| DistL2.eval(p, position) < 3. * (10f32).sqrt())
+            })
+            .map(|(w, _, _)| *w)
+            .sum();
+        log::info!("weight near recent data : {:.3e}", near_weight);
+        assert!(near_weight >= 0.98 * total_weight);
+    } // end of test_bmor_decay
+
+    // a facility at origin and light points at distance 1, 2 or 3 : they are (almost surely) inserted
+    // into the facility which accumulates weight * cost(distance)
+    #[test]
+    fn test_bmor_cost_function() {
+        log_init_test();
+        //
+        let nbdata = 100;
+        let weight = 1.0e-9;
+        let data: Vec<Vec<f32>> = (0..=nbdata)
+            .map(|i| if i == 0 { vec![0., 0.] } else { vec![(1 + i % 3) as f32, 0.] })
+            .collect();
+        let weighted_data: Vec<(f64, &Vec<f32>, usize)> = data
+            .iter()
+            .enumerate()
+            .map(|(i, d)| (if i == 0 { 1. } else { weight }, d, i))
+            .collect();
+        let sum_dist: f64 = data[1..].iter().map(|d| d[0] as f64).sum();
+        let sum_dist2: f64 = data[1..].iter().map(|d| (d[0] * d[0]) as f64).sum();
+        for (cost_fn, expected) in [
+            (CostFunction::KMedian, weight * sum_dist),
+            (CostFunction::KMeans, weight * sum_dist2),
+        ] {
+            let bmor: Bmor<usize, f32, DistL2> = Bmor::new(1, nbdata + 1, 2., 2., DistL2, cost_fn);
+            bmor.process_weighted_data(&weighted_data).unwrap();
+            let facilities = bmor.end_data(false).unwrap();
+            assert_eq!(facilities.len(), 1);
+            let cost = facilities.get_cloned_facility(0).unwrap().get_cost();
+            log::info!("cost function : {:?}, facility cost : {:.6e}, expected : {:.6e}", cost_fn, cost, expected);
+            assert!((cost - expected).abs() <= 1.0e-6 * expected);
+            assert!((facilities.get_cost() - expected).abs() <= 1.0e-6 * expected);
+            assert!((bmor.state.borrow().get_cost() - expected).abs() <= 1.0e-6 * expected);
+        }
+    } // end of test_bmor_cost_function
+} // end of mod tests
@@ -4,14 +4,17 @@
 //! to their nearest coreset clustering center, recomputing global
 //! cost and storing membership
 //!
-//! Clusters are dumped in a csv file name "clustercoreset.csv".  
-//! Each line consists in the DataId of an item, the DataId of its cluster center
+//! Clusters are dumped in a csv file named "clustercoreset-pid.csv" (see [ClusterCoreset::set_dump_path()]) or in any writer
+//! with [ClusterCoreset::dispatch_to_writer()].  
+//! Each line consists in the DataId of an item, the DataId of its cluster center and optionally the distance to it, see [AssignmentFormat].  
+//! Lines are written buffer by buffer in the order of the data iterator.
 //!
 
-use std::collections::HashMap;
 use std::hash::Hash;
+use std::path::{Path, PathBuf};
 
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use rayon::iter::ParallelIterator;
+use rayon::slice::ParallelSlice;
 
 use cpu_time::ProcessTime;
 use num_cpus;
@@ -21,9 +24,15 @@ use std::io::Write;
 
 use anndists::dist::*;
 
-use crate::sensitivity::*;
-// use crate::facility::*;
+use crate::bicriteria::*;
+use crate::bmor::Bmor;
+use crate::clustermodel::ClusterModel;
+use crate::error::CoresetError;
+use crate::facility::CostFunction;
+use crate::kernel::{CenterBlock, POINT_BLOCK};
 use crate::makeiter::*;
+use crate::seeding::derive_seed;
+use crate::sensitivity::*;
 use crate::wkmedian::*;
 
 #[derive(Copy, Clone)]
@@ -43,6 +52,21 @@ impl BmorArg {
             gamma,
         }
     }
+
+    /// returns the number of data expected
+    pub fn get_nb_data_expected(&self) -> usize {
+        self.nb_data_expected
+    }
+
+    /// returns the upper cost multiplicative factor
+    pub fn get_beta(&self) -> f64 {
+        self.beta
+    }
+
+    /// returns the slackness factor for number of facilities
+    pub fn get_gamma(&self) -> f64 {
+        self.gamma
+    }
 }
 
 impl Default for BmorArg {
@@ -57,6 +81,36 @@ impl Default for BmorArg {
 
 //==================================================================
 
+/// Format of lines of cluster assignments written by [ClusterCoreset::dispatch_to_writer()].  
+/// A line consists in the DataId of a data, the DataId of its cluster center and optionally the distance of data to the center,
+/// separated by delimiter. DataId are written with their Debug format.
+#[derive(Copy, Clone, Debug)]
+pub struct AssignmentFormat {
+    delimiter: char,
+    with_distance: bool,
+}
+
+impl AssignmentFormat {
+    pub fn new(delimiter: char, with_distance: bool) -> Self {
+        AssignmentFormat {
+            delimiter,
+            with_distance,
+        }
+    }
+}
+
+impl Default for AssignmentFormat {
+    /// comma separated, without distance
+    fn default() -> Self {
+        AssignmentFormat {
+            delimiter: ',',
+            with_distance: false,
+        }
+    }
+}
+
+//==================================================================
+
 pub struct ClusterCoreset<DataId: std::fmt::Debug + Eq + std::hash::Hash + Clone + Send + Sync, T> {
     //
     nb_cluster: usize,
@@ -64,31 +118,64 @@ pub struct ClusterCoreset<DataId: std::fmt::Debug + Eq + std::hash::Hash + Clone
     fraction: f64,
     //
     bmor_arg: BmorArg,
+    // cost function of clustering
+    cost_fn: CostFunction,
     // total nb data processed
     nb_data: usize,
     /// To store kmedoid result
     kmedoids: Option<Kmedoid<DataId, T>>,
-    /// associate each DataId to its medoid rank (computed by function dispatch)
-    ids_to_cluster: Option<HashMap<DataId, DataId>>,
+    /// file where dispatch writes cluster assignments
+    dump_path: PathBuf,
+    /// seed from which seeds of coreset and kmedoid are derived
+    seed: u64,
 }
 
 impl<DataId, T> ClusterCoreset<DataId, T>
 where
     DataId: Default + Eq + Hash + Send + Sync + Clone + std::fmt::Debug,
-    T: Clone + Send + Sync + std::fmt::Debug,
+    T: Clone + Send + Sync + std::fmt::Debug + 'static,
 {
-    pub fn new(nb_cluster: usize, fraction: f64, bmor_arg: BmorArg) -> Self {
+    /// - nb_cluster : number of clusters asked
+    /// - fraction : fraction of data to keep in coreset
+    /// - bmor_arg : arguments of Bmor
+    /// - cost_fn : [CostFunction::KMedian] or [CostFunction::KMeans] (or any power of distance)
+    pub fn new(nb_cluster: usize, fraction: f64, bmor_arg: BmorArg, cost_fn: CostFunction) -> Self {
         ClusterCoreset {
             nb_cluster,
             fraction,
             bmor_arg,
+            cost_fn,
             nb_data: 0,
             kmedoids: None,
-            ids_to_cluster: None,
+            dump_path: PathBuf::from(format!("clustercoreset-{}.csv", std::process::id())),
+            seed: 1454691,
         }
     }
 
-    /// computes coreset and kmedoid clustering.  
+    /// sets the seed of the whole computation (default is 1454691).
+    /// The seeds of the coreset (and of its bicriteria approximation) and of kmedoid are derived from it,
+    /// so with a given seed the clustering is reproducible, whatever the number of threads.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    /// returns the seed of the computation
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// sets the file where [dispatch](Self::dispatch()) writes cluster assignments.
+    /// Default is *clustercoreset-pid.csv* where pid is the pid of the process.
+    pub fn set_dump_path<P: AsRef<Path>>(&mut self, path: P) {
+        self.dump_path = path.as_ref().to_path_buf();
+    }
+
+    /// returns the file where [dispatch](Self::dispatch()) writes cluster assignments.
+    pub fn get_dump_path(&self) -> &Path {
+        &self.dump_path
+    }
+
+    /// computes coreset and kmedoid clustering, using [Bmor] as bicriteria approximation.  
     /// - distance : the metric to use
     /// - nb_iter : the maximal number of iterations in kmedoid.  
     ///    
@@ -101,32 +188,79 @@ where
         distance: Dist,
         nb_iter: usize,
         iter_producer: &IterProducer,
-    ) where
-        Dist: Distance<T> + Send + Sync + Clone,
+    ) -> Result<(), CoresetError>
+    where
+        Dist: Distance<T> + Send + Sync + Clone + 'static,
         IterProducer: MakeIter<Item = (DataId, Vec<T>)>,
     {
-        //
-        let cpu_start = ProcessTime::now();
-        let sys_now = SystemTime::now();
-        //
-        let mut coreset1 = Coreset1::<DataId, T, Dist>::new(
+        self.compute_weighted(distance, nb_iter, &UnitWeight::new(iter_producer))
+    } // end of compute
+
+    /// same as [compute](Self::compute()) but for weighted data given as items (DataId, weight, data).
+    pub fn compute_weighted<Dist, IterProducer>(
+        &mut self,
+        distance: Dist,
+        nb_iter: usize,
+        iter_producer: &IterProducer,
+    ) -> Result<(), CoresetError>
+    where
+        Dist: Distance<T> + Send + Sync + Clone + 'static,
+        IterProducer: MakeIter<Item = (DataId, f64, Vec<T>)>,
+    {
+        let bmor = Bmor::<DataId, T, Dist>::new(
             self.nb_cluster,
             self.bmor_arg.nb_data_expected,
             self.bmor_arg.beta,
             self.bmor_arg.gamma,
-            distance.clone(),
+            distance,
+            self.cost_fn,
         );
+        self.compute_weighted_with(bmor, nb_iter, iter_producer)
+    } // end of compute_weighted
+
+    /// computes coreset and kmedoid clustering as [compute](Self::compute()) but with any bicriteria approximation.  
+    /// - approx : the approximation used by coreset construction, see [BicriteriaApprox].  
+    /// - nb_iter : the maximal number of iterations in kmedoid.  
+    pub fn compute_with<Dist, Approx, IterProducer>(
+        &mut self,
+        approx: Approx,
+        nb_iter: usize,
+        iter_producer: &IterProducer,
+    ) -> Result<(), CoresetError>
+    where
+        Dist: Distance<T> + Send + Sync + Clone + 'static,
+        Approx: BicriteriaApprox<DataId, T, Dist>,
+        IterProducer: MakeIter<Item = (DataId, Vec<T>)>,
+    {
+        self.compute_weighted_with(approx, nb_iter, &UnitWeight::new(iter_producer))
+    } // end of compute_with
+
+    /// same as [compute_with](Self::compute_with()) but for weighted data given as items (DataId, weight, data).
+    pub fn compute_weighted_with<Dist, Approx, IterProducer>(
+        &mut self,
+        approx: Approx,
+        nb_iter: usize,
+        iter_producer: &IterProducer,
+    ) -> Result<(), CoresetError>
+    where
+        Dist: Distance<T> + Send + Sync + Clone + 'static,
+        Approx: BicriteriaApprox<DataId, T, Dist>,
+        IterProducer: MakeIter<Item = (DataId, f64, Vec<T>)>,
+    {
         //
-        let result = coreset1.make_coreset(iter_producer, self.fraction);
+        let cpu_start = ProcessTime::now();
+        let sys_now = SystemTime::now();
+        //
+        let mut coreset1 = Coreset1::with_approx(approx, self.cost_fn);
+        coreset1.set_seed(derive_seed(self.seed, 1));
+        //
+        let result = coreset1.make_weighted_coreset(iter_producer, self.fraction);
         log::info!(
             "make_coreset done sys time {}, cpu time {}",
             sys_now.elapsed().unwrap().as_millis(),
             cpu_start.elapsed().as_millis()
         );
-        if result.is_err() {
-            log::error!("construction of coreset1 failed");
-        }
-        let coreset = result.unwrap();
+        let coreset = result.inspect_err(|_| log::error!("construction of coreset1 failed"))?;
         log::info!("coreset1 nb different points : {}", coreset.get_nb_points());
         //
         log::info!(
@@ -134,8 +268,9 @@ where
             std::any::type_name::<Dist>()
         );
         let nb_cluster = self.nb_cluster;
-        let mut kmedoids = Kmedoid::new(&coreset, nb_cluster);
-        let (nb_iter, cost) = kmedoids.compute_medians(nb_iter);
+        let mut kmedoids = Kmedoid::new(&coreset, nb_cluster)?;
+        kmedoids.set_seed(derive_seed(self.seed, 2));
+        let (nb_iter, cost) = kmedoids.compute_medians(nb_iter, InitMethod::MaxCost)?;
         // TODO: we have coreset and kmedoids we must store center (Vec<T>) of each medoid!
         self.nb_data = coreset1.get_nb_data();
         //
@@ -151,17 +286,86 @@ where
         );
         //
         self.kmedoids = Some(kmedoids);
-    } // end of compute
+        Ok(())
+    } // end of compute_weighted_with
 
     //
 
     /// Once you have Kmedoid, you can compute the clustering cost for the whole data, not just the coreset.
     /// This function can also fill in  [Kmedoid] structure the data vector associated to each center, see [Kmedoid::get_cluster_center]
-    pub fn dispatch<Dist, IterProducer>(&mut self, distance: &Dist, iter_producer: &IterProducer)
+    /// Cluster assignments are written in csv file given by [get_dump_path](Self::get_dump_path()) with [AssignmentFormat::default()].  
+    /// Returns the number of data dispatched.
+    pub fn dispatch<Dist, IterProducer>(
+        &mut self,
+        distance: &Dist,
+        iter_producer: &IterProducer,
+    ) -> Result<usize, CoresetError>
+    where
+        T: Send + Sync + Clone,
+        Dist: Distance<T> + Send + Sync + Clone + 'static,
+        IterProducer: MakeIter<Item = (DataId, Vec<T>)>,
+    {
+        self.dispatch_weighted(distance, &UnitWeight::new(iter_producer))
+    } // end of dispatch
+
+    /// same as [dispatch](Self::dispatch()) but for weighted data, the cost of each data is multiplied by its weight.
+    pub fn dispatch_weighted<Dist, IterProducer>(
+        &mut self,
+        distance: &Dist,
+        iter_producer: &IterProducer,
+    ) -> Result<usize, CoresetError>
+    where
+        T: Send + Sync + Clone,
+        Dist: Distance<T> + Send + Sync + Clone + 'static,
+        IterProducer: MakeIter<Item = (DataId, f64, Vec<T>)>,
+    {
+        let file = std::fs::File::create(&self.dump_path)?;
+        let bufw = std::io::BufWriter::new(file);
+        let nb_record = self.dispatch_weighted_to_writer(
+            distance,
+            iter_producer,
+            bufw,
+            AssignmentFormat::default(),
+        )?;
+        log::info!(
+            "clustercoreset, dumping cluster info in file {:?} , nb_record : {:?} ",
+            self.dump_path,
+            nb_record
+        );
+        Ok(nb_record)
+    } // end of dispatch_weighted
+
+    /// same as [dispatch](Self::dispatch()) but cluster assignments are written in writer with format.  
+    /// Assignments are written buffer by buffer so memory does not depend on data size, and in the order of the data iterator.
+    pub fn dispatch_to_writer<Dist, IterProducer, W>(
+        &mut self,
+        distance: &Dist,
+        iter_producer: &IterProducer,
+        writer: W,
+        format: AssignmentFormat,
+    ) -> Result<usize, CoresetError>
     where
         T: Send + Sync + Clone,
-        Dist: Distance<T> + Send + Sync + Clone,
+        Dist: Distance<T> + Send + Sync + Clone + 'static,
         IterProducer: MakeIter<Item = (DataId, Vec<T>)>,
+        W: Write,
+    {
+        self.dispatch_weighted_to_writer(distance, &UnitWeight::new(iter_producer), writer, format)
+    } // end of dispatch_to_writer
+
+    /// same as [dispatch_to_writer](Self::dispatch_to_writer()) for weighted data.
+    pub fn dispatch_weighted_to_writer<Dist, IterProducer, W>(
+        &mut self,
+        distance: &Dist,
+        iter_producer: &IterProducer,
+        mut writer: W,
+        format: AssignmentFormat,
+    ) -> Result<usize, CoresetError>
+    where
+        T: Send + Sync + Clone,
+        Dist: Distance<T> + Send + Sync + Clone + 'static,
+        IterProducer: MakeIter<Item = (DataId, f64, Vec<T>)>,
+        W: Write,
     {
         //
         let cpu_start = ProcessTime::now();
@@ -172,38 +376,34 @@ where
         let mut data_iter = iter_producer.makeiter();
         let nb_cpus = num_cpus::get();
         let buffer_size = 5000 * nb_cpus;
-        let mut map_to_medoid = HashMap::<DataId, DataId>::with_capacity(self.nb_data);
-        // We must retrive datas corresponding to medoid centers
-        self.get_kmedoids().retrieve_cluster_centers(iter_producer);
-        let centers = self.kmedoids.as_ref().unwrap().get_centers().unwrap();
-        if centers.is_empty() {
-            log::error!("ClusterCore::dispatch, kmedoids centers have not yet been computed");
-            std::process::exit(1);
-        }
-        //
-        // This function returns for each data (id,data) a triplet (id, rank of nearest center found and distance to its cluster center)
+        self.kmedoids
+            .as_mut()
+            .ok_or_else(|| {
+                log::error!("ClusterCore::dispatch, kmedoids centers have not yet been computed");
+                CoresetError::NotComputed(
+                    "ClusterCoreset::dispatch, compute must be called before".to_string(),
+                )
+            })?
+            // We must retrive datas corresponding to medoid centers
+            .retrieve_cluster_centers(&WithoutWeight::new(iter_producer))?;
+        let kmedoids = self.kmedoids.as_ref().unwrap();
+        let centers = kmedoids.get_centers().ok_or_else(|| {
+            CoresetError::MissingData(
+                "ClusterCoreset::dispatch, no center data vectors".to_string(),
+            )
+        })?;
+        let center_ids: Vec<DataId> = kmedoids
+            .get_clusters()
+            .iter()
+            .map(|m| m.get_center_id())
+            .collect();
+        // centers are prepared once for batched search of nearest center
+        let kernel = CenterBlock::<T, Dist>::new(centers.clone());
         //
-        let dispatch_i = |(id, data): (DataId, &Vec<T>)| -> (DataId, usize, f32) {
-            // get nearest medoid. We can retrieve Vec<T> for each medoid from coreset , so we must get access to it
-            assert!(!data.is_empty());
-            let dists: Vec<f32> = centers.iter().map(|c| distance.eval(data, c)).collect();
-            let mut dmin = f32::MAX;
-            let mut imin = usize::MAX;
-            for (i, d) in dists.iter().enumerate() {
-                if *d < dmin {
-                    dmin = *d;
-                    imin = i;
-                }
-            }
-            if imin >= dists.len() {
-                log::error!("\n dispatch failed for id {:?}, FATAL EXITING", id);
-                std::process::exit(1);
-            }
-            //
-            (id, imin, dmin)
-        };
+        let cost_fn = self.cost_fn;
         let mut dispatching_cost: f64 = 0.;
-        let mut nb_total_data = 0usize;
+        let mut total_weight: f64 = 0.;
+        let mut nb_record = 0usize;
         //
         loop {
             let buffres = self.get_buffer_data(buffer_size, &mut data_iter);
@@ -211,92 +411,71 @@ where
                 break;
             }
             let ids_datas = buffres.unwrap();
-            nb_total_data += ids_datas.len();
-            // dispatch buffer
-            let res_dispatch: Vec<(DataId, usize, f32)> = ids_datas
-                .into_par_iter()
-                .map(|(i, d)| dispatch_i((i, &d)))
+            // dispatch buffer, each block of data is scored against all centers at once
+            let res_dispatch: Vec<Vec<(usize, f32)>> = ids_datas
+                .par_chunks(POINT_BLOCK)
+                .map(|chunk| {
+                    let datas: Vec<&Vec<T>> = chunk.iter().map(|(_, _, d)| d).collect();
+                    kernel.nearest_block(&datas, distance)
+                })
                 .collect();
-            for (id, cluster_rank, d) in res_dispatch {
-                let c_id_res = self.kmedoids.as_ref().unwrap().get_center_id(cluster_rank);
-                if c_id_res.is_err() {
-                    log::error!("cannot get center of cluster n° : {}", cluster_rank);
-                    std::process::exit(1);
+            let nearest = res_dispatch.into_iter().flatten();
+            for ((id, w, _), (cluster_rank, d)) in ids_datas.into_iter().zip(nearest) {
+                if cluster_rank >= centers.len() {
+                    log::error!("\n dispatch failed for id {:?}", id);
+                    return Err(CoresetError::AlgorithmFailure(format!(
+                        "ClusterCoreset::dispatch found no center for id {:?}",
+                        id
+                    )));
+                }
+                if format.with_distance {
+                    writeln!(
+                        writer,
+                        "{:?}{}{:?}{}{}",
+                        id, format.delimiter, center_ids[cluster_rank], format.delimiter, d
+                    )?;
+                } else {
+                    writeln!(
+                        writer,
+                        "{:?}{}{:?}",
+                        id, format.delimiter, center_ids[cluster_rank]
+                    )?;
                 }
-                let c_id = c_id_res.unwrap();
-                map_to_medoid.insert(id, c_id);
-                dispatching_cost += d as f64;
+                nb_record += 1;
+                dispatching_cost += w * cost_fn.eval(d) as f64;
+                total_weight += w;
             }
         }
+        writer.flush()?;
         println!(
             "\n end of data dispatching dispatching all data to their cluster, global cost : {:.3e}, cost by data : {:.3e}",
             dispatching_cost,
-            dispatching_cost/ nb_total_data as f64
+            dispatching_cost/ total_weight
         );
         //
-        // dump clusters DataId info
-        //
-        self.ids_to_cluster = Some(map_to_medoid);
-        let _ = self.dump_clusters();
-        //
         log::info!(
             "\n  ClusterCoreset::dispatch sys time(ms) {:?} cpu time(ms) {:?}",
             sys_now.elapsed().unwrap().as_millis(),
             cpu_start.elapsed().as_millis()
         );
-    } // end of dispatch
-
-    //
-
-    /// Dumps in file for each dataId, the DataId of corresponding cluster center. If Ok returns number of record dumped.  
-    /// The dump is a csv file whose name is *clustercoreset-pid.csv* where pid is the pid of the process.
-    /// Each line consists in 2 DataId, the first one identifies a data point and the second the DataId of the center of its corresponding cluster.  
-    /// This function requires [dispatch][Self::dispatch()] to have been called previously
-    pub fn dump_clusters(&self) -> anyhow::Result<usize> {
-        //
-        let mut name = String::from("clustercoreset");
-        name.push_str(".csv");
-        let file = std::fs::File::create(&name)?;
-        let mut bufw = std::io::BufWriter::new(file);
-        let mut nb_record = 0;
-        //
-        if self.ids_to_cluster.is_none() {
-            log::error!(
-                "ClusterCorest::dump_clusters: The method dispatch should have been alled before"
-            );
-            return Err(anyhow::anyhow!(
-                "ClusterCorest::dump_clusters: The method dispatch should have been alled before"
-            ));
-        }
-        for (d, c) in self.ids_to_cluster.as_ref().unwrap() {
-            writeln!(bufw, "{:?},{:?}\n", d, c).unwrap();
-            nb_record += 1;
-        }
-        bufw.flush().unwrap();
-        log::info!(
-            "clustercorest, dumping cluster info in file {:?} , nb_record : {:?} ",
-            name,
-            nb_record
-        );
-        //
         Ok(nb_record)
-    }
+    } // end of dispatch_weighted_to_writer
 
     /// use iterator to return a block of data
     fn get_buffer_data(
         &self,
         buffer_size: usize,
-        data_iter: &mut impl Iterator<Item = (DataId, Vec<T>)>,
-    ) -> Result<Vec<(DataId, Vec<T>)>, u32> {
+        data_iter: &mut impl Iterator<Item = (DataId, f64, Vec<T>)>,
+    ) -> Result<Vec<(DataId, f64, Vec<T>)>, u32> {
         //
-        let mut ids_datas = Vec::<(DataId, Vec<T>)>::with_capacity(buffer_size);
+        let mut ids_datas = Vec::<(DataId, f64, Vec<T>)>::with_capacity(buffer_size);
         //
         loop {
             let data_opt = data_iter.next();
             match data_opt {
-                Some((id, data)) => {
+                Some((id, weight, data)) => {
                     // insert
-                    ids_datas.push((id, data));
+                    ids_datas.push((id, weight, data));
                     if ids_datas.len() == buffer_size {
                         break;
                     }
@@ -314,7 +493,186 @@ where
         }
     } // end of get_buffer_data
 
-    fn get_kmedoids(&mut self) -> &mut Kmedoid<DataId, T> {
-        self.kmedoids.as_mut().unwrap()
-    }
+    /// returns an owned model of the clustering, to assign new data to clusters, see [ClusterModel].  
+    /// This function requires [dispatch][Self::dispatch()] to have been called previously to get data vectors of centers.
+    pub fn get_model<Dist>(
+        &self,
+        distance: Dist,
+    ) -> Result<ClusterModel<DataId, T, Dist>, CoresetError>
+    where
+        Dist: Distance<T> + Send + Sync + 'static,
+    {
+        let kmedoids = self.kmedoids.as_ref().ok_or_else(|| {
+            CoresetError::NotComputed(
+                "ClusterCoreset::get_model, compute must be called before".to_string(),
+            )
+        })?;
+        let centers = kmedoids.get_centers().ok_or_else(|| {
+            CoresetError::NotComputed(
+                "ClusterCoreset::get_model, dispatch must be called before".to_string(),
+            )
+        })?;
+        let center_ids: Vec<DataId> = kmedoids
+            .get_clusters()
+            .iter()
+            .map(|m| m.get_center_id())
+            .collect();
+        ClusterModel::new(center_ids, centers.clone(), distance, self.cost_fn)
+    } // end of get_model
 } // end of impl ClusterCorese
+
+//========================================================================================
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rand::distributions::{Distribution, Uniform};
+    use rand_xoshiro::rand_core::SeedableRng;
+    use rand_xoshiro::Xoshiro256PlusPlus;
+
+    fn log_init_test() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    struct DataForIter {
+        datas: Vec<Vec<f32>>,
+    }
+
+    impl MakeIter for DataForIter {
+        type Item = (usize, Vec<f32>);
+        //
+        fn makeiter(&self) -> impl Iterator<Item = Self::Item> {
+            self.datas.iter().cloned().enumerate()
+        }
+    }
+
+    #[test]
+    fn test_dispatch_to_writer() {
+        log_init_test();
+        //
+        let nbdata = 5000;
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(1457);
+        let unif = Uniform::<f32>::new(-20., 20.);
+        let centers: Vec<Vec<f32>> = (0..4)
+            .map(|_| (0..5).map(|_| unif.sample(&mut rng)).collect())
+            .collect();
+        let normal = rand_distr::Normal::<f32>::new(0., 1.).unwrap();
+        let datas: Vec<Vec<f32>> = (0..nbdata)
+            .map(|i| {
+                centers[i % 4]
+                    .iter()
+                    .map(|x| x + normal.sample(&mut rng))
+                    .collect()
+            })
+            .collect();
+        let iter_producer = DataForIter { datas };
+        //
+        let bmor_arg = BmorArg::new(nbdata, 2., 2.);
+        let mut clustercoreset =
+            ClusterCoreset::<usize, f32>::new(4, 0.1, bmor_arg, CostFunction::KMedian);
+        clustercoreset.compute(DistL2, 10, &iter_producer).unwrap();
+        let mut buffer = Vec::<u8>::new();
+        let nb_record = clustercoreset
+            .dispatch_to_writer(
+                &DistL2,
+                &iter_producer,
+                &mut buffer,
+                AssignmentFormat::new('\t', true),
+            )
+            .unwrap();
+        assert_eq!(nb_record, nbdata);
+        // lines are in data order, with id, center id and distance
+        let text = String::from_utf8(buffer).unwrap();
+        let model = clustercoreset.get_model(DistL2).unwrap();
+        for (i, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            assert_eq!(fields.len(), 3);
+            assert_eq!(fields[0].parse::<usize>().unwrap(), i);
+            let (rank, dist) = model.predict(&iter_producer.datas[i]);
+            assert_eq!(
+                fields[1].parse::<usize>().unwrap(),
+                *model.get_center_id(rank).unwrap()
+            );
+            assert_eq!(fields[2].parse::<f32>().unwrap(), dist);
+        }
+        assert_eq!(text.lines().count(), nbdata);
+    } // end of test_dispatch_to_writer
+
+    // results of Coreset1, ClusterCoreset and Kmedoid restarts run with the same seed in a pool of nb_threads threads
+    #[allow(clippy::type_complexity)]
+    fn run_in_pool(
+        nb_threads: usize,
+        iter_producer: &DataForIter,
+    ) -> (Vec<(usize, f64)>, Vec<(usize, f32)>, (usize, Vec<(usize, f32)>, Vec<(usize, f32)>)) {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(nb_threads)
+            .build()
+            .unwrap();
+        pool.install(|| {
+            let nbdata = iter_producer.datas.len();
+            // coreset
+            let mut coreset1 = Coreset1::new(4, nbdata, 2., 2., DistL2, CostFunction::KMedian);
+            coreset1.set_seed(7451);
+            let coreset = coreset1.make_coreset(iter_producer, 0.1).unwrap();
+            let mut coreset_items: Vec<(usize, f64)> = coreset.get_items().map(|(id, w)| (*id, *w)).collect();
+            coreset_items.sort_unstable_by_key(|(id, _)| *id);
+            // clustering
+            let mut clustercoreset = ClusterCoreset::<usize, f32>::new(
+                4,
+                0.1,
+                BmorArg::new(nbdata, 2., 2.),
+                CostFunction::KMedian,
+            );
+            clustercoreset.set_seed(7451);
+            clustercoreset.compute(DistL2, 10, iter_producer).unwrap();
+            let medoids: Vec<(usize, f32)> = clustercoreset
+                .kmedoids
+                .as_ref()
+                .unwrap()
+                .get_clusters()
+                .iter()
+                .map(|m| (m.get_center_id(), m.get_cost()))
+                .collect();
+            // kmedoid restarts
+            let mut kmedoids = Kmedoid::new(&coreset, 4).unwrap();
+            let (best, costs) = kmedoids.compute_medians_restarts(4, 10, 7451).unwrap();
+            let restart_medoids: Vec<(usize, f32)> = kmedoids
+                .get_clusters()
+                .iter()
+                .map(|m| (m.get_center_id(), m.get_cost()))
+                .collect();
+            (coreset_items, medoids, (best, costs, restart_medoids))
+        })
+    } // end of run_in_pool
+
+    #[test]
+    fn test_thread_count_reproducibility() {
+        log_init_test();
+        //
+        let nbdata = 20000;
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(3517);
+        let unif = Uniform::<f32>::new(-20., 20.);
+        let centers: Vec<Vec<f32>> = (0..4)
+            .map(|_| (0..5).map(|_| unif.sample(&mut rng)).collect())
+            .collect();
+        let normal = rand_distr::Normal::<f32>::new(0., 1.).unwrap();
+        let datas: Vec<Vec<f32>> = (0..nbdata)
+            .map(|i| {
+                centers[i % 4]
+                    .iter()
+                    .map(|x| x + normal.sample(&mut rng))
+                    .collect()
+            })
+            .collect();
+        let iter_producer = DataForIter { datas };
+        //
+        let (coreset_1, medoids_1, restarts_1) = run_in_pool(1, &iter_producer);
+        let (coreset_4, medoids_4, restarts_4) = run_in_pool(4, &iter_producer);
+        // same ids and weights, same medoids and costs
+        assert!(!coreset_1.is_empty());
+        assert_eq!(coreset_1, coreset_4);
+        assert_eq!(medoids_1, medoids_4);
+        assert_eq!(restarts_1, restarts_4);
+    } // end of test_thread_count_reproducibility
+} // end of mod tests
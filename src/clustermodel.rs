@@ -0,0 +1,240 @@
+//! A trained clustering model, extracted from [ClusterCoreset](crate::clustercore::ClusterCoreset) by
+//! [get_model()](crate::clustercore::ClusterCoreset::get_model()).
+//!
+//! The model owns the DataId and data vectors of cluster centers, so new data can be assigned to clusters
+//! without recomputing anything. It can be saved and reloaded.
+
+use std::path::Path;
+
+use rayon::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use anndists::dist::*;
+
+use crate::error::CoresetError;
+use crate::facility::CostFunction;
+use crate::kernel::{CenterBlock, POINT_BLOCK};
+use crate::sensitivity::DumpFormat;
+
+// what is serialized by ClusterModel::save. The distance is identified by its type name
+#[derive(Serialize, Deserialize)]
+struct ClusterModelDump<I, V> {
+    distance_name: String,
+    cost_fn: CostFunction,
+    center_ids: Vec<I>,
+    centers: Vec<V>,
+}
+
+/// A clustering model: cluster centers and the distance used to compute them.
+pub struct ClusterModel<DataId, T, Dist> {
+    /// DataId of centers, in cluster rank order
+    center_ids: Vec<DataId>,
+    /// data vectors of centers, in cluster rank order, prepared for batched search
+    centers: CenterBlock<T, Dist>,
+    //
+    distance: Dist,
+    /// cost function of the clustering
+    cost_fn: CostFunction,
+}
+
+impl<DataId, T, Dist> ClusterModel<DataId, T, Dist>
+where
+    DataId: Clone + Send + Sync,
+    T: Clone + Send + Sync + 'static,
+    Dist: Distance<T> + Send + Sync + 'static,
+{
+    /// center_ids and centers must have the same length, center of rank i is cluster i.
+    pub fn new(
+        center_ids: Vec<DataId>,
+        centers: Vec<Vec<T>>,
+        distance: Dist,
+        cost_fn: CostFunction,
+    ) -> Result<Self, CoresetError> {
+        if center_ids.len() != centers.len() || centers.is_empty() {
+            return Err(CoresetError::InvalidParameter(format!(
+                "ClusterModel::new, got {} center ids and {} centers",
+                center_ids.len(),
+                centers.len()
+            )));
+        }
+        Ok(ClusterModel {
+            center_ids,
+            centers: CenterBlock::new(centers),
+            distance,
+            cost_fn,
+        })
+    }
+
+    /// returns the number of clusters
+    pub fn get_nb_cluster(&self) -> usize {
+        self.centers.len()
+    }
+
+    /// returns DataId of center of cluster of rank
+    pub fn get_center_id(&self, rank: usize) -> Option<&DataId> {
+        self.center_ids.get(rank)
+    }
+
+    /// returns data vector of center of cluster of rank
+    pub fn get_center(&self, rank: usize) -> Option<&Vec<T>> {
+        self.centers.get_center(rank)
+    }
+
+    /// returns the distance
+    pub fn get_distance(&self) -> &Dist {
+        &self.distance
+    }
+
+    /// returns the cost function of the clustering
+    pub fn get_cost_function(&self) -> CostFunction {
+        self.cost_fn
+    }
+
+    /// returns rank of cluster whose center is nearest to data and distance to this center.
+    /// In case of equality the cluster of lower rank is returned.
+    /// The search uses the batched kernels of module [kernel](crate::kernel).
+    pub fn predict(&self, data: &[T]) -> (usize, f32) {
+        self.centers.nearest(data, &self.distance)
+    }
+
+    /// same as [predict](Self::predict()) for a batch of data, computed in parallel by blocks. Results are in data order.
+    pub fn predict_batch(&self, datas: &[Vec<T>]) -> Vec<(usize, f32)> {
+        let nearest: Vec<Vec<(usize, f32)>> = datas
+            .par_chunks(POINT_BLOCK)
+            .map(|chunk| self.centers.nearest_block(chunk, &self.distance))
+            .collect();
+        nearest.into_iter().flatten().collect()
+    }
+
+    /// saves model in file path. Formats are [DumpFormat::Bincode] or [DumpFormat::Json].
+    /// The distance is not serialized, just its type name which is checked at reload.
+    pub fn save<P: AsRef<Path>>(&self, path: P, format: DumpFormat) -> Result<(), CoresetError>
+    where
+        DataId: Serialize,
+        T: Serialize,
+    {
+        let path = path.as_ref();
+        if DumpFormat::from_path(path) != Some(format) {
+            log::warn!(
+                "ClusterModel::save extension of {:?} does not match format {:?}, load will fail",
+                path,
+                format
+            );
+        }
+        let dump = ClusterModelDump {
+            distance_name: std::any::type_name::<Dist>().to_string(),
+            cost_fn: self.cost_fn,
+            center_ids: self.center_ids.iter().collect(),
+            centers: (0..self.centers.len())
+                .filter_map(|rank| self.centers.get_center(rank))
+                .collect(),
+        };
+        let file = std::fs::File::create(path)?;
+        let bufw = std::io::BufWriter::new(file);
+        match format {
+            DumpFormat::Bincode => bincode::serialize_into(bufw, &dump)?,
+            DumpFormat::Json => serde_json::to_writer(bufw, &dump)?,
+            DumpFormat::Csv => {
+                return Err(CoresetError::Format(
+                    "ClusterModel::save, csv format is not supported".to_string(),
+                ));
+            }
+        }
+        log::info!(
+            "cluster model saved in file : {:?}, format : {:?}, nb cluster {}",
+            path,
+            format,
+            self.get_nb_cluster()
+        );
+        Ok(())
+    } // end of save
+
+    /// reloads a model saved by [save](Self::save()), the format is deduced from file extension.
+    /// distance must be of the type used at save.
+    pub fn load<P: AsRef<Path>>(path: P, distance: Dist) -> Result<Self, CoresetError>
+    where
+        DataId: DeserializeOwned,
+        T: DeserializeOwned,
+    {
+        let path = path.as_ref();
+        let format = DumpFormat::from_path(path).ok_or_else(|| {
+            CoresetError::Format(format!(
+                "ClusterModel::load cannot deduce format from extension of {:?}",
+                path
+            ))
+        })?;
+        let file = std::fs::File::open(path)?;
+        let bufr = std::io::BufReader::new(file);
+        let dump: ClusterModelDump<DataId, Vec<T>> = match format {
+            DumpFormat::Bincode => bincode::deserialize_from(bufr)?,
+            DumpFormat::Json => serde_json::from_reader(bufr)?,
+            DumpFormat::Csv => {
+                return Err(CoresetError::Format(
+                    "ClusterModel::load, csv format is not supported".to_string(),
+                ));
+            }
+        };
+        if dump.distance_name != std::any::type_name::<Dist>() {
+            return Err(CoresetError::Incompatible(format!(
+                "ClusterModel::load, model was saved with distance {}, got {}",
+                dump.distance_name,
+                std::any::type_name::<Dist>()
+            )));
+        }
+        log::info!(
+            "cluster model loaded from file : {:?}, nb cluster : {}",
+            path,
+            dump.centers.len()
+        );
+        ClusterModel::new(dump.center_ids, dump.centers, distance, dump.cost_fn)
+    } // end of load
+} // end of impl ClusterModel
+
+//========================================================================================
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn log_init_test() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn test_cluster_model() {
+        log_init_test();
+        //
+        let centers: Vec<Vec<f32>> = (0..4)
+            .map(|c| (0..3).map(|j| (10 * c + j) as f32).collect())
+            .collect();
+        let center_ids: Vec<usize> = (0..4).map(|c| 100 + c).collect();
+        let model =
+            ClusterModel::new(center_ids, centers.clone(), DistL2, CostFunction::KMeans).unwrap();
+        // points near each center
+        let datas: Vec<Vec<f32>> = (0..40)
+            .map(|i| centers[i % 4].iter().map(|x| x + 0.5).collect())
+            .collect();
+        let predictions = model.predict_batch(&datas);
+        for (i, (rank, dist)) in predictions.iter().enumerate() {
+            assert_eq!(*rank, i % 4);
+            assert_eq!((*rank, *dist), model.predict(&datas[i]));
+        }
+        //
+        let dir = std::env::temp_dir();
+        for (name, format) in [
+            ("cluster_model.bin", DumpFormat::Bincode),
+            ("cluster_model.json", DumpFormat::Json),
+        ] {
+            let path = dir.join(format!("{}-{}", std::process::id(), name));
+            model.save(&path, format).unwrap();
+            let reloaded = ClusterModel::<usize, f32, DistL2>::load(&path, DistL2).unwrap();
+            assert!(ClusterModel::<usize, f32, DistL1>::load(&path, DistL1).is_err());
+            let _ = std::fs::remove_file(&path);
+            assert_eq!(reloaded.get_nb_cluster(), 4);
+            assert_eq!(reloaded.get_center_id(2), Some(&102));
+            assert_eq!(reloaded.get_cost_function(), CostFunction::KMeans);
+            assert_eq!(reloaded.predict_batch(&datas), predictions);
+        }
+    } // end of test_cluster_model
+} // end of mod tests
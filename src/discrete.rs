@@ -7,35 +7,48 @@ use rand::Rng;
 
 use std::cmp::Ordering;
 
+use crate::error::CoresetError;
+
 pub struct DiscreteProba<F: Float + rand_distr::uniform::SampleUniform> {
     repartition: Vec<F>,
     unif: Uniform<F>,
 }
 
 impl<F: Float + std::fmt::Debug + rand_distr::uniform::SampleUniform> DiscreteProba<F> {
-    pub fn new(probas: &Vec<F>) -> Self {
+    /// probas must be finite and non negative, with a positive sum. They need not be normalized.
+    /// A slot of null probability is never sampled.
+    pub fn new(probas: &Vec<F>) -> Result<Self, CoresetError> {
         let size = probas.len() + 1;
         let mut repartition = Vec::<F>::with_capacity(size);
         let mut cumul = F::zero();
         //
         repartition.push(F::zero());
         for v in probas {
-            assert!(*v >= F::zero());
+            if *v < F::zero() || !v.is_finite() {
+                return Err(CoresetError::InvalidParameter(format!(
+                    "DiscreteProba::new, bad probability {:?}",
+                    v
+                )));
+            }
             cumul = cumul + *v;
             repartition.push(cumul);
         }
+        if cumul <= F::zero() {
+            return Err(CoresetError::NotEnoughData(
+                "DiscreteProba::new, probabilities sum to 0".to_string(),
+            ));
+        }
         // cumulate
-        for i in 1..repartition.len() {
-            repartition[i] = repartition[i] / cumul;
-            assert!(repartition[i] > repartition[i - 1]);
+        for r in repartition.iter_mut().skip(1) {
+            *r = *r / cumul;
         }
         let last = repartition.len() - 1;
         repartition[last] = F::one();
         //
-        DiscreteProba {
+        Ok(DiscreteProba {
             repartition,
             unif: Uniform::<F>::new(F::zero(), F::one()),
-        }
+        })
     }
 
     /// returns slot sampled and associated proba
@@ -91,7 +104,7 @@ mod tests {
 
         let p1: Vec<f32> = vec![0.1, 0.2, 0.2, 0.1, 0.3, 0.05, 0.05];
         //
-        let proba = DiscreteProba::new(&p1);
+        let proba = DiscreteProba::new(&p1).unwrap();
         log::debug!(
             "repartiton function : {:?}",
             proba.get_repartition_function()
@@ -102,4 +115,19 @@ mod tests {
             log::debug!(" slot : {}, weight : {:?}", slot, weight);
         }
     } // end of test1
+
+    #[test]
+    fn test_bad_probas() {
+        log_init_test();
+        //
+        assert!(DiscreteProba::new(&vec![0.5f64, -0.1]).is_err());
+        assert!(DiscreteProba::new(&vec![0.5f64, f64::NAN]).is_err());
+        assert!(DiscreteProba::new(&vec![0.0f64, 0.0]).is_err());
+        // a null probability is accepted and never sampled
+        let proba = DiscreteProba::new(&vec![0.5f64, 0., 0.5]).unwrap();
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(14537);
+        for _ in 0..1000 {
+            assert_ne!(proba.sample(&mut rng).0, 2);
+        }
+    } // end of test_bad_probas
 } // end of mod tests
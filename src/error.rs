@@ -0,0 +1,45 @@
+//! Error type returned by the public entry points of the crate.
+//!
+//! No code path of the library exits the process or panics on bad input or internal failure,
+//! errors are returned as a [CoresetError].
+
+/// errors of the crate
+#[derive(Debug, thiserror::Error)]
+pub enum CoresetError {
+    /// a parameter is out of its admissible range
+    #[error("invalid parameter: {0}")]
+    InvalidParameter(String),
+    /// there is not enough data (or no data) to run the algorithm
+    #[error("not enough data: {0}")]
+    NotEnoughData(String),
+    /// a DataId was found twice where ids must be unique
+    #[error("duplicate id: {0}")]
+    DuplicateId(String),
+    /// the structure does not store data vectors needed by the operation
+    #[error("missing data: {0}")]
+    MissingData(String),
+    /// structures to combine are not compatible (distance, cost function, parameters)
+    #[error("incompatible: {0}")]
+    Incompatible(String),
+    /// a previous step (compute, dispatch ...) must be run before
+    #[error("not computed: {0}")]
+    NotComputed(String),
+    /// a format is not supported, or cannot be deduced from file name
+    #[error("unsupported format: {0}")]
+    Format(String),
+    /// an internal step of an algorithm failed
+    #[error("algorithm failure: {0}")]
+    AlgorithmFailure(String),
+    //
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    //
+    #[error("bincode error: {0}")]
+    Bincode(#[from] bincode::Error),
+    //
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+    //
+    #[error("csv error: {0}")]
+    Csv(#[from] csv::Error),
+} // end of enum CoresetError
@@ -0,0 +1,457 @@
+//! Empirical evaluation of coreset quality.
+//!
+//! A weighted coreset is an epsilon-coreset if for every set of centers, its weighted cost is within a factor (1 +/- epsilon) of the cost of full data.
+//! The guarantee cannot be checked for every set of centers, but [CoresetEvaluator] computes the relative error for a family of candidate center sets:
+//!   - random centers sampled among coreset points,
+//!   - k-means++ seeding on the weighted coreset,
+//!   - perturbed solutions, obtained by swapping some centers of a given solution with random coreset points,
+//!   - or any center set given by the user.
+//!
+//! Costs of all candidates on the full data are computed in one pass on data.
+//!
+
+use rand::distributions::{Distribution, Uniform, WeightedIndex};
+use rand_xoshiro::rand_core::SeedableRng;
+use rand_xoshiro::Xoshiro256PlusPlus;
+
+use quantiles::ckms::CKMS;
+use rayon::prelude::*;
+
+use cpu_time::ProcessTime;
+use std::time::SystemTime;
+
+use anndists::dist::*;
+
+use crate::error::CoresetError;
+use crate::facility::CostFunction;
+use crate::makeiter::*;
+use crate::sensitivity::*;
+
+// cost of a point with respect to each candidate
+fn candidates_cost<T: Send + Sync, Dist: Distance<T>>(
+    data: &[T],
+    candidates: &[Vec<Vec<T>>],
+    distance: &Dist,
+    cost_fn: CostFunction,
+) -> Vec<f64> {
+    candidates
+        .iter()
+        .map(|centers| {
+            let dist = centers
+                .iter()
+                .map(|c| distance.eval(c, data))
+                .fold(f32::INFINITY, f32::min);
+            cost_fn.eval(dist) as f64
+        })
+        .collect()
+}
+
+/// Result of a coreset evaluation, for each candidate center set the cost on full data, the weighted cost on coreset
+/// and the relative error between them.
+pub struct Distortion {
+    // cost of each candidate on full data
+    full_costs: Vec<f64>,
+    // weighted cost of each candidate on coreset
+    coreset_costs: Vec<f64>,
+    // |coreset_cost - full_cost| / full_cost
+    relative_errors: Vec<f64>,
+    //
+    quantiles: CKMS<f64>,
+}
+
+impl Distortion {
+    fn new(full_costs: Vec<f64>, coreset_costs: Vec<f64>) -> Self {
+        let relative_errors: Vec<f64> = full_costs
+            .iter()
+            .zip(coreset_costs.iter())
+            .map(|(f, c)| if *f > 0. { (c - f).abs() / f } else { 0. })
+            .collect();
+        let mut quantiles = CKMS::<f64>::new(0.01);
+        for e in &relative_errors {
+            quantiles.insert(*e);
+        }
+        Distortion {
+            full_costs,
+            coreset_costs,
+            relative_errors,
+            quantiles,
+        }
+    }
+
+    /// returns cost of each candidate on full data
+    pub fn get_full_costs(&self) -> &Vec<f64> {
+        &self.full_costs
+    }
+
+    /// returns weighted cost of each candidate on coreset
+    pub fn get_coreset_costs(&self) -> &Vec<f64> {
+        &self.coreset_costs
+    }
+
+    /// returns relative error of each candidate
+    pub fn get_relative_errors(&self) -> &Vec<f64> {
+        &self.relative_errors
+    }
+
+    /// maximal relative error, this is the empirical epsilon of the coreset
+    pub fn get_max_error(&self) -> f64 {
+        self.relative_errors.iter().fold(0., |acc, e| e.max(acc))
+    }
+
+    /// mean relative error
+    pub fn get_mean_error(&self) -> f64 {
+        if self.relative_errors.is_empty() {
+            return 0.;
+        }
+        self.relative_errors.iter().sum::<f64>() / self.relative_errors.len() as f64
+    }
+
+    /// returns quantile of relative errors, q must be in [0., 1.]
+    pub fn get_quantile(&self, q: f64) -> Option<f64> {
+        self.quantiles.query(q).map(|(_, e)| e)
+    }
+
+    /// dumps a summary on stdout
+    pub fn summary(&self) {
+        println!(
+            "\n coreset distortion on {} candidates, max relative error : {:.3e}, mean relative error : {:.3e}",
+            self.relative_errors.len(),
+            self.get_max_error(),
+            self.get_mean_error()
+        );
+        println!(" quantiles of relative error :");
+        for q in [0.1, 0.25, 0.5, 0.75, 0.9, 0.99] {
+            if let Some(e) = self.get_quantile(q) {
+                println!("  {:.2}   {:.3e}", q, e);
+            }
+        }
+    } // end of summary
+} // end of impl Distortion
+
+//=========================================================================================
+
+#[cfg_attr(doc, katexit::katexit)]
+/// Computes the relative error of coreset cost with respect to full data cost, for a family of candidate center sets.  
+/// For a coreset $C$ with weights $w$ of data $P$ and a center set $S$ the relative error is :
+///   $ | \sum_{c \in C} w(c) * cost(c, S) - \sum_{p \in P} cost(p, S) | / \sum_{p \in P} cost(p, S) $
+pub struct CoresetEvaluator<
+    'a,
+    DataId,
+    T: Send + Sync + Clone,
+    Dist: Distance<T> + Clone + Sync + Send,
+> {
+    //
+    coreset: &'a CoreSet<DataId, T, Dist>,
+    // each candidate is a set of centers
+    candidates: Vec<Vec<Vec<T>>>,
+    //
+    seed: u64,
+    //
+    rng: Xoshiro256PlusPlus,
+}
+
+impl<'a, DataId, T, Dist> CoresetEvaluator<'a, DataId, T, Dist>
+where
+    DataId: Eq + std::hash::Hash + Send + Sync + Clone + std::fmt::Debug,
+    T: Send + Sync + Clone,
+    Dist: Distance<T> + Clone + Sync + Send,
+{
+    /// The coreset must store its data vectors as candidates are sampled among coreset points.
+    pub fn new(coreset: &'a CoreSet<DataId, T, Dist>) -> Result<Self, CoresetError> {
+        match coreset.get_data_points() {
+            None => {
+                return Err(CoresetError::MissingData(
+                    "CoresetEvaluator::new, coreset must store data vectors".to_string(),
+                ))
+            }
+            Some(points) if points.is_empty() => {
+                return Err(CoresetError::NotEnoughData(
+                    "CoresetEvaluator::new, empty coreset".to_string(),
+                ))
+            }
+            _ => {}
+        }
+        Ok(CoresetEvaluator {
+            coreset,
+            candidates: Vec::new(),
+            seed: 7919,
+            rng: Xoshiro256PlusPlus::seed_from_u64(7919),
+        })
+    }
+
+    /// sets the seed of the random generator used to sample candidates (default is 7919) and resets the generator.  
+    /// It must be called before adding candidates. With a given seed candidates are reproducible.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = Xoshiro256PlusPlus::seed_from_u64(seed);
+    }
+
+    /// returns the seed of the random generator used to sample candidates
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// returns number of candidate center sets
+    pub fn get_nb_candidates(&self) -> usize {
+        self.candidates.len()
+    }
+
+    /// adds a candidate center set given by the user
+    pub fn add_candidate(&mut self, centers: Vec<Vec<T>>) -> Result<(), CoresetError> {
+        if centers.is_empty() {
+            return Err(CoresetError::InvalidParameter(
+                "CoresetEvaluator::add_candidate, no center".to_string(),
+            ));
+        }
+        self.candidates.push(centers);
+        Ok(())
+    }
+
+    // points of coreset and their weights
+    #[allow(clippy::type_complexity)]
+    fn get_points(
+        coreset: &'a CoreSet<DataId, T, Dist>,
+    ) -> Result<(&'a Vec<(DataId, Vec<T>)>, Vec<f64>), CoresetError> {
+        let points = coreset.get_data_points().ok_or_else(|| {
+            CoresetError::MissingData("CoresetEvaluator, coreset must store data vectors".to_string())
+        })?;
+        let weights = points
+            .iter()
+            .map(|(id, _)| {
+                coreset.get_weight(id).ok_or_else(|| {
+                    CoresetError::MissingData(format!("CoresetEvaluator, no weight for id {:?}", id))
+                })
+            })
+            .collect::<Result<Vec<f64>, CoresetError>>()?;
+        Ok((points, weights))
+    }
+
+    /// adds nb_candidates sets of k centers sampled uniformly among coreset points
+    pub fn add_random_candidates(&mut self, k: usize, nb_candidates: usize) -> Result<(), CoresetError> {
+        if k == 0 {
+            return Err(CoresetError::InvalidParameter(
+                "CoresetEvaluator::add_random_candidates, k must be at least 1".to_string(),
+            ));
+        }
+        let (points, _) = Self::get_points(self.coreset)?;
+        let unif = Uniform::<usize>::new(0, points.len());
+        let candidates: Vec<Vec<Vec<T>>> = (0..nb_candidates)
+            .map(|_| {
+                (0..k)
+                    .map(|_| points[unif.sample(&mut self.rng)].1.clone())
+                    .collect()
+            })
+            .collect();
+        self.candidates.extend(candidates);
+        Ok(())
+    } // end of add_random_candidates
+
+    /// adds nb_candidates sets of k centers obtained by k-means++ seeding (with coreset cost function) on weighted coreset points
+    pub fn add_kmeanspp_candidates(&mut self, k: usize, nb_candidates: usize) -> Result<(), CoresetError> {
+        if k == 0 {
+            return Err(CoresetError::InvalidParameter(
+                "CoresetEvaluator::add_kmeanspp_candidates, k must be at least 1".to_string(),
+            ));
+        }
+        let (points, weights) = Self::get_points(self.coreset)?;
+        let distance = self.coreset.get_distance();
+        let cost_fn = self.coreset.get_cost_function();
+        let mut candidates = Vec::<Vec<Vec<T>>>::with_capacity(nb_candidates);
+        for _ in 0..nb_candidates {
+            let mut centers = Vec::<Vec<T>>::with_capacity(k);
+            let mut costs = weights.clone();
+            for _ in 0..k {
+                let sampler = match WeightedIndex::new(&costs) {
+                    Ok(sampler) => sampler,
+                    // all points are centers
+                    Err(_) => break,
+                };
+                let chosen = &points[sampler.sample(&mut self.rng)].1;
+                centers.push(chosen.clone());
+                for (i, (_, data)) in points.iter().enumerate() {
+                    let cost = weights[i] * cost_fn.eval(distance.eval(chosen, data)) as f64;
+                    if centers.len() == 1 || cost < costs[i] {
+                        costs[i] = cost;
+                    }
+                }
+            }
+            // all coreset weights are null, no center can be sampled
+            if centers.is_empty() {
+                return Err(CoresetError::NotComputed(
+                    "CoresetEvaluator::add_kmeanspp_candidates, null coreset weights".to_string(),
+                ));
+            }
+            candidates.push(centers);
+        }
+        self.candidates.extend(candidates);
+        Ok(())
+    } // end of add_kmeanspp_candidates
+
+    /// adds nb_candidates perturbations of a solution : for each candidate nb_swap centers of solution (chosen at random)
+    /// are replaced by random coreset points.
+    pub fn add_perturbed_candidates(
+        &mut self,
+        solution: &[Vec<T>],
+        nb_candidates: usize,
+        nb_swap: usize,
+    ) -> Result<(), CoresetError> {
+        if solution.is_empty() {
+            return Err(CoresetError::InvalidParameter(
+                "CoresetEvaluator::add_perturbed_candidates, empty solution".to_string(),
+            ));
+        }
+        let (points, _) = Self::get_points(self.coreset)?;
+        let unif_points = Uniform::<usize>::new(0, points.len());
+        let unif_centers = Uniform::<usize>::new(0, solution.len());
+        let candidates: Vec<Vec<Vec<T>>> = (0..nb_candidates)
+            .map(|_| {
+                let mut centers = solution.to_vec();
+                for _ in 0..nb_swap {
+                    let c = unif_centers.sample(&mut self.rng);
+                    centers[c] = points[unif_points.sample(&mut self.rng)].1.clone();
+                }
+                centers
+            })
+            .collect();
+        self.candidates.extend(candidates);
+        Ok(())
+    } // end of add_perturbed_candidates
+
+    /// computes costs of all candidates on full data (one pass on data) and on coreset and returns relative errors.
+    pub fn evaluate<IterProducer>(
+        &self,
+        iter_producer: &IterProducer,
+    ) -> Result<Distortion, CoresetError>
+    where
+        IterProducer: MakeIter<Item = (DataId, Vec<T>)>,
+    {
+        //
+        let cpu_start = ProcessTime::now();
+        let sys_now = SystemTime::now();
+        //
+        if self.candidates.is_empty() {
+            return Err(CoresetError::NotComputed(
+                "CoresetEvaluator::evaluate, no candidates".to_string(),
+            ));
+        }
+        let distance = self.coreset.get_distance();
+        let cost_fn = self.coreset.get_cost_function();
+        let nb_candidates = self.candidates.len();
+        let add_costs = |mut a: Vec<f64>, b: Vec<f64>| {
+            a.iter_mut().zip(b).for_each(|(x, y)| *x += y);
+            a
+        };
+        // coreset costs
+        let (points, weights) = Self::get_points(self.coreset)?;
+        let coreset_costs = points
+            .par_iter()
+            .zip(weights.par_iter())
+            .map(|((_, data), w)| {
+                let mut costs = candidates_cost(data, &self.candidates, distance, cost_fn);
+                costs.iter_mut().for_each(|c| *c *= w);
+                costs
+            })
+            .reduce(|| vec![0.; nb_candidates], add_costs);
+        // full data costs, one pass by blocks
+        let bufsize: usize = 10000;
+        let mut full_costs = vec![0.; nb_candidates];
+        let mut nb_data = 0usize;
+        let mut iter = iter_producer.makeiter();
+        let mut buffer = Vec::<Vec<T>>::with_capacity(bufsize);
+        loop {
+            buffer.extend(iter.by_ref().take(bufsize).map(|(_, data)| data));
+            if buffer.is_empty() {
+                break;
+            }
+            nb_data += buffer.len();
+            let block_costs = buffer
+                .par_iter()
+                .map(|data| candidates_cost(data, &self.candidates, distance, cost_fn))
+                .reduce(|| vec![0.; nb_candidates], add_costs);
+            full_costs = add_costs(full_costs, block_costs);
+            buffer.clear();
+        }
+        log::info!(
+            "CoresetEvaluator::evaluate nb data : {}, nb candidates : {}, sys time(ms) {:?} cpu time(ms) {:?}",
+            nb_data,
+            nb_candidates,
+            sys_now.elapsed().unwrap().as_millis(),
+            cpu_start.elapsed().as_millis()
+        );
+        //
+        Ok(Distortion::new(full_costs, coreset_costs))
+    } // end of evaluate
+} // end of impl CoresetEvaluator
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rand_distr::Normal;
+
+    fn log_init_test() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    struct DataForIter {
+        datas: Vec<Vec<f32>>,
+    }
+
+    impl MakeIter for DataForIter {
+        type Item = (usize, Vec<f32>);
+        //
+        fn makeiter(&self) -> impl Iterator<Item = Self::Item> {
+            self.datas.iter().cloned().enumerate()
+        }
+    }
+
+    #[test]
+    fn test_coreset_distortion() {
+        log_init_test();
+        //
+        let nbdata = 20000;
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(1454691);
+        let unif = Uniform::<f32>::new(-20., 20.);
+        let centers: Vec<Vec<f32>> = (0..5)
+            .map(|_| (0..10).map(|_| unif.sample(&mut rng)).collect())
+            .collect();
+        let normal = Normal::<f32>::new(0., 1.).unwrap();
+        let datas: Vec<Vec<f32>> = (0..nbdata)
+            .map(|i| {
+                centers[i % 5]
+                    .iter()
+                    .map(|x| x + normal.sample(&mut rng))
+                    .collect()
+            })
+            .collect();
+        let iter_producer = DataForIter { datas };
+        //
+        let mut coreset1 = Coreset1::new(5, nbdata, 2., 2., DistL2, CostFunction::KMedian);
+        let coreset = coreset1.make_coreset(&iter_producer, 0.1).unwrap();
+        //
+        let mut evaluator = CoresetEvaluator::new(&coreset).unwrap();
+        evaluator.add_random_candidates(5, 20).unwrap();
+        evaluator.add_kmeanspp_candidates(5, 20).unwrap();
+        evaluator.add_perturbed_candidates(&centers, 20, 1).unwrap();
+        assert_eq!(evaluator.get_nb_candidates(), 60);
+        let distortion = evaluator.evaluate(&iter_producer).unwrap();
+        distortion.summary();
+        assert!(distortion.get_mean_error() < 0.1);
+        assert!(distortion.get_max_error() < 0.2);
+        // k = 0 gives empty center sets
+        assert!(evaluator.add_random_candidates(0, 20).is_err());
+        assert!(evaluator.add_kmeanspp_candidates(0, 20).is_err());
+        assert_eq!(evaluator.get_nb_candidates(), 60);
+        // same seed gives same candidates, other seed gives other candidates
+        let sample = |seed: u64| {
+            let mut evaluator = CoresetEvaluator::new(&coreset).unwrap();
+            evaluator.set_seed(seed);
+            assert_eq!(evaluator.get_seed(), seed);
+            evaluator.add_random_candidates(5, 4).unwrap();
+            evaluator.add_kmeanspp_candidates(5, 4).unwrap();
+            evaluator.candidates
+        };
+        assert_eq!(sample(4583), sample(4583));
+        assert_ne!(sample(4583), sample(7919));
+    } // end of test_coreset_distortion
+} // end of mod tests
@@ -2,7 +2,9 @@
 //! Each facility maintain weights of data items dispatched to it and cost contribution
 //!
 
-use anyhow::*;
+use crate::error::CoresetError;
+use crate::kernel::{CenterBlock, POINT_BLOCK};
+use crate::pivot::{PivotIndex, PivotIndexArg};
 
 use serde::{Deserialize, Serialize};
 
@@ -11,16 +13,52 @@ use quantiles::ckms::CKMS; // we could use also greenwald_khanna
 
 use rayon::prelude::*;
 
-use parking_lot::RwLock;
-use std::sync::Arc;
-
 use std::collections::HashMap;
 
 use anndists::dist::*;
 
+#[cfg_attr(doc, katexit::katexit)]
+/// The cost function used by clustering algorithms.  
+/// The cost of a point $p$ dispatched to center $c$ is $ w(p) * dist(p,c)^z $.  
+/// z = 1 corresponds to the k-median problem, z = 2 to the k-means problem.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum CostFunction {
+    /// z = 1, the cost is the distance
+    #[default]
+    KMedian,
+    /// z = 2, the cost is the squared distance
+    KMeans,
+    /// cost is distance at power z
+    Power(f32),
+}
+
+impl CostFunction {
+    /// returns exponent z of distance
+    pub fn get_z(&self) -> f32 {
+        match self {
+            CostFunction::KMedian => 1.,
+            CostFunction::KMeans => 2.,
+            CostFunction::Power(z) => *z,
+        }
+    }
+
+    #[cfg_attr(doc, katexit::katexit)]
+    /// returns cost associated to a distance, i.e $dist^z$
+    #[inline]
+    pub fn eval(&self, dist: f32) -> f32 {
+        match self {
+            CostFunction::KMedian => dist,
+            CostFunction::KMeans => dist * dist,
+            CostFunction::Power(z) => dist.powf(*z),
+        }
+    }
+} // end of impl CostFunction
+
+//===================================================================================
+
 /// A facility is a dataid and the center (or point in data) that correspond to a k medoid point.  
 /// The struture stores the data vector and point id which serve as a center, the sum of points weight
-/// attached to this point and the cost (distance between data points and center multiplied by point's weight)
+/// attached to this point and the cost (distance at power z, see [CostFunction], between data points and center multiplied by point's weight)
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Facility<DataId, T: Send + Sync + Clone> {
     // rank in data
@@ -64,15 +102,16 @@ impl<DataId: std::fmt::Debug + Clone, T: Send + Sync + Clone> Facility<DataId, T
     }
 
     #[cfg_attr(doc, katexit::katexit)]
-    /// return cost carried by this facility $f$ i.e :  $ cost(f) = \sum_{p \in f} w(p) * dist(p,f) $
+    /// return cost carried by this facility $f$ i.e :  $ cost(f) = \sum_{p \in f} w(p) * dist(p,f)^z $
     pub fn get_cost(&self) -> f64 {
         self.cost
     }
 
-    // This function increments weight and cost related to a facility
-    pub(crate) fn insert(&mut self, weight: f64, dist: f32) {
+    // This function increments weight and cost related to a facility.
+    // point_cost is the distance of the point to facility transformed by the cost function
+    pub(crate) fn insert(&mut self, weight: f64, point_cost: f32) {
         self.weight += weight;
-        self.cost += dist as f64 * weight;
+        self.cost += point_cost as f64 * weight;
     }
 
     // This function empties a facility keeping its position
@@ -101,10 +140,10 @@ impl<DataId: std::fmt::Debug + Clone, T: Send + Sync + Clone> Facility<DataId, T
 /// The structure maintains the list of open facilities (or cluster) and their centers.
 /// It computes and store (see [compute_weight_cost](compute_weight_cost)) total weight dispatched into facilities and maintain
 /// global facility cost assignment  as :
-///   $ \sum_{p \in P}  \medspace  w(p) * dist(p, cf_{p})$.
+///   $ \sum_{p \in P}  \medspace  w(p) * dist(p, cf_{p})^z$.
 /// where $ cf_{p}$ is the center of facility assigned to $p$
 ///
-// As we want parallel access, running concurrently on all data we need Arc RwLock stuff
+// Facilities are stored without locks, dispatching passes accumulate weights and costs in FacilityAccumulator
 
 pub type FacilityId = usize;
 
@@ -145,16 +184,71 @@ impl PointMap {
     }
 } // end of PointMap
 
-/// This structuree represents the list of facilities created
+/// Weights and costs accumulated on a chunk of points during a dispatching pass, indexed by facility rank.  
+/// Accumulators of chunks are merged at end of the pass and then added once to facilities,
+/// so dispatching passes can run in parallel on chunks of data.
+#[derive(Clone, Debug)]
+pub(crate) struct FacilityAccumulator {
+    weights: Vec<f64>,
+    costs: Vec<f64>,
+}
+
+impl FacilityAccumulator {
+    pub(crate) fn new(nb_facility: usize) -> Self {
+        FacilityAccumulator {
+            weights: vec![0.; nb_facility],
+            costs: vec![0.; nb_facility],
+        }
+    }
+
+    // point_cost is the distance of the point to facility transformed by the cost function
+    #[inline]
+    pub(crate) fn insert(&mut self, facility: usize, weight: f64, point_cost: f32) {
+        self.weights[facility] += weight;
+        self.costs[facility] += point_cost as f64 * weight;
+    }
+
+    pub(crate) fn merge(mut self, other: Self) -> Self {
+        for (w, w_o) in self.weights.iter_mut().zip(other.weights) {
+            *w += w_o;
+        }
+        for (c, c_o) in self.costs.iter_mut().zip(other.costs) {
+            *c += c_o;
+        }
+        self
+    }
+} // end of impl FacilityAccumulator
+
+// number of points accumulated in one FacilityAccumulator by Facilities::dispatch_block
+const ACCUMULATION_BLOCK: usize = 4096;
+
+/// This structuree represents the list of facilities created.  
+/// Facilities are stored as a struct of arrays : centers are stored contiguously in one vector,
+/// weights and costs in two others, so nearest facility searches and dispatching passes do not need any lock.
 #[derive(Clone)]
 pub struct Facilities<DataId, T: Send + Sync + Clone, Dist: Distance<T>> {
-    centers: Vec<Arc<RwLock<Facility<DataId, T>>>>,
+    // data id of each facility center
+    ids: Vec<DataId>,
+    // centers positions stored contiguously, center i is positions[offsets[i]..offsets[i+1]].
+    positions: Vec<T>,
+    //
+    offsets: Vec<usize>,
+    // sum of points weight dispatched to each facility, divided by scale
+    weights: Vec<f64>,
+    // sum of points weight * cost to center for each facility, divided by scale
+    costs: Vec<f64>,
+    // common factor of all weights and costs. A decay multiplies it, so it costs O(1) and not O(nb facilities).
+    scale: f64,
     //
     distance: Dist,
+    // transforms distances into costs
+    cost_fn: CostFunction,
     // sum of weights dispatched into facilities
     weight: f64,
-    // sum of weights * distance to facility center dispatched into facilities
+    // sum of weights * cost to facility center dispatched into facilities
     cost: f64,
+    // optional index to accelerate nearest facility search
+    index: Option<PivotIndex>,
 } // end of struct Facilities
 
 impl<
@@ -164,43 +258,90 @@ impl<
     > Facilities<DataId, T, Dist>
 {
     /// to be allocated , size should be log(nb_data)
-    pub fn new(size: usize, distance: Dist) -> Self {
-        let centers = Vec::<Arc<RwLock<Facility<DataId, T>>>>::with_capacity(size);
+    /// cost_fn defines how distances to facilities are transformed into costs.
+    pub fn new(size: usize, distance: Dist, cost_fn: CostFunction) -> Self {
         Facilities {
-            centers,
+            ids: Vec::with_capacity(size),
+            positions: Vec::new(),
+            offsets: vec![0],
+            weights: Vec::with_capacity(size),
+            costs: Vec::with_capacity(size),
+            scale: 1.,
             distance,
+            cost_fn,
             weight: 0.,
             cost: 0.,
+            index: None,
+        }
+    }
+
+    /// sets (or removes with None) a pivot index to accelerate [get_nearest_facility](Self::get_nearest_facility()), see [pivot](crate::pivot).  
+    /// The distance must satisfy the triangle inequality. Default is no index, i.e a linear scan on facilities.
+    pub fn set_index(&mut self, arg: Option<PivotIndexArg>) {
+        self.index = arg.map(PivotIndex::new);
+        self.update_index();
+    }
+
+    // indexes facilities not yet in index
+    fn update_index(&mut self) {
+        if let Some(mut index) = self.index.take() {
+            index.update(self.ids.len(), |i, j| {
+                self.distance.eval(self.get_center(i), self.get_center(j))
+            });
+            self.index = Some(index);
         }
     }
 
+    /// returns the parameters of the pivot index if any
+    pub fn get_index_arg(&self) -> Option<PivotIndexArg> {
+        self.index.as_ref().map(|index| index.get_arg())
+    }
+
     /// return number of facility
     pub fn len(&self) -> usize {
-        self.centers.len()
+        self.ids.len()
     }
 
     pub fn is_empty(&self) -> bool {
-        matches!(self.centers.len(), 0)
+        matches!(self.ids.len(), 0)
     }
 
     /// total weight already inserted
     pub fn get_weight(&self) -> f64 {
-        return self.centers.iter().map(|f| f.read().get_weight()).sum();
+        self.scale * self.weights.iter().sum::<f64>()
     }
 
     pub fn get_distance(&self) -> &Dist {
         &self.distance
     }
 
+    /// returns the cost function used to compute facility costs
+    pub fn get_cost_function(&self) -> CostFunction {
+        self.cost_fn
+    }
+
+    // changes the cost function. Facilities must be emptied and data dispatched again
+    pub(crate) fn set_cost_function(&mut self, cost_fn: CostFunction) {
+        self.cost_fn = cost_fn;
+    }
+
     /// returns sum of costs dispatched into facilities.
     pub fn get_cost(&self) -> f64 {
-        return self.centers.iter().map(|f| f.read().get_cost()).sum();
+        self.scale * self.costs.iter().sum::<f64>()
     }
 
     // deletes all facilities. useful in algorithm bmor when we need to reinitialize.
     pub(crate) fn clear(&mut self) {
         log::debug!("clearing facilities");
-        self.centers.clear();
+        self.ids.clear();
+        self.positions.clear();
+        self.offsets.truncate(1);
+        self.weights.clear();
+        self.costs.clear();
+        self.scale = 1.;
+        if let Some(index) = self.index.as_mut() {
+            index.clear();
+        }
         self.weight = 0.;
         self.cost = 0.;
     }
@@ -209,23 +350,98 @@ impl<
     // useful in coreset construction
     pub(crate) fn empty(&mut self) {
         log::debug!("emptying facilities");
-        for f in &self.centers {
-            f.write().empty();
-        }
+        self.weights.iter_mut().for_each(|w| *w = 0.);
+        self.costs.iter_mut().for_each(|c| *c = 0.);
+        self.scale = 1.;
         self.weight = 0.;
         self.cost = 0.;
     }
 
-    // access to internal representation
-    pub(crate) fn get_vec(&self) -> &Vec<Arc<RwLock<Facility<DataId, T>>>> {
-        &self.centers
+    // multiplies weight and cost of each facility by factor. Only the common scale is multiplied.
+    // When the scale falls below 0.5, weights and costs are renormalized and facilities whose weight (in absolute value)
+    // fell below min_weight are removed, so the cost is O(nb facilities) once every log(0.5)/log(factor) decays.
+    // returns absolute weight and cost of removed facilities
+    pub(crate) fn decay(&mut self, factor: f64, min_weight: f64) -> (f64, f64) {
+        self.scale *= factor;
+        self.weight *= factor;
+        self.cost *= factor;
+        if self.scale >= 0.5 {
+            return (0., 0.);
+        }
+        // renormalize, weights and costs get their true value and scale is reset
+        let scale = self.scale;
+        self.weights.iter_mut().for_each(|w| *w *= scale);
+        self.costs.iter_mut().for_each(|c| *c *= scale);
+        self.scale = 1.;
+        if self.weights.iter().all(|w| w.abs() >= min_weight) {
+            return (0., 0.);
+        }
+        let centers = self.to_facility_vec();
+        let (weight, cost) = (self.weight, self.cost);
+        self.clear();
+        let mut dropped = (0., 0.);
+        for f in centers {
+            if f.weight.abs() < min_weight {
+                dropped.0 += f.weight.abs();
+                dropped.1 += f.cost.abs();
+            } else {
+                self.insert(f);
+            }
+        }
+        self.weight = (weight - dropped.0).max(0.);
+        self.cost = (cost - dropped.1).max(0.);
+        log::debug!(
+            "Facilities::decay dropped weight : {:.3e}, nb facilities kept : {}",
+            dropped.0,
+            self.ids.len()
+        );
+        dropped
+    } // end of decay
+
+    // returns a copy of facilities, in rank order
+    pub(crate) fn to_facility_vec(&self) -> Vec<Facility<DataId, T>> {
+        (0..self.ids.len())
+            .map(|i| self.get_cloned_facility(i).unwrap())
+            .collect()
+    }
+
+    // returns a copy of facilities with weights and costs as stored, i.e divided by the scale, and the scale and totals.
+    // With [from_raw_parts](Self::from_raw_parts()) decays go on exactly as in the original facilities.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn to_raw_parts(&self) -> (Vec<Facility<DataId, T>>, f64, f64, f64) {
+        let facilities = (0..self.ids.len())
+            .map(|i| Facility {
+                d_rank: self.ids[i].clone(),
+                center: self.get_center(i).to_vec(),
+                weight: self.weights[i],
+                cost: self.costs[i],
+            })
+            .collect();
+        (facilities, self.scale, self.weight, self.cost)
+    }
+
+    // inserts facilities returned by [to_raw_parts](Self::to_raw_parts()) in empty facilities, and sets scale and totals.
+    pub(crate) fn from_raw_parts(
+        &mut self,
+        facilities: Vec<Facility<DataId, T>>,
+        scale: f64,
+        weight: f64,
+        cost: f64,
+    ) {
+        self.clear();
+        for f in facilities {
+            self.insert(f);
+        }
+        self.scale = scale;
+        self.weight = weight;
+        self.cost = cost;
     }
 
     /// return true if there is a facility around point at distance less than dmax
     pub fn match_point(&self, point: &[T], dmax: f32, distance: &Dist) -> bool {
         //
-        for f in &self.centers {
-            if distance.eval(f.read().get_position(), point) <= dmax {
+        for i in 0..self.ids.len() {
+            if distance.eval(self.get_center(i), point) <= dmax {
                 return true;
             }
         }
@@ -234,96 +450,214 @@ impl<
 
     /// insert a new facility
     pub(crate) fn insert(&mut self, facility: Facility<DataId, T>) {
-        self.centers.push(Arc::new(RwLock::new(facility)));
+        self.positions.extend_from_slice(facility.get_position());
+        self.offsets.push(self.positions.len());
+        self.ids.push(facility.d_rank);
+        self.weights.push(facility.weight / self.scale);
+        self.costs.push(facility.cost / self.scale);
+        self.update_index();
         //
         log::trace!(
             "Facilities: facility insertion nb facilities : {}",
-            self.centers.len()
+            self.ids.len()
         );
     }
 
-    /// retrieve facility by rank if rank is Ok
-    pub fn get_facility(&self, rank: usize) -> Option<&Arc<RwLock<Facility<DataId, T>>>> {
-        if rank >= self.centers.len() {
-            None
-        } else {
-            Some(&self.centers[rank])
-        }
+    /// returns position of facility of given rank. rank must be less than [len](Self::len()).
+    #[inline]
+    pub fn get_center(&self, rank: usize) -> &[T] {
+        &self.positions[self.offsets[rank]..self.offsets[rank + 1]]
+    }
+
+    /// returns data id of center of facility of given rank. rank must be less than [len](Self::len()).
+    pub fn get_dataid(&self, rank: usize) -> DataId {
+        self.ids[rank].clone()
     }
 
-    /// retrieve facility of given rank , and returns a clone (to avoid synchro stuff)
+    /// retrieve facility of given rank as a [Facility] (center, weight and cost are copied).
     /// useful for easy final analysis
     pub fn get_cloned_facility(&self, rank: usize) -> Option<Facility<DataId, T>> {
-        if rank >= self.centers.len() {
+        if rank >= self.ids.len() {
             None
         } else {
-            return Some(self.centers[rank].read().clone());
+            Some(Facility {
+                d_rank: self.ids[rank].clone(),
+                center: self.get_center(rank).to_vec(),
+                weight: self.scale * self.weights[rank],
+                cost: self.scale * self.costs[rank],
+            })
         }
     } // end of get_cloned_facility
 
     /// return weight in facility of rank rank, error else
-    pub fn get_facility_weight(&self, rank: usize) -> Result<f64> {
-        if rank <= self.centers.len() {
-            return Ok(self.centers[rank].read().get_weight());
+    pub fn get_facility_weight(&self, rank: usize) -> Result<f64, CoresetError> {
+        if rank < self.ids.len() {
+            Ok(self.scale * self.weights[rank])
         } else {
-            Err(anyhow!("not so many facilities , rank is {}", rank))
+            Err(CoresetError::InvalidParameter(format!(
+                "not so many facilities , rank is {}",
+                rank
+            )))
         }
-    } // end of facililities_ref
+    } // end of get_facility_weight
+
+    /// return cost in facility of rank rank, error else
+    pub fn get_facility_cost(&self, rank: usize) -> Result<f64, CoresetError> {
+        if rank < self.ids.len() {
+            Ok(self.scale * self.costs[rank])
+        } else {
+            Err(CoresetError::InvalidParameter(format!(
+                "not so many facilities , rank is {}",
+                rank
+            )))
+        }
+    } // end of get_facility_cost
 
     /// return rank of nearest facility and distance to it
-    /// If there are many facilities to search (thousands), setting the parallel flag to true is useful
-    pub fn get_nearest_facility(&self, data: &[T], parallel: bool) -> anyhow::Result<(usize, f32)> {
+    /// If there are many facilities to search (thousands), setting the parallel flag to true is useful.  
+    /// If a pivot index is set (see [set_index](Self::set_index())) and built the search uses it and the parallel flag is ignored.
+    pub fn get_nearest_facility(
+        &self,
+        data: &[T],
+        parallel: bool,
+    ) -> Result<(usize, f32), CoresetError> {
         let mut dist = f32::INFINITY;
         let mut rank_f: usize = usize::MAX;
-        if self.centers.is_empty() {
-            return Err(anyhow!("Empty facility"));
+        if self.ids.is_empty() {
+            return Err(CoresetError::NotEnoughData("Empty facility".to_string()));
         }
         //
-        let dist_to_f = |i| -> (usize, f32) {
-            let f_i = self.get_facility(i).unwrap().read();
-            let center_i = f_i.get_position();
-            let d_i = self.distance.eval(center_i, data);
-            (i, d_i)
-        };
-        let dist_slot: Vec<(usize, f32)> = match parallel {
-            true => (0..self.centers.len())
-                .into_par_iter()
-                .map(dist_to_f)
-                .collect(),
-            false => (0..self.centers.len()).map(dist_to_f).collect(),
-        };
-        for (f, d) in dist_slot {
-            if d < dist {
-                dist = d;
-                rank_f = f;
+        let dist_to_f = |i| -> (usize, f32) { (i, self.distance.eval(self.get_center(i), data)) };
+        match self.index.as_ref() {
+            Some(index) if index.is_built() => {
+                (rank_f, dist) = index.search(|i| dist_to_f(i).1);
+            }
+            _ => {
+                let dist_slot: Vec<(usize, f32)> = match parallel {
+                    true => (0..self.ids.len())
+                        .into_par_iter()
+                        .map(dist_to_f)
+                        .collect(),
+                    false => (0..self.ids.len()).map(dist_to_f).collect(),
+                };
+                for (f, d) in dist_slot {
+                    if d < dist {
+                        dist = d;
+                        rank_f = f;
+                    }
+                }
             }
         }
-        assert!(rank_f < usize::MAX);
+        if rank_f == usize::MAX {
+            return Err(CoresetError::AlgorithmFailure(
+                "get_nearest_facility, no finite distance to facilities".to_string(),
+            ));
+        }
         //
         Ok((rank_f, dist))
     } // end of get_nearest_facility
 
     /// insert a point into given facility (must be the one given by get_nearest_facility)
-    pub(crate) fn insert_point(&self, facility: usize, dist: f32, weight: f32) {
-        let mut f = self.centers[facility].write();
-        f.weight += weight as f64;
-        f.cost += dist as f64 * weight as f64;
+    pub(crate) fn insert_point(&mut self, facility: usize, dist: f32, weight: f64) {
+        self.weights[facility] += weight / self.scale;
+        self.costs[facility] += self.cost_fn.eval(dist) as f64 * weight / self.scale;
     }
 
+    // adds weights and costs accumulated during a dispatching pass
+    pub(crate) fn add_accumulated(&mut self, accumulator: &FacilityAccumulator) {
+        for (w, w_a) in self.weights.iter_mut().zip(accumulator.weights.iter()) {
+            *w += w_a / self.scale;
+        }
+        for (c, c_a) in self.costs.iter_mut().zip(accumulator.costs.iter()) {
+            *c += c_a / self.scale;
+        }
+    }
+
+    // returns (in parallel) for each data its nearest facility and distance to it.
+    // Unless the pivot index is used, data are scored by blocks against all facilities with a CenterBlock kernel.
+    // All passes needing nearest facilities of a whole block of data (dispatching, sampling) must go through this function,
+    // so that near ties are resolved the same way in all passes.
+    pub(crate) fn nearest_block<D>(&self, data: &[D]) -> Result<Vec<(usize, f32)>, CoresetError>
+    where
+        D: AsRef<[T]> + Sync,
+        T: 'static,
+        Dist: 'static,
+    {
+        let nb_facility = self.ids.len();
+        if nb_facility == 0 {
+            return Err(CoresetError::NotEnoughData("Empty facility".to_string()));
+        }
+        let use_index = matches!(self.index.as_ref(), Some(index) if index.is_built());
+        let kernel = (!use_index).then(|| {
+            CenterBlock::<T, Dist>::new(
+                (0..nb_facility)
+                    .map(|i| self.get_center(i).to_vec())
+                    .collect(),
+            )
+        });
+        let nearest = data
+            .par_chunks(POINT_BLOCK)
+            .map(|chunk| -> Result<Vec<(usize, f32)>, CoresetError> {
+                let nearest: Vec<(usize, f32)> = match kernel.as_ref() {
+                    Some(kernel) => kernel.nearest_block(chunk, &self.distance),
+                    None => chunk
+                        .iter()
+                        .map(|d| self.get_nearest_facility(d.as_ref(), false))
+                        .collect::<Result<Vec<(usize, f32)>, CoresetError>>()?,
+                };
+                if nearest.iter().any(|(facility, _)| *facility == usize::MAX) {
+                    return Err(CoresetError::AlgorithmFailure(
+                        "nearest_block, no finite distance to facilities".to_string(),
+                    ));
+                }
+                Ok(nearest)
+            })
+            .collect::<Result<Vec<Vec<(usize, f32)>>, CoresetError>>()?;
+        Ok(nearest.concat())
+    } // end of nearest_block
+
+    // dispatches (in parallel) a block of data to their nearest facility (given by nearest_block) and adds their weight and cost to facilities.
+    // weight(i) is the weight of data[i].
+    // Each chunk of ACCUMULATION_BLOCK points accumulates weights and costs in its own FacilityAccumulator,
+    // accumulators are merged in chunk order so that sums do not depend on the number of threads.
+    pub(crate) fn dispatch_block<D, W>(&mut self, data: &[D], weight: W) -> Result<(), CoresetError>
+    where
+        D: AsRef<[T]> + Sync,
+        W: Fn(usize) -> f64 + Sync,
+        T: 'static,
+        Dist: 'static,
+    {
+        let nb_facility = self.ids.len();
+        let nearest = self.nearest_block(data)?;
+        let accumulators: Vec<FacilityAccumulator> = nearest
+            .par_chunks(ACCUMULATION_BLOCK)
+            .enumerate()
+            .map(|(ichunk, chunk)| {
+                let mut acc = FacilityAccumulator::new(nb_facility);
+                for (j, (facility, dist)) in chunk.iter().enumerate() {
+                    acc.insert(
+                        *facility,
+                        weight(ichunk * ACCUMULATION_BLOCK + j),
+                        self.cost_fn.eval(*dist),
+                    );
+                }
+                acc
+            })
+            .collect();
+        let accumulator = accumulators
+            .into_iter()
+            .fold(FacilityAccumulator::new(nb_facility), |acc1, acc2| acc1.merge(acc2));
+        self.add_accumulated(&accumulator);
+        Ok(())
+    } // end of dispatch_block
+
     /// returns (total weight, total cost)
     ///
     pub fn compute_weight_cost(&mut self) -> (f64, f64) {
         //
         if self.weight <= 0. {
-            let mut total_weight = 0.;
-            let mut total_cost = 0.;
-            for f in &self.centers {
-                let f_access = f.read();
-                total_cost += f_access.get_cost();
-                total_weight += f_access.get_weight();
-            }
-            self.cost = total_cost;
-            self.weight = total_weight;
+            self.cost = self.get_cost();
+            self.weight = self.get_weight();
         }
         (self.weight, self.cost)
     } // end of compute_cost
@@ -332,21 +666,16 @@ impl<
     /// - level = 0, will log total weight and total cost summed over facilities
     /// - level = 1 it will log weight and cost of each facility.
     pub fn log(&self, level: usize) {
-        let mut total_weight = 0.;
-        let mut total_cost = 0.;
-        for f in &self.centers {
-            let f_access = f.read();
-            if level == 1 {
-                f_access.log();
+        if level == 1 {
+            for f in self.to_facility_vec() {
+                f.log();
             }
-            total_cost += f_access.get_cost();
-            total_weight += f_access.get_weight();
         }
         log::info!(
             "\n\n nb facilities : {} sum of facilities weight : {:.3e}, total cost : {:.3e}",
-            self.centers.len(),
-            total_weight,
-            total_cost
+            self.ids.len(),
+            self.get_weight(),
+            self.get_cost()
         );
     } // end of log
 
@@ -364,36 +693,37 @@ impl<
         data: &[&Vec<T>],
         ids: &[usize],
         weights: Option<&Vec<f32>>,
-    ) -> f64 {
+    ) -> Result<f64, CoresetError>
+    where
+        T: 'static,
+        Dist: 'static,
+    {
         //
         log::info!("in facilities::dispatch_data");
         //
-        if weights.is_some() {
-            assert_eq!(data.len(), weights.unwrap().len());
+        if let Some(w_values) = weights {
+            if data.len() != w_values.len() {
+                return Err(CoresetError::InvalidParameter(format!(
+                    "dispatch_data got {} data and {} weights",
+                    data.len(),
+                    w_values.len()
+                )));
+            }
         }
         // keep facilities but empty facilities keep them at their position
         self.empty();
         //
-        let dispatch_i = |item: usize| {
-            // get facility rank and weight
-            // parallel flag is set to false as we // on data.
-            let (facility, dist) = self.get_nearest_facility(data[item], false).unwrap();
-            let weight = if let Some(w_values) = weights {
-                w_values[item]
+        let weight_i = |item: usize| -> f64 {
+            if let Some(w_values) = weights {
+                w_values[item] as f64
             } else {
                 1.
-            };
-            self.insert_point(facility, dist, weight);
+            }
         };
+        self.dispatch_block(data, weight_i)?;
         //
-        (0..data.len()).into_par_iter().for_each(dispatch_i);
-        //
-        let mut global_cost = 0_f64;
-        let mut total_weight = 0.;
-        for i in 0..self.centers.len() {
-            global_cost += self.centers[i].read().cost;
-            total_weight += self.centers[i].read().weight;
-        }
+        let global_cost = self.get_cost();
+        let total_weight = self.get_weight();
         //
         println!(
             "\n\n total weight collected in facilities : {:.3e}, total cost : {:.3e}",
@@ -401,7 +731,7 @@ impl<
         );
         println!("\n **************************************************************************");
         //
-        global_cost
+        Ok(global_cost)
     } // end of dispatch_data
 
     /// If we have labelled data we can store labels counts affected to each facility.  
@@ -409,66 +739,55 @@ impl<
     /// It computes for each facililty label distribution, entropy of distribution and can be used to check clustering.
     /// **This methods can be called after processing all the data**.     
     /// Returns Vector of label distribution entropy by facility and distribution as a HashMap
+    #[allow(clippy::type_complexity)]
     pub fn dispatch_labels<L: PartialEq + Eq + Copy + std::hash::Hash + Sync + Send>(
         &mut self,
         data: &[Vec<T>],
         labels: &[L],
         weights: Option<&Vec<f32>>,
-    ) -> (Vec<f64>, Vec<HashMap<L, u32>>) {
+    ) -> Result<(Vec<f64>, Vec<HashMap<L, u32>>), CoresetError>
+    where
+        T: 'static,
+        Dist: 'static,
+    {
         //
         log::info!("dispatch_labels");
         //
-        type SafeHashMap<L> = Arc<RwLock<HashMap<L, u32>>>;
-        assert_eq!(data.len(), labels.len());
+        if data.len() != labels.len() {
+            return Err(CoresetError::InvalidParameter(format!(
+                "dispatch_labels got {} data and {} labels",
+                data.len(),
+                labels.len()
+            )));
+        }
         //
-        let nb_facility = self.centers.len();
-        let mut label_distribution = Vec::<SafeHashMap<L>>::with_capacity(nb_facility);
-
-        for i in 0..nb_facility {
-            // reinitialize weights and cost of facilities
-            self.centers[i].write().cost = 0.;
-            self.centers[i].write().weight = 0.;
-            // allocate hashmaps
-            let newmap = HashMap::<L, u32>::with_capacity(data.len() / (2 * nb_facility));
-            label_distribution.push(Arc::new(RwLock::new(newmap)));
+        let nb_facility = self.ids.len();
+        if nb_facility == 0 {
+            return Err(CoresetError::NotEnoughData(
+                "dispatch_labels, no facility".to_string(),
+            ));
         }
+        // reinitialize weights and cost of facilities
+        self.empty();
+        let mut label_distribution: Vec<HashMap<L, u32>> = (0..nb_facility)
+            .map(|_| HashMap::<L, u32>::with_capacity(data.len() / (2 * nb_facility)))
+            .collect();
         //
-        let dispatch_i = |i: usize| {
-            // find facility
-            let (itemf, dist) = self.get_nearest_facility(&data[i], false).unwrap();
+        log::info!("computing global cost and weights");
+        let nearest = self.nearest_block(data)?;
+        for (i, (itemf, dist)) in nearest.into_iter().enumerate() {
             // dispatch data
             let weight = if let Some(w_values) = weights {
                 w_values[itemf] as f64
             } else {
                 1.
             };
-            let cost_incr = dist as f64 * weight;
-            {
-                let mut facility = self.centers[itemf].write();
-                facility.weight += weight;
-                facility.cost += cost_incr;
-            }
+            self.insert_point(itemf, dist, weight);
             // dispatch label
-            {
-                // write lock
-                let mut distribution = label_distribution[itemf].write();
-                if let Some(count) = distribution.get_mut(&labels[i]) {
-                    *count += 1;
-                } else {
-                    distribution.insert(labels[i], 1);
-                }
-            }
-        };
-        //
-        log::info!("computing global cost and weights");
-        (0..data.len()).into_par_iter().for_each(dispatch_i);
-        // recompute globlas cost and weight
-        let mut global_cost = 0_f64;
-        let mut total_weight = 0.;
-        for i in 0..nb_facility {
-            global_cost += self.centers[i].read().cost;
-            total_weight += self.centers[i].read().weight;
+            *label_distribution[itemf].entry(labels[i]).or_insert(0) += 1;
         }
+        let global_cost = self.get_cost();
+        let total_weight = self.get_weight();
         //
         println!(
             "\n\n total weight collected in facilities : {:.3e}, total cost : {:.3e}",
@@ -480,8 +799,7 @@ impl<
         //
         log::info!("computing label distribution entropy");
         let mut entropies = Vec::<f64>::with_capacity(nb_facility);
-        for (i, ld) in label_distribution.iter().enumerate() {
-            let distribution = ld.read();
+        for (i, distribution) in label_distribution.iter().enumerate() {
             let mut mass = 0.0f64;
             let nb_label = distribution.len();
             let mut weights = Vec::<f64>::with_capacity(nb_label);
@@ -496,7 +814,10 @@ impl<
             // checks
             if entropy < -f64::EPSILON * 10. {
                 log::error!("facility {:?} entropy {:.3e}", i, entropy);
-                std::panic!("negative entropy");
+                return Err(CoresetError::AlgorithmFailure(format!(
+                    "negative entropy {:.3e} in facility {}",
+                    entropy, i
+                )));
             } else {
                 entropy = entropy.max(0.);
             }
@@ -505,11 +826,9 @@ impl<
         // Construct global weighted entropy measure
         let mut global_entropy = 0.;
         let mut total_weight = 0.;
-        for (i, f) in self.centers.iter().enumerate() {
-            let facility = f.read();
-            let weight = facility.get_weight();
-            total_weight += weight;
-            global_entropy += weight * entropies[i];
+        for (weight, entropy) in self.weights.iter().zip(entropies.iter()) {
+            total_weight += self.scale * weight;
+            global_entropy += self.scale * weight * entropy;
         }
         global_entropy /= total_weight;
         println!(
@@ -518,12 +837,7 @@ impl<
         );
         println!("\n **************************************************************************");
         //
-        let mut simple_label_distribution = Vec::<HashMap<L, u32>>::with_capacity(nb_facility);
-        for ld in &label_distribution {
-            simple_label_distribution.push(ld.read().clone());
-        }
-        //
-        (entropies, simple_label_distribution)
+        Ok((entropies, label_distribution))
     } // end of dispatch_labels
 
     /// extract facility centers and associated weight for possible other clustering step
@@ -533,18 +847,18 @@ impl<
         let nb_facility = self.len();
         let mut weighted_data = Vec::<(f64, Vec<T>, DataId)>::with_capacity(nb_facility);
         for i in 0..nb_facility {
-            let facility = self.get_facility(i).unwrap().read();
-            let weight = facility.get_weight();
-            let pos = facility.get_position();
-            let id: DataId = facility.get_dataid();
-            weighted_data.push((weight, pos.clone(), id.clone()));
+            weighted_data.push((
+                self.scale * self.weights[i],
+                self.get_center(i).to_vec(),
+                self.ids[i].clone(),
+            ));
         }
         weighted_data
     } // end of into_weighted_data
 
     /// computes distances between facility
     pub fn cross_distances(&self) {
-        let nb_facility = self.centers.len();
+        let nb_facility = self.ids.len();
         let mut distances = Array2::<f32>::zeros((nb_facility, nb_facility));
         let mut q_dist = CKMS::<f32>::new(0.01);
 
@@ -553,12 +867,10 @@ impl<
             return;
         }
         for i in 0..nb_facility {
-            let f_i = self.get_facility(i).unwrap().read();
-            let center_i = f_i.get_position();
+            let center_i = self.get_center(i);
             for j in 0..nb_facility {
                 if i != j {
-                    let f_j = self.get_facility(j).unwrap().read();
-                    distances[[i, j]] = self.distance.eval(center_i, f_j.get_position());
+                    distances[[i, j]] = self.distance.eval(center_i, self.get_center(j));
                     q_dist.insert(distances[[i, j]]);
                 }
             }
@@ -571,3 +883,81 @@ impl<
         log::debug!("\n cross distances : {:.3e}", distances);
     } // end of cross_distances
 } // end of impl block Facilities
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    use rand::distributions::{Distribution, Uniform};
+    use rand_xoshiro::rand_core::SeedableRng;
+    use rand_xoshiro::Xoshiro256PlusPlus;
+
+    fn log_init_test() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn test_accumulated_dispatch() {
+        log_init_test();
+        //
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(5413);
+        let unif = Uniform::<f32>::new(-10., 10.);
+        let data: Vec<Vec<f32>> = (0..10000)
+            .map(|_| (0..5).map(|_| unif.sample(&mut rng)).collect())
+            .collect();
+        let weights: Vec<f64> = (0..data.len()).map(|i| 1. + (i % 7) as f64).collect();
+        //
+        let mut accumulated = Facilities::<usize, f32, DistL2>::new(20, DistL2, CostFunction::KMeans);
+        for (i, d) in data.iter().enumerate().step_by(500) {
+            accumulated.insert(Facility::new(i, d));
+        }
+        let mut sequential = accumulated.clone();
+        // dispatch by chunks merged with FacilityAccumulator
+        accumulated.dispatch_block(&data, |i| weights[i]).unwrap();
+        // point by point insertion
+        let nearest = sequential.nearest_block(&data).unwrap();
+        for (i, (facility, dist)) in nearest.into_iter().enumerate() {
+            sequential.insert_point(facility, dist, weights[i]);
+        }
+        //
+        assert_eq!(accumulated.len(), sequential.len());
+        for f in 0..accumulated.len() {
+            let w_a = accumulated.get_facility_weight(f).unwrap();
+            let w_s = sequential.get_facility_weight(f).unwrap();
+            assert!(w_a > 0.);
+            assert!((w_a - w_s).abs() <= 1.0e-10 * w_s);
+            let c_a = accumulated.get_facility_cost(f).unwrap();
+            let c_s = sequential.get_facility_cost(f).unwrap();
+            assert!((c_a - c_s).abs() <= 1.0e-10 * c_s);
+        }
+        let total_weight: f64 = weights.iter().sum();
+        assert!((accumulated.get_weight() - total_weight).abs() <= 1.0e-10 * total_weight);
+    } // end of test_accumulated_dispatch
+
+    #[test]
+    fn test_lazy_decay() {
+        log_init_test();
+        //
+        let mut facilities = Facilities::<usize, f32, DistL2>::new(3, DistL2, CostFunction::KMedian);
+        for (i, w) in [1000., 10., 0.1].iter().enumerate() {
+            let mut f = Facility::new(i, &[i as f32, 0.]);
+            f.insert(*w, 1.);
+            facilities.insert(f);
+        }
+        let factor = 0.9f64;
+        let mut dropped = 0.;
+        for n in 1..=50 {
+            dropped += facilities.decay(factor, 1.0e-2).0;
+            // points inserted after a decay get their own weight
+            facilities.insert_point(0, 1., 1.);
+            let expected: f64 = (0..n).map(|j| factor.powi(j)).sum::<f64>() + 1000. * factor.powi(n);
+            let w = facilities.get_facility_weight(0).unwrap();
+            assert!((w - expected).abs() <= 1.0e-10 * expected);
+        }
+        // 0.1 * 0.9^n < 0.01 for n >= 22, the facility is dropped once the scale has been halved
+        assert_eq!(facilities.len(), 2);
+        assert!(dropped > 0. && dropped < 1.0e-2);
+        assert!((facilities.get_facility_weight(1).unwrap() - 10. * factor.powi(50)).abs() <= 1.0e-10);
+    } // end of test_lazy_decay
+} // end of mod tests
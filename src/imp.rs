@@ -27,8 +27,10 @@ use rand::distributions::{Distribution, Uniform}; // we could use also greenwald
 
 use anndists::dist::*;
 
+use crate::error::CoresetError;
 use crate::facility::*;
 use crate::scale::*;
+use crate::seeding::stream_rng;
 
 //==================================================================================
 
@@ -45,8 +47,8 @@ pub struct MettuPlaxton<'b, T: Send + Sync, Dist: Distance<T>> {
     j: u32,
     //
     distance: Dist,
-    //
-    rng: Xoshiro256PlusPlus,
+    // seed of random generators
+    seed: u64,
 } // end of struct MettuPlaxton
 
 impl<'b, T: Send + Sync + Clone, Dist: Distance<T>> MettuPlaxton<'b, T, Dist> {
@@ -60,10 +62,21 @@ impl<'b, T: Send + Sync + Clone, Dist: Distance<T>> MettuPlaxton<'b, T, Dist> {
             data,
             j,
             distance,
-            rng: Xoshiro256PlusPlus::seed_from_u64(123),
+            seed: 123,
         }
     }
 
+    /// sets the seed of random generators (default is 123).
+    /// With a given seed results are reproducible, whatever the number of threads.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    /// returns the seed of random generators
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
     pub fn get_nb_data(&self) -> usize {
         self.nb_data
     }
@@ -73,7 +86,11 @@ impl<'b, T: Send + Sync + Clone, Dist: Distance<T>> MettuPlaxton<'b, T, Dist> {
     // return n * N /K
     // running time O(r * n * log(n)).
     // to be called in //
-    fn estimate_ball_cardinal(&self, (ip, point): (usize, &Vec<T>), scale: f32) -> (usize, f32)
+    fn estimate_ball_cardinal(
+        &self,
+        (ip, point): (usize, &Vec<T>),
+        scale: f32,
+    ) -> Result<(usize, f32), CoresetError>
     where
         Dist: Sync,
     {
@@ -81,9 +98,9 @@ impl<'b, T: Send + Sync + Clone, Dist: Distance<T>> MettuPlaxton<'b, T, Dist> {
         let c = 2.;
         let mut j_tmp = self.j;
         // at beginning nb_sample = c * self.j i.e c * log(nb_data) and double at each iteration
-        let mut rng = self.rng.clone();
+        // each point has its own generator so we do not depend on thread scheduling
+        let mut rng = stream_rng(self.seed, ip as u64);
         let mut iter_num = 0;
-        rng.jump();
         let unif = Uniform::<usize>::new(0, self.nb_data);
         let r: f32 = loop {
             let r_test = scale / 2_u32.pow(j_tmp) as f32;
@@ -107,34 +124,47 @@ impl<'b, T: Send + Sync + Clone, Dist: Distance<T>> MettuPlaxton<'b, T, Dist> {
             } else {
                 if j_tmp < 1 {
                     log::error!("error in estimate_ball_cardinal, j_tmp becomes negative");
-                    std::process::exit(1);
+                    return Err(CoresetError::AlgorithmFailure(format!(
+                        "estimate_ball_cardinal did not converge for point {}",
+                        ip
+                    )));
                 }
                 j_tmp -= 1;
                 iter_num += 1;
             }
         };
-        (ip, r)
+        Ok((ip, r))
     }
 
     /// construct centers (facilities) for a given distance and returns allocated facilities (or centers)
     /// The parameter alfa drives the number of facilities created.
-    pub fn construct_centers(&self, alfa: f32) -> Facilities<usize, T, Dist>
+    pub fn construct_centers(&self, alfa: f32) -> Result<Facilities<usize, T, Dist>, CoresetError>
     where
         Dist: Send + Sync + Clone,
     {
         // get scales
-        let q_dist = get_neighborhood_size(1_000_000, self.data, &self.distance);
-        let threshold = q_dist.query(0.999).unwrap().1;
+        let q_dist = get_neighborhood_size(1_000_000, self.data, &self.distance, self.seed);
+        let threshold = q_dist
+            .query(0.999)
+            .ok_or_else(|| {
+                CoresetError::NotEnoughData(
+                    "MettuPlaxton::construct_centers could not estimate distance scale".to_string(),
+                )
+            })?
+            .1;
         log::info!("dist medi : {:.3e}", threshold);
         //
-        let mut facilities: Facilities<usize, T, Dist> =
-            Facilities::<usize, T, Dist>::new(self.j as usize, self.distance.clone());
+        let mut facilities: Facilities<usize, T, Dist> = Facilities::<usize, T, Dist>::new(
+            self.j as usize,
+            self.distance.clone(),
+            CostFunction::KMedian,
+        );
         // estimate radii in //
         let value_to_match = alfa * threshold;
         let mut radii: Vec<(usize, f32)> = (0..self.nb_data)
             .into_par_iter()
             .map(|i| self.estimate_ball_cardinal((i, &self.data[i]), value_to_match))
-            .collect();
+            .collect::<Result<Vec<(usize, f32)>, CoresetError>>()?;
         log::debug!("estimate_ball_cardinal done");
         // sort radii
         radii.sort_unstable_by(|it1, it2| it1.1.partial_cmp(&it2.1).unwrap());
@@ -153,7 +183,7 @@ impl<'b, T: Send + Sync + Clone, Dist: Distance<T>> MettuPlaxton<'b, T, Dist> {
         // let data_unweighted: Vec<&Vec<T>> = self.data.iter().map(|d| d).collect();
         //        facilities.dispatch_data(&data_unweighted, None);
         //
-        facilities
+        Ok(facilities)
     } // end of construct_centers
 
     pub fn compute_distances(&self, facilities: &Facilities<usize, T, Dist>)
@@ -244,7 +274,11 @@ impl<'b, T: Send + Sync + Clone, Dist: Distance<T> + Send + Sync + Clone>
         dists
     } // end of compute_all_dists
 
-    fn compute_ball_radius(&self, alfa: f32, dists: &RwLock<Vec<f32>>) -> f32 {
+    fn compute_ball_radius(
+        &self,
+        alfa: f32,
+        dists: &RwLock<Vec<f32>>,
+    ) -> Result<f32, CoresetError> {
         //
         log::debug!(
             "\n\n WeightedMettuPlaxton compute_ball_radius , coeff value to match {:.3e}",
@@ -307,7 +341,10 @@ impl<'b, T: Send + Sync + Clone, Dist: Distance<T> + Send + Sync + Clone>
             );
             // we can solve for a large r directly
             // radius = value - upper_value;
-            std::panic!("not yet implemented");
+            return Err(CoresetError::AlgorithmFailure(format!(
+                "compute_ball_radius, value {:.3e} too large, not yet implemented",
+                value
+            )));
         } else {
             let mut upper_index = self.get_nb_data() - 1;
             let mut lower_index = 0;
@@ -347,9 +384,12 @@ impl<'b, T: Send + Sync + Clone, Dist: Distance<T> + Send + Sync + Clone>
         }
         //
         if radius > 0. {
-            radius
+            Ok(radius)
         } else {
-            std::panic!("error in compute_ball_radius, radius : {:?}", radius);
+            Err(CoresetError::AlgorithmFailure(format!(
+                "error in compute_ball_radius, radius : {:?}",
+                radius
+            )))
         }
     } // end of compute_ball_radius
 
@@ -361,20 +401,23 @@ impl<'b, T: Send + Sync + Clone, Dist: Distance<T> + Send + Sync + Clone>
         &self,
         alfa: f32,
         dists: &[RwLock<Vec<f32>>],
-    ) -> Facilities<usize, T, Dist> {
+    ) -> Result<Facilities<usize, T, Dist>, CoresetError> {
         //
         log::debug!("in WeightedMettuPlaxton::compute_balls_at_value");
         // for each point compute ball around it of given value
         // corresponds to step 1 of algorithm 2.1 paper [online-median](https://epubs.siam.org/doi/10.1137/S0097539701383443)
         let mut radii: Vec<(usize, f32)> = (0..self.nb_data)
             .into_par_iter()
-            .map(|i| (i, self.compute_ball_radius(alfa, &dists[i])))
-            .collect();
+            .map(|i| Ok((i, self.compute_ball_radius(alfa, &dists[i])?)))
+            .collect::<Result<Vec<(usize, f32)>, CoresetError>>()?;
         // sort by increasing radius (step 2 of algo)
         radii.sort_unstable_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
         // radii[4] corresponds to point of original index radii[4].0 and so distance to its neighbours are given by dists[radii[4].0]
-        let mut facilities: Facilities<usize, T, Dist> =
-            Facilities::<usize, T, Dist>::new(self.j as usize, self.distance.clone());
+        let mut facilities: Facilities<usize, T, Dist> = Facilities::<usize, T, Dist>::new(
+            self.j as usize,
+            self.distance.clone(),
+            CostFunction::KMedian,
+        );
         // we can do step 3 and 4 of algo 2.1
         for p in radii.iter() {
             let matched = facilities.match_point(&self.data[p.0], 2. * p.1, &self.distance);
@@ -392,14 +435,18 @@ impl<'b, T: Send + Sync + Clone, Dist: Distance<T> + Send + Sync + Clone>
             }
         }
         //
-        facilities
+        Ok(facilities)
     } // end of compute_balls_at_value
 
     /// alfa governs the cost of facility creation so the number of facilities we will get.
     /// alfa = 0.5 is a good value.  
     /// To reduce number of facilities produced increase alfa and inversely
     /// reducing alfa increase the number of facilities
-    pub fn construct_centers(&self, alfa: f32) -> Facilities<usize, T, Dist> {
+    pub fn construct_centers(&self, alfa: f32) -> Result<Facilities<usize, T, Dist>, CoresetError>
+    where
+        T: 'static,
+        Dist: 'static,
+    {
         //
         log::info!(
             "in WeightedMettuPlaxton::construct_centers alfa : {:.3e}",
@@ -408,14 +455,14 @@ impl<'b, T: Send + Sync + Clone, Dist: Distance<T> + Send + Sync + Clone>
         //
         let dists: Vec<RwLock<Vec<f32>>> = self.compute_all_dists();
         //
-        let mut facilities = self.compute_balls_at_value(alfa, &dists);
+        let mut facilities = self.compute_balls_at_value(alfa, &dists)?;
         //
         // We explicitly dispatch data to facilities as imp algo do not do it
         let data_unweighted: Vec<&Vec<T>> = self.data.iter().collect();
         let ids = (0..data_unweighted.len()).collect::<Vec<usize>>();
-        facilities.dispatch_data(&data_unweighted, &ids, None);
+        facilities.dispatch_data(&data_unweighted, &ids, None)?;
         //
-        facilities
+        Ok(facilities)
     } // end of construct_centers
 } // end of impl WeightedMettuPlaxton
 
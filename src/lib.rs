@@ -2,16 +2,25 @@ use lazy_static::lazy_static;
 
 pub mod prelude;
 
+pub mod bicriteria;
 pub mod bmor;
+pub mod error;
 pub mod facility;
 pub mod imp;
+pub mod kernel;
 mod scale;
+mod seeding;
+pub mod pivot;
 pub mod sensitivity;
+pub mod streaming;
+pub mod window;
 
 pub mod discrete;
+pub mod evaluation;
 pub mod makeiter;
 
 pub mod clustercore;
+pub mod clustermodel;
 pub mod wkmedian;
 
 lazy_static! {
@@ -9,9 +9,59 @@
 /// Any algorithm (such as [Coreset1](super::sensitivity::Coreset1)) needing an iterator and more than one pass on data to run must use this trait.  
 ///   
 /// The crate hnsw_rs will provide such an iterator on data stored in hnsw database.
+///
+/// Weighted data (for example deduplicated vectors with their counts) are given as items (DataId, weight, Vec\<T\>),
+/// see [Coreset1::make_weighted_coreset()](super::sensitivity::Coreset1::make_weighted_coreset()).
+/// The adaptors [UnitWeight] and [WithoutWeight] convert between unweighted and weighted items.
 pub trait MakeIter {
     /// an item of data iterated over
     type Item;
     /// how to get an iterator
     fn makeiter(&self) -> impl Iterator<Item = Self::Item>;
 }
+
+/// An adaptor giving a unit weight to items of a [MakeIter] producing (DataId, Vec\<T\>),
+/// so that unweighted data can be processed by algorithms expecting weighted items (DataId, weight, Vec\<T\>).
+pub struct UnitWeight<'a, M> {
+    inner: &'a M,
+}
+
+impl<'a, M> UnitWeight<'a, M> {
+    pub fn new(inner: &'a M) -> Self {
+        UnitWeight { inner }
+    }
+}
+
+impl<DataId, T, M> MakeIter for UnitWeight<'_, M>
+where
+    M: MakeIter<Item = (DataId, Vec<T>)>,
+{
+    type Item = (DataId, f64, Vec<T>);
+    //
+    fn makeiter(&self) -> impl Iterator<Item = Self::Item> {
+        self.inner.makeiter().map(|(id, data)| (id, 1., data))
+    }
+}
+
+/// An adaptor dropping weights of items of a [MakeIter] producing (DataId, weight, Vec\<T\>).  
+/// Useful when only data vectors are needed, for example to retrieve data vectors of cluster centers.
+pub struct WithoutWeight<'a, M> {
+    inner: &'a M,
+}
+
+impl<'a, M> WithoutWeight<'a, M> {
+    pub fn new(inner: &'a M) -> Self {
+        WithoutWeight { inner }
+    }
+}
+
+impl<DataId, T, M> MakeIter for WithoutWeight<'_, M>
+where
+    M: MakeIter<Item = (DataId, f64, Vec<T>)>,
+{
+    type Item = (DataId, Vec<T>);
+    //
+    fn makeiter(&self) -> impl Iterator<Item = Self::Item> {
+        self.inner.makeiter().map(|(id, _, data)| (id, data))
+    }
+}
@@ -0,0 +1,203 @@
+//! A pivot index (LAESA like) to accelerate the search of the nearest facility.
+//!
+//! The first facilities inserted serve as pivots and the index stores the distances of each facility to the pivots.
+//! For a query point q, facility f and pivot p the triangle inequality gives `|d(q,p) - d(f,p)| <= d(q,f)`
+//! so once the distances of q to the pivots are known, most facilities are discarded without a distance computation.
+//! The search is exact (up to rounding errors) but **needs a distance satisfying the triangle inequality** (L1, L2, Hamming ...).
+//! Facilities can be inserted one at a time as in [Bmor](crate::bmor::Bmor), only their distances to pivots are computed.
+//!
+//! The index is built only when the number of facilities reaches a minimal size, below that a linear scan is cheaper.
+
+use serde::{Deserialize, Serialize};
+
+/// parameters of the pivot index
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PivotIndexArg {
+    /// number of pivots. Each search costs at least nb_pivots distance computations.
+    nb_pivots: usize,
+    /// the index is used only if there are at least min_size facilities
+    min_size: usize,
+}
+
+impl PivotIndexArg {
+    /// min_size is raised to nb_pivots if it is less.
+    pub fn new(nb_pivots: usize, min_size: usize) -> Self {
+        let nb_pivots = nb_pivots.max(1);
+        PivotIndexArg {
+            nb_pivots,
+            min_size: min_size.max(nb_pivots),
+        }
+    }
+
+    /// returns the number of pivots
+    pub fn get_nb_pivots(&self) -> usize {
+        self.nb_pivots
+    }
+
+    /// returns the minimal number of facilities for which the index is used
+    pub fn get_min_size(&self) -> usize {
+        self.min_size
+    }
+}
+
+impl Default for PivotIndexArg {
+    /// 16 pivots, index used from 128 facilities
+    fn default() -> Self {
+        PivotIndexArg {
+            nb_pivots: 16,
+            min_size: 128,
+        }
+    }
+}
+
+//==================================================================================
+
+// Facilities are identified by their rank of insertion, pivots are the nb_pivots first facilities.
+#[derive(Clone, Debug)]
+pub(crate) struct PivotIndex {
+    arg: PivotIndexArg,
+    // number of facilities indexed, 0 as long as the index is not built
+    nb_indexed: usize,
+    // pivot_dists[i * nb_pivots + j] is the distance between facility i and pivot j
+    pivot_dists: Vec<f32>,
+}
+
+impl PivotIndex {
+    pub(crate) fn new(arg: PivotIndexArg) -> Self {
+        PivotIndex {
+            arg,
+            nb_indexed: 0,
+            pivot_dists: Vec::new(),
+        }
+    }
+
+    pub(crate) fn get_arg(&self) -> PivotIndexArg {
+        self.arg
+    }
+
+    // returns true if there are enough facilities for the index to be used
+    pub(crate) fn is_built(&self) -> bool {
+        self.nb_indexed > 0
+    }
+
+    // to call when all facilities are deleted
+    pub(crate) fn clear(&mut self) {
+        self.nb_indexed = 0;
+        self.pivot_dists.clear();
+    }
+
+    // to call after each facility insertion, nb_facility is the number of facilities after insertion.
+    // dist(i,j) returns the distance between facilities i and j.
+    // The index is built when nb_facility reaches min_size, then each insertion costs nb_pivots distances.
+    pub(crate) fn update<F>(&mut self, nb_facility: usize, dist: F)
+    where
+        F: Fn(usize, usize) -> f32,
+    {
+        let nb_pivots = self.arg.nb_pivots;
+        if nb_facility < self.arg.min_size.max(nb_pivots) {
+            return;
+        }
+        self.pivot_dists
+            .reserve((nb_facility - self.nb_indexed) * nb_pivots);
+        for i in self.nb_indexed..nb_facility {
+            for j in 0..nb_pivots {
+                let d = if i == j { 0. } else { dist(i, j) };
+                self.pivot_dists.push(d);
+            }
+        }
+        if self.nb_indexed == 0 {
+            log::debug!("PivotIndex built with {} facilities", nb_facility);
+        }
+        self.nb_indexed = nb_facility;
+    } // end of update
+
+    // returns rank of nearest facility and distance to it, (usize::MAX, f32::INFINITY) if no finite distance is found.
+    // dist_to(i) returns the distance of the query point to facility i.
+    // Ties are resolved as in a linear scan, by lowest rank.
+    pub(crate) fn search<F>(&self, dist_to: F) -> (usize, f32)
+    where
+        F: Fn(usize) -> f32,
+    {
+        let nb_pivots = self.arg.nb_pivots;
+        let to_pivots: Vec<f32> = (0..nb_pivots).map(&dist_to).collect();
+        let mut rank_f = usize::MAX;
+        let mut dist = f32::INFINITY;
+        for (j, d) in to_pivots.iter().enumerate() {
+            if *d < dist {
+                dist = *d;
+                rank_f = j;
+            }
+        }
+        for i in nb_pivots..self.nb_indexed {
+            let row = &self.pivot_dists[i * nb_pivots..(i + 1) * nb_pivots];
+            // lower bound of distance to facility i
+            let lower = row
+                .iter()
+                .zip(to_pivots.iter())
+                .fold(0.0f32, |acc, (d_f, d_q)| acc.max((d_f - d_q).abs()));
+            if lower < dist {
+                let d = dist_to(i);
+                if d < dist {
+                    dist = d;
+                    rank_f = i;
+                }
+            }
+        }
+        (rank_f, dist)
+    } // end of search
+} // end of impl PivotIndex
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::facility::*;
+
+    use anndists::dist::*;
+    use rand::distributions::{Distribution, Uniform};
+    use rand_xoshiro::rand_core::SeedableRng;
+    use rand_xoshiro::Xoshiro256PlusPlus;
+
+    fn log_init_test() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn test_pivot_search() {
+        log_init_test();
+        //
+        let dim = 20;
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(4537);
+        let unif = Uniform::<f32>::new(-10., 10.);
+        let mut generate = |n: usize| -> Vec<Vec<f32>> {
+            (0..n)
+                .map(|_| (0..dim).map(|_| unif.sample(&mut rng)).collect())
+                .collect()
+        };
+        let centers = generate(500);
+        let queries = generate(1000);
+        //
+        let mut linear = Facilities::<usize, f32, DistL2>::new(500, DistL2, CostFunction::KMedian);
+        let mut indexed = Facilities::<usize, f32, DistL2>::new(500, DistL2, CostFunction::KMedian);
+        // index set before insertions, built on the fly
+        indexed.set_index(Some(PivotIndexArg::new(8, 100)));
+        for (i, c) in centers.iter().enumerate() {
+            linear.insert(Facility::new(i, c));
+            indexed.insert(Facility::new(i, c));
+        }
+        for q in &queries {
+            let (r1, d1) = linear.get_nearest_facility(q, false).unwrap();
+            let (r2, d2) = indexed.get_nearest_facility(q, false).unwrap();
+            assert_eq!(r1, r2);
+            assert_eq!(d1, d2);
+        }
+        // index set on existing facilities
+        linear.set_index(Some(PivotIndexArg::default()));
+        assert_eq!(linear.get_index_arg(), Some(PivotIndexArg::default()));
+        for q in &queries {
+            let (r1, _) = linear.get_nearest_facility(q, false).unwrap();
+            let (r2, _) = indexed.get_nearest_facility(q, false).unwrap();
+            assert_eq!(r1, r2);
+        }
+    } // end of test_pivot_search
+} // end of mod tests
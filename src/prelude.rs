@@ -2,14 +2,28 @@
 
 pub use crate::imp::*;
 
+pub use crate::bicriteria::*;
+
 pub use crate::bmor::*;
 
 pub use crate::clustercore::*;
 
+pub use crate::clustermodel::*;
+
+pub use crate::error::*;
+
+pub use crate::evaluation::*;
+
+pub use crate::facility::CostFunction;
+
 pub use crate::makeiter::*;
 
 pub use crate::sensitivity::*;
 
+pub use crate::streaming::*;
+
+pub use crate::window::*;
+
 pub use crate::wkmedian::*;
 
 #[derive(Copy, Clone)]
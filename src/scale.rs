@@ -6,6 +6,8 @@ use rayon::prelude::*;
 use rand_xoshiro::rand_core::SeedableRng;
 use rand_xoshiro::Xoshiro256PlusPlus;
 
+use crate::seeding::stream_rng;
+
 use quantiles::ckms::CKMS;
 use rand::distributions::{Distribution, Uniform}; // we could use also greenwald_khanna
 
@@ -49,10 +51,12 @@ where
 }
 
 /// sample neighborhood radii.
+/// The sampling for point i uses its own generator derived from seed and i, so the result does not depend on the number of threads.
 pub(crate) fn get_neighborhood_size<T, Dist>(
     _nbsample_arg: usize,
     data: &[Vec<T>],
     distance: &Dist,
+    seed: u64,
 ) -> CKMS<f32>
 where
     Dist: Distance<T> + Sync,
@@ -65,7 +69,7 @@ where
     // We use log(nbpoint) as default neighborhood size
     let nb_sample: usize = 1.max(nbdata.ilog2() as usize);
     let explore = |i: usize| -> (f32, f32) {
-        let mut rng = Xoshiro256PlusPlus::seed_from_u64(14547 + i as u64).clone();
+        let mut rng = stream_rng(seed, i as u64);
         let mut dvec: Vec<f32> = (0..nb_sample)
             .map(|_| {
                 loop {
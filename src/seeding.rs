@@ -0,0 +1,19 @@
+//! derivation of random generators from a user seed
+//!
+//! A component receiving a seed derives the seeds of its sub-components with [derive_seed],
+//! and a parallel loop builds one generator per item with [stream_rng] from the item rank, never from the thread.
+//! So results only depend on the seed and not on the number of threads used by rayon.
+
+use rand_xoshiro::rand_core::{RngCore, SeedableRng};
+use rand_xoshiro::{SplitMix64, Xoshiro256PlusPlus};
+
+/// returns a seed for stream of rank stream, decorrelated from seed and from other streams.
+pub(crate) fn derive_seed(seed: u64, stream: u64) -> u64 {
+    let mut mixer = SplitMix64::seed_from_u64(seed ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    mixer.next_u64()
+}
+
+/// returns the random generator of stream of rank stream.
+pub(crate) fn stream_rng(seed: u64, stream: u64) -> Xoshiro256PlusPlus {
+    Xoshiro256PlusPlus::seed_from_u64(derive_seed(seed, stream))
+}
@@ -6,77 +6,73 @@
 
 // We need 2 passes on data as Bmor algorithm can merge data when rescaling cost and facility number so data id are not conserved.
 
-use anyhow::*;
+use crate::error::CoresetError;
 
 use rayon::prelude::*;
-use std::sync::Arc;
 
-use dashmap::DashMap;
 use std::collections::hash_map; // for key() method
 use std::collections::HashMap;
 use std::hash::Hash;
 
 use ndarray::{Array1, Array2};
 use std::io::Write;
+use std::path::Path;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use rand::Rng;
+use rand_distr::{Binomial, Distribution};
 use rand_xoshiro::rand_core::SeedableRng;
 use rand_xoshiro::Xoshiro256PlusPlus;
 
 use cpu_time::ProcessTime;
 use std::time::{Duration, SystemTime};
 
+use crate::bicriteria::*;
 use crate::bmor::*;
+use crate::clustercore::BmorArg;
 use crate::discrete::DiscreteProba;
 use crate::facility::*;
 use crate::makeiter::*;
+use crate::pivot::PivotIndexArg;
+use crate::seeding::derive_seed;
 
 use anndists::dist::*;
 
-#[derive(Copy, Clone, Debug)]
-// a sampled point
-struct Point<DataId> {
-    pub(self) id: DataId,
-    pub(self) _rank: usize,
-    pub(self) proba: f64,
-}
+//======================================================================================================
 
-struct PointSampler<DataId> {
-    /// proba distribution over points
-    proba: DiscreteProba<f64>,
-    // first usize (key) is an index in self.proba, second DataId (value) is data_id in data (possibly an index).
-    w_index: HashMap<usize, DataId>,
+/// Format of a coreset file, see [CoreSet::save()] and [CoreSet::load()].  
+/// The format is deduced from file extension when loading.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// compact binary format (bincode), extension .bin
+    Bincode,
+    /// a line by point : DataId, weight and data vector coordinates, extension .csv.  
+    /// **The cost function is not stored** see [CoreSet::set_cost_function()]
+    Csv,
+    /// extension .json
+    Json,
 }
 
-impl<DataId: Clone> PointSampler<DataId> {
-    fn new(weights: &Vec<f64>, w_index: HashMap<usize, DataId>) -> Self {
-        PointSampler {
-            proba: DiscreteProba::new(weights),
-            w_index,
+impl DumpFormat {
+    /// returns the format associated to extension of path (bin or bincode, csv, json)
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "bin" | "bincode" => Some(DumpFormat::Bincode),
+            "csv" => Some(DumpFormat::Csv),
+            "json" => Some(DumpFormat::Json),
+            _ => None,
         }
     }
+} // end of impl DumpFormat
 
-    /// sample a random data point, returning its Id (possibly an index in some array)
-    fn sample<R>(&self, rng: &mut R) -> Point<DataId>
-    where
-        R: Rng,
-    {
-        let (rank, proba) = self.proba.sample(rng);
-        let opt_id = self.w_index.get(&rank);
-        if opt_id.is_none() {
-            log::error!("no id for rank sampled : {}", rank);
-            std::panic!();
-        }
-        let id = opt_id.cloned().unwrap();
-        Point {
-            id,
-            _rank: rank,
-            proba,
-        }
-    } // end of sample
-} // end of PointSampler
-
-//======================================================================================================
+// what is serialized by CoreSet::save, points are (id, weight, data vector)
+#[derive(Serialize, Deserialize)]
+struct CoreSetDump<I, V> {
+    cost_fn: CostFunction,
+    points: Vec<(I, f64, V)>,
+}
 
 // How do we represent a coreset: For now minimal
 /// Structure representing Coreset obtained with coreset construction algorithms
@@ -88,6 +84,8 @@ pub struct CoreSet<DataId, T: Send + Sync + Clone, Dist: Distance<T> + Clone + S
     datas_wid: Option<Vec<(DataId, Vec<T>)>>,
     //
     distance: Dist,
+    // cost function used to build the coreset
+    cost_fn: CostFunction,
 } // end of Coreset
 
 impl<DataId, T: Send + Sync + Clone, Dist> CoreSet<DataId, T, Dist>
@@ -95,17 +93,50 @@ where
     DataId: Eq + Hash + Send + Sync + Clone + std::fmt::Debug,
     Dist: Distance<T> + Clone + Sync + Send,
 {
+    /// - core_w : weight of each point of the coreset
+    /// - datas_wid : optional data vectors. If given, there must be exactly one vector for each id of core_w.
     pub fn new(
         core_w: HashMap<DataId, f64>,
         datas_wid: Option<Vec<(DataId, Vec<T>)>>,
         distance: Dist,
-    ) -> CoreSet<DataId, T, Dist> {
-        assert_eq!(core_w.len(), datas_wid.as_ref().unwrap().len());
-        CoreSet {
+        cost_fn: CostFunction,
+    ) -> Result<CoreSet<DataId, T, Dist>, CoresetError> {
+        if let Some(datas) = datas_wid.as_ref() {
+            if datas.len() != core_w.len() {
+                return Err(CoresetError::InvalidParameter(format!(
+                    "CoreSet::new got {} weights and {} data vectors",
+                    core_w.len(),
+                    datas.len()
+                )));
+            }
+            if let Some((id, _)) = datas.iter().find(|(id, _)| !core_w.contains_key(id)) {
+                return Err(CoresetError::MissingData(format!(
+                    "CoreSet::new, no weight for id {:?}",
+                    id
+                )));
+            }
+        }
+        Ok(CoreSet {
             id_weight_map: core_w,
             datas_wid,
             distance,
-        }
+            cost_fn,
+        })
+    }
+
+    /// returns the distance used
+    pub fn get_distance(&self) -> &Dist {
+        &self.distance
+    }
+
+    /// returns the cost function the coreset was built for
+    pub fn get_cost_function(&self) -> CostFunction {
+        self.cost_fn
+    }
+
+    /// sets the cost function. Useful after reloading a coreset from a csv file which does not store it.
+    pub fn set_cost_function(&mut self, cost_fn: CostFunction) {
+        self.cost_fn = cost_fn;
     }
 
     /// returns number of different points
@@ -139,37 +170,13 @@ where
 
     //
 
-    /// for a coreset point of rank r returns id and data vector.
-    pub(crate) fn get_point_by_rank(&self, r: usize) -> Option<(DataId, &Vec<T>)> {
-        let res = match self.datas_wid.as_ref() {
-            Some(v) => {
-                if r < v.len() {
-                    Some((v[r].0.clone(), &v[r].1))
-                } else {
-                    log::error!(
-                        "get_point_by_rank could not find data vector r : {} size : {}",
-                        r,
-                        v.len()
-                    );
-                    std::panic!("get_point_by_rank could not find data vector r");
-                }
-            }
-            None => {
-                log::error!("get_point_by_rank could not find data vector r : {}", r);
-                std::panic!("get_point_by_rank could not find data vector r");
-            }
-        };
-        //
-        res
-    } // end of get_point
-
-    //
-
     /// dump info in a csv file name coreset.csv
     /// Csv file contains:
     /// - DataId of coreset point
     /// - weight of coreset point
-    pub fn dump(&self) -> anyhow::Result<usize> {
+    ///
+    /// To save a coreset that can be reloaded, see [save](Self::save())
+    pub fn dump(&self) -> Result<usize, CoresetError> {
         let mut name = String::from("coreset");
         name.push_str(".csv");
         let file = std::fs::File::create(&name)?;
@@ -180,33 +187,269 @@ where
             writeln!(bufw, "{:?},{:.3e}\n", id, weight)?;
             nb_record += 1;
         }
-        bufw.flush().unwrap();
+        bufw.flush()?;
         //
         println!(
             " coreset dumped in file : {}, nb_record {}",
             name, nb_record
         );
         //
-        assert_eq!(nb_record, self.get_nb_points());
-        //
         Ok(nb_record)
     } // end of dump
 
     //
 
+    /// union of coresets. A union of coresets of disjoint data sets is a coreset of the union of data sets.  
+    /// If the same id is found in many coresets with the same data vector (the point was sampled in many coresets) weights are added,
+    /// an id associated to different data vectors is an error.  
+    /// The coresets must have the same cost function. The distance of the first coreset is used.
+    pub fn union(coresets: &[CoreSet<DataId, T, Dist>]) -> Result<Self, CoresetError>
+    where
+        T: PartialEq,
+    {
+        if coresets.is_empty() {
+            return Err(CoresetError::NotEnoughData(
+                "CoreSet::union, no coreset to merge".to_string(),
+            ));
+        }
+        let cost_fn = coresets[0].get_cost_function();
+        let nb_points: usize = coresets.iter().map(|c| c.get_nb_points()).sum();
+        let mut id_weight_map = HashMap::<DataId, f64>::with_capacity(nb_points);
+        let mut datas_wid = Vec::<(DataId, Vec<T>)>::with_capacity(nb_points);
+        // to find data vector of an id already inserted
+        let mut id_rank = HashMap::<DataId, usize>::with_capacity(nb_points);
+        for coreset in coresets {
+            if coreset.get_cost_function() != cost_fn {
+                return Err(CoresetError::Incompatible(
+                    "CoreSet::union, coresets do not have the same cost function".to_string(),
+                ));
+            }
+            let points = coreset.get_data_points().ok_or_else(|| {
+                CoresetError::MissingData("CoreSet::union, coreset has no data vectors".to_string())
+            })?;
+            for (id, data) in points {
+                let weight = coreset.id_weight_map[id];
+                match id_rank.get(id) {
+                    Some(rank) => {
+                        if datas_wid[*rank].1 != *data {
+                            return Err(CoresetError::DuplicateId(format!(
+                                "CoreSet::union, id {:?} has different data vectors",
+                                id
+                            )));
+                        }
+                        *id_weight_map.get_mut(id).unwrap() += weight;
+                    }
+                    None => {
+                        id_rank.insert(id.clone(), datas_wid.len());
+                        id_weight_map.insert(id.clone(), weight);
+                        datas_wid.push((id.clone(), data.clone()));
+                    }
+                }
+            }
+        }
+        log::info!(
+            "CoreSet::union of {} coresets, nb points : {}, nb collisions : {}",
+            coresets.len(),
+            datas_wid.len(),
+            nb_points - datas_wid.len()
+        );
+        CoreSet::new(
+            id_weight_map,
+            Some(datas_wid),
+            coresets[0].distance.clone(),
+            cost_fn,
+        )
+    } // end of union
+
+    /// reduces the size of a (weighted) coreset by running sensitivity sampling again on it. Sensitivities are computed
+    /// with respect to facilities given by [Bmor] run on the weighted coreset points.
+    /// - target_size : number of points sampled. The returned coreset can be smaller as points sampled many times are merged.
+    /// - k : the number of clusters Bmor is run with. It gets about k * log(coreset size) facilities, so k should be
+    ///   the number of clusters the compressed coreset is meant for (as in [Coreset1::new()]).
+    ///   A smaller k gives fewer facilities and sensitivities driven by the cost of points more than by the size of their facility.
+    /// - bmor_arg : beta and gamma of [Bmor], ([BmorArg::default()] gives the standard 2. and 2.).
+    ///   The number of expected data is not used, it is the coreset size.
+    /// - seed : seed of the random generator, the same seed gives the same result.
+    ///
+    /// If target_size is greater than the coreset size, the coreset is returned unchanged.
+    pub fn compress(&self, target_size: usize, k: usize, bmor_arg: BmorArg, seed: u64) -> Result<Self, CoresetError>
+    where
+        T: 'static,
+        Dist: 'static,
+    {
+        let points = self.get_data_points().ok_or_else(|| {
+            CoresetError::MissingData("CoreSet::compress, coreset has no data vectors".to_string())
+        })?;
+        let weighted_data: Vec<(f64, Vec<T>, DataId)> = points
+            .iter()
+            .map(|(id, data)| (self.id_weight_map[id], data.clone(), id.clone()))
+            .collect();
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(seed);
+        let sampled = weighted_sensitivity_sampling(
+            &weighted_data,
+            (k, bmor_arg.get_beta(), bmor_arg.get_gamma()),
+            &self.distance,
+            self.cost_fn,
+            target_size,
+            &mut rng,
+        )?;
+        //
+        let mut id_weight_map = HashMap::<DataId, f64>::with_capacity(sampled.len());
+        let mut datas_wid = Vec::<(DataId, Vec<T>)>::with_capacity(sampled.len());
+        for (weight, data, id) in sampled {
+            id_weight_map.insert(id.clone(), weight);
+            datas_wid.push((id, data));
+        }
+        log::info!(
+            "CoreSet::compress from {} points to {} points",
+            self.get_nb_points(),
+            datas_wid.len()
+        );
+        CoreSet::new(
+            id_weight_map,
+            Some(datas_wid),
+            self.distance.clone(),
+            self.cost_fn,
+        )
+    } // end of compress
+
+    //
+
+    /// saves ids, weights and data vectors of the coreset in file path, in given format.
+    /// Returns the number of points saved.
+    /// The extension of path should match the format (see [DumpFormat]) so that [load](Self::load()) can deduce it.
+    pub fn save<P: AsRef<Path>>(&self, path: P, format: DumpFormat) -> Result<usize, CoresetError>
+    where
+        DataId: Serialize,
+        T: Serialize,
+    {
+        let path = path.as_ref();
+        if DumpFormat::from_path(path) != Some(format) {
+            log::warn!(
+                "CoreSet::save extension of {:?} does not match format {:?}, load will fail",
+                path,
+                format
+            );
+        }
+        let datas_wid = self.datas_wid.as_ref().ok_or_else(|| {
+            CoresetError::MissingData("CoreSet::save, coreset has no data vectors".to_string())
+        })?;
+        let points: Vec<(&DataId, f64, &Vec<T>)> = datas_wid
+            .iter()
+            .map(|(id, data)| (id, self.id_weight_map[id], data))
+            .collect();
+        let nb_record = points.len();
+        //
+        let file = std::fs::File::create(path)?;
+        let bufw = std::io::BufWriter::new(file);
+        match format {
+            DumpFormat::Bincode => {
+                let dump = CoreSetDump {
+                    cost_fn: self.cost_fn,
+                    points,
+                };
+                bincode::serialize_into(bufw, &dump)?;
+            }
+            DumpFormat::Json => {
+                let dump = CoreSetDump {
+                    cost_fn: self.cost_fn,
+                    points,
+                };
+                serde_json::to_writer(bufw, &dump)?;
+            }
+            DumpFormat::Csv => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .flexible(true)
+                    .from_writer(bufw);
+                for point in &points {
+                    writer.serialize(point)?;
+                }
+                writer.flush()?;
+            }
+        }
+        log::info!(
+            "coreset saved in file : {:?}, format : {:?}, nb_record {}",
+            path,
+            format,
+            nb_record
+        );
+        Ok(nb_record)
+    } // end of save
+
+    /// reloads a coreset saved by [save](Self::save()), the format is deduced from file extension.
+    pub fn load<P: AsRef<Path>>(path: P, distance: Dist) -> Result<Self, CoresetError>
+    where
+        DataId: DeserializeOwned,
+        T: DeserializeOwned,
+    {
+        let path = path.as_ref();
+        let format = DumpFormat::from_path(path).ok_or_else(|| {
+            CoresetError::Format(format!(
+                "CoreSet::load cannot deduce format from extension of {:?}",
+                path
+            ))
+        })?;
+        let file = std::fs::File::open(path)?;
+        let bufr = std::io::BufReader::new(file);
+        let dump: CoreSetDump<DataId, Vec<T>> = match format {
+            DumpFormat::Bincode => bincode::deserialize_from(bufr)?,
+            DumpFormat::Json => serde_json::from_reader(bufr)?,
+            DumpFormat::Csv => {
+                log::warn!("CoreSet::load, csv file does not store cost function, using default");
+                let mut reader = csv::ReaderBuilder::new()
+                    .has_headers(false)
+                    .flexible(true)
+                    .from_reader(bufr);
+                let points = reader
+                    .deserialize::<(DataId, f64, Vec<T>)>()
+                    .collect::<Result<Vec<_>, _>>()?;
+                CoreSetDump {
+                    cost_fn: CostFunction::default(),
+                    points,
+                }
+            }
+        };
+        //
+        let mut id_weight_map = HashMap::<DataId, f64>::with_capacity(dump.points.len());
+        let mut datas_wid = Vec::<(DataId, Vec<T>)>::with_capacity(dump.points.len());
+        for (id, weight, data) in dump.points {
+            if id_weight_map.insert(id.clone(), weight).is_some() {
+                return Err(CoresetError::DuplicateId(format!(
+                    "CoreSet::load, id {:?} found twice",
+                    id
+                )));
+            }
+            datas_wid.push((id, data));
+        }
+        log::info!(
+            "coreset loaded from file : {:?}, nb points : {}",
+            path,
+            datas_wid.len()
+        );
+        CoreSet::new(
+            id_weight_map,
+            Some(datas_wid),
+            distance,
+            dump.cost_fn,
+        )
+    } // end of load
+
+    //
+
     /// computes matrix distances between points.
     /// line i of matrix corresponds to id in the Vec\<usize\> i'th element of first argument of the option returned
     ///
     pub fn compute_distances(&self) -> Option<(Vec<DataId>, Array2<f32>)> {
-        let nbpoints = self.get_nb_points();
+        let points = self.datas_wid.as_ref()?;
+        let nbpoints = points.len();
         // allocates to zero rows. We will computes rows in //
         let mut distances = Array2::<f32>::zeros((0, nbpoints));
         //
-        let compute_row = |i| -> Array1<f32> {
+        let compute_row = |i: usize| -> Array1<f32> {
             let mut row_i = Array1::zeros(nbpoints);
-            let (_, p_i) = self.get_point_by_rank(i).unwrap();
-            for j in 0..nbpoints {
-                let p_j = self.get_point_by_rank(j).unwrap().1;
+            let p_i = &points[i].1;
+            for (j, (_, p_j)) in points.iter().enumerate() {
                 if j != i {
                     row_i[j] = self.distance.eval(p_i, p_j);
                 }
@@ -225,59 +468,101 @@ where
             distances.push_row(v.into()).unwrap();
         }
         //
-        let ids: Vec<DataId> = self
-            .datas_wid
-            .as_ref()
-            .unwrap()
-            .iter()
-            .map(|(id, _)| (*id).clone())
-            .collect();
+        let ids: Vec<DataId> = points.iter().map(|(id, _)| (*id).clone()).collect();
         //
         Some((ids, distances))
     } // end of compute_distances
 } // end of impl Coreset
 
 /// This structure provides Algorithm1 Braverman and al 2022, implementing Sensitivity sampling.  
-/// It relies on [bmor](super::bmor) algorithm.  
-/// The algorithm needs  one streaming pass and one sampling pass.  
-/// The data must be given consistent id across the 2 passes. (The data id can be its rank in the stream in which case the 2 pass
-/// must process data in the same order)
-pub struct Coreset1<DataId, T: Send + Sync + Clone, Dist: Distance<T> + Clone + Sync + Send> {
-    //
-    phase: usize,
+/// It relies on a bicriteria approximation (see [BicriteriaApprox]), by default the [bmor](super::bmor) algorithm.  
+/// The algorithm needs one pass to get the approximation, one pass dispatching data to facilities and one sampling pass.  
+/// The data must be given in the same order across the passes. (The data id can be its rank in the stream)
+///
+/// Memory needed (besides the approximation) is bounded by the number of facilities and the coreset size, not by the data size.
+pub struct Coreset1<
+    DataId,
+    T: Send + Sync + Clone,
+    Dist: Distance<T> + Clone + Sync + Send,
+    Approx = Bmor<DataId, T, Dist>,
+> {
     /// keep track of number of data processed
     nb_data: usize,
-    /// bmor instance
-    bmor: Bmor<DataId, T, Dist>,
+    /// the (alfa, beta) approximation providing facilities
+    approx: Approx,
+    /// cost function used in sensitivity computation
+    cost_fn: CostFunction,
     /// facilities with respect to which we compute sensitivity (or importance)
     facilities: Option<Facilities<DataId, T, Dist>>,
-    // A map to store facility associated to each point. Needed in sensitivity
-    point_facility_map: Option<Arc<DashMap<DataId, PointMap>>>,
+    /// seed of the sampling random generator
+    seed: u64,
+    /// pivot index set on facilities for the dispatch passes
+    index_arg: Option<PivotIndexArg>,
 } // end of Coreset1
 
 // s estimation
 
-impl<DataId, T: Send + Sync + Clone, Dist> Coreset1<DataId, T, Dist>
+impl<DataId, T: Send + Sync + Clone + 'static, Dist> Coreset1<DataId, T, Dist>
 where
-    Dist: Distance<T> + Clone + Sync + Send,
+    Dist: Distance<T> + Clone + Sync + Send + 'static,
     DataId: Eq + Hash + std::fmt::Debug + Clone + Send + Sync,
 {
+    /// Coreset construction using [Bmor] as bicriteria approximation.  
     /// nbdata_expected : the (expected) data size
     /// k  : The expected number of facilities
     /// distance : the metric to use.
     /// beta and gamma are arguments of Bmor
-    pub fn new(k: usize, nbdata_expected: usize, beta: f64, gamma: f64, distance: Dist) -> Self {
-        let bmor = Bmor::new(k, nbdata_expected, beta, gamma, distance);
-        let phase = 0usize;
-        //
+    /// cost_fn : the cost function of the clustering problem (k-median, k-means, ...)
+    pub fn new(
+        k: usize,
+        nbdata_expected: usize,
+        beta: f64,
+        gamma: f64,
+        distance: Dist,
+        cost_fn: CostFunction,
+    ) -> Self {
+        let bmor = Bmor::new(k, nbdata_expected, beta, gamma, distance, cost_fn);
+        Coreset1::with_approx(bmor, cost_fn)
+    } // end of new
+} // end of impl block Coreset1 with Bmor
+
+impl<DataId, T: Send + Sync + Clone + 'static, Dist, Approx> Coreset1<DataId, T, Dist, Approx>
+where
+    Dist: Distance<T> + Clone + Sync + Send + 'static,
+    DataId: Eq + Hash + std::fmt::Debug + Clone + Send + Sync,
+    Approx: BicriteriaApprox<DataId, T, Dist>,
+{
+    /// approx : the bicriteria approximation giving facilities with respect to which sensitivities are computed.  
+    /// cost_fn : the cost function of the clustering problem.
+    pub fn with_approx(approx: Approx, cost_fn: CostFunction) -> Self {
         Coreset1 {
-            phase,
             nb_data: 0,
-            bmor,
+            approx,
+            cost_fn,
             facilities: None,
-            point_facility_map: None,
+            seed: 14537,
+            index_arg: None,
         }
-    } // end of new
+    } // end of with_approx
+
+    /// sets the seed of the sampling random generator (default is 14537) and the seed of the approximation, derived from it.  
+    /// It must be called before [make_coreset()](Self::make_coreset()). With a given seed the coreset is reproducible,
+    /// whatever the number of threads.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.approx.set_seed(derive_seed(seed, 1));
+    }
+
+    /// returns the seed of the sampling random generator
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// sets a pivot index (see [pivot](crate::pivot)) on facilities given by the approximation, used in the passes
+    /// dispatching data to facilities. Default is None, the index of the approximation facilities (if any) is kept.
+    pub fn set_facility_index(&mut self, arg: Option<PivotIndexArg>) {
+        self.index_arg = arg;
+    }
 
     /// The main interface to the algorithm.  
     ///
@@ -291,130 +576,116 @@ where
         &mut self,
         iter_generator: &IterGenerator,
         fraction: f64,
-    ) -> anyhow::Result<CoreSet<DataId, T, Dist>>
+    ) -> Result<CoreSet<DataId, T, Dist>, CoresetError>
     where
         IterGenerator: MakeIter<Item = (DataId, Vec<T>)>,
         DataId: Eq + Hash + std::fmt::Debug + Send + Sync,
+    {
+        self.make_weighted_coreset(&UnitWeight::new(iter_generator), fraction)
+    } // end of make_coreset
+
+    /// Same as [make_coreset()](Self::make_coreset()) but for weighted data.  
+    /// The iterator yields items (DataId, weight, data), for example deduplicated vectors with their multiplicity.
+    /// A point of weight w is treated as w copies of itself: the weight enters facility weights and costs and the sampling probability,
+    /// so the weights of the coreset sum (in expectation) to the total weight of the data.  
+    /// The size of the coreset is around fraction * number of items.
+    pub fn make_weighted_coreset<IterGenerator>(
+        &mut self,
+        iter_generator: &IterGenerator,
+        fraction: f64,
+    ) -> Result<CoreSet<DataId, T, Dist>, CoresetError>
+    where
+        IterGenerator: MakeIter<Item = (DataId, f64, Vec<T>)>,
+        DataId: Eq + Hash + std::fmt::Debug + Send + Sync,
     {
         //
         let cpu_start = ProcessTime::now();
         let sys_now = SystemTime::now();
         //
-        // first bmor pass to get a list of facilities
-        let iter = iter_generator.makeiter();
-        let res1 = self.process_data_iterator(iter);
-        if res1.is_err() {
-            log::error!("first pass failed");
-            return Err(anyhow!("first pass failed"));
+        // first pass to get a list of facilities
+        let mut facilities = self
+            .approx
+            .build_facilities(iter_generator)
+            .inspect_err(|_| log::error!("first pass failed"))?;
+        if facilities.is_empty() {
+            return Err(CoresetError::NotEnoughData(
+                "Coreset1 first pass did not produce facilities".to_string(),
+            ));
         }
-        // In phase 2, we have facilities, we empty them and redispatch data and store the facility of each point
-        log::debug!("end of first pass, second pass to compute point facility map");
-        self.facilities.as_mut().unwrap().empty();
-        self.init_facility_map(self.nb_data);
-        let iter = iter_generator.makeiter();
-        let res2 = self.process_data_iterator(iter);
-        if res2.is_err() {
-            log::error!("seond pass failed");
-            return Err(anyhow!("second pass failed"));
+        log::debug!(
+            "end of first pass, got nb facilities : {:?}",
+            facilities.len()
+        );
+        // In phase 2, we have facilities, we empty them and redispatch data to get weight and cost of each facility
+        log::debug!("second pass to compute facility weights and costs");
+        facilities.empty();
+        facilities.set_cost_function(self.cost_fn);
+        if self.index_arg.is_some() {
+            facilities.set_index(self.index_arg);
         }
-        // now we have info for building sampling distribution in self.p_facility_map
+        self.facilities = Some(facilities);
+        self.nb_data = 0;
+        let iter = iter_generator.makeiter();
+        self.process_data_iterator(iter)
+            .inspect_err(|_| log::error!("second pass failed"))?;
+        // now we have facility totals, we can compute probabilities on the fly, sample and retrieve data in a last pass
         log::debug!("end of second pass, doing sensitivity and sampling computations");
-        let sampler = self.build_sampling_distribution();
-        // we can now get rid of p_facility_map
-        self.point_facility_map = None;
-        //
-        let id_weight_map = self.sample_coreset(&sampler, fraction);
-        let distance = self.facilities.as_ref().unwrap().get_distance();
-        // now we have ids and weights of points in coreset but we need a last pass to store the data associated to id!
-        let id_data_map = self.retrieve_corepoints_by_id(&id_weight_map, iter_generator);
+        let (id_weight_map, id_data_map) = self.sample_coreset(iter_generator, fraction)?;
+        let facilities = self.facilities.as_ref().ok_or_else(|| {
+            CoresetError::NotComputed("Coreset1::make_weighted_coreset, no facilities".to_string())
+        })?;
+        let distance = facilities.get_distance();
+        let cost_fn = facilities.get_cost_function();
         //
         let cpu_time: Duration = cpu_start.elapsed();
         println!(
-            "\n Coreset1::make_coreset  sys time(ms) {:?} cpu time(ms) {:?}",
+            "\n Coreset1::make_weighted_coreset  sys time(ms) {:?} cpu time(ms) {:?}",
             sys_now.elapsed().unwrap().as_millis(),
             cpu_time.as_millis()
         );
         //
-        Ok(CoreSet::new(
+        CoreSet::new(
             id_weight_map,
             Some(id_data_map),
             distance.clone(),
-        ))
-    } // end of make_coreset
-
-    // we need to retrieve the data vector corresponding to the id of coreset points
-    // Careful , the data are stored in the order they are found by iter_generator and not in the order of the HashMap
-    fn retrieve_corepoints_by_id<IterGenerator>(
-        &self,
-        id_weight_map: &HashMap<DataId, f64>,
-        iter_generator: &IterGenerator,
-    ) -> Vec<(DataId, Vec<T>)>
-    where
-        IterGenerator: MakeIter<Item = (DataId, Vec<T>)>,
-        DataId: Eq + Hash + std::fmt::Debug,
-    {
-        //
-        let iter = iter_generator.makeiter();
-        //
-        let nbpoints = id_weight_map.len();
-        let mut datas_wid: Vec<(DataId, Vec<T>)> = Vec::with_capacity(nbpoints);
-        for (id, data) in iter {
-            // do we need the data of this id
-            if id_weight_map.contains_key(&id) {
-                datas_wid.push((id, data));
-            }
-        }
-        // temporary what is the id not found (bug fixed !)
-        if datas_wid.len() < nbpoints {
-            let mut set = indexmap::IndexSet::with_capacity(nbpoints + 100);
-            for (id, _) in &datas_wid {
-                set.insert(id);
-            }
-            for id in id_weight_map.keys() {
-                // do we have id in set
-                if set.get(id).is_none() {
-                    log::error!(" we do not have id : {:?} in set", id);
-                }
-            }
-        }
-        //
-        assert_eq!(datas_wid.len(), nbpoints);
-        //
-        datas_wid
-    } // end of retrieve_corepoints_by_id
+            cost_fn,
+        )
+    } // end of make_weighted_coreset
 
     /// This function takes an iterator on all data and process (with buffering and parallelizing) them via calling *process_data()* , consuming the iterator
     fn process_data_iterator(
         &mut self,
-        mut iter: impl Iterator<Item = (DataId, Vec<T>)>,
-    ) -> anyhow::Result<()> {
+        mut iter: impl Iterator<Item = (DataId, f64, Vec<T>)>,
+    ) -> Result<(), CoresetError> {
         // TODO: adapt bufsize to memory/cpu
         let bufsize: usize = 50000;
         let mut datas = Vec::<Vec<T>>::with_capacity(bufsize);
+        let mut weights = Vec::<f64>::with_capacity(bufsize);
         let mut ids = Vec::<DataId>::with_capacity(bufsize);
         //
         loop {
             let data_opt = iter.next();
             match data_opt {
-                Some((id, data)) => {
+                Some((id, weight, data)) => {
                     // insert
                     datas.push(data);
+                    weights.push(weight);
                     ids.push(id);
                     if datas.len() == bufsize {
                         // process
-                        let res = self.process_data(&datas, &ids);
-                        assert!(res.is_ok());
+                        self.process_data(&datas, &weights, &ids)?;
                         // empty buffer
                         datas.clear();
+                        weights.clear();
                         ids.clear();
                     }
                 }
                 _ => {
                     if !datas.is_empty() {
-                        let res = self.process_data(&datas, &ids);
-                        assert!(res.is_ok());
+                        self.process_data(&datas, &weights, &ids)?;
                         // empty buffer
                         datas.clear();
+                        weights.clear();
                         ids.clear();
                     }
                     break;
@@ -422,173 +693,540 @@ where
             } // end match
         } // end loop
           // DO NOT FORGET calling end_pass
-        self.end_pass();
-        //
-        Ok(())
+        self.end_pass()
     } // end of process_data_iterator
 
-    /// treat unweighted data.
+    /// treat weighted data (unweighted data have unit weights).
     /// This functions provides a buffered, parallelized internal implementation of process_data_iterator.   
-    /// At end of first round on data [end_pass](Self::end_pass()) must be called before running the second pass on data
-    fn process_data(&mut self, data: &[Vec<T>], data_id: &[DataId]) -> anyhow::Result<()> {
-        //
-        if self.phase == 0 {
-            let _ = self.bmor.process_data(data, data_id).unwrap();
-            self.bmor.log();
-            self.nb_data += data.len();
-            Ok(())
-        } else {
-            let facilities_ref = self.facilities.as_ref().unwrap();
-            //
-            let dispatch_i = |item: usize| {
-                // get facility rank and weight
-                let (facility, dist) = facilities_ref
-                    .get_nearest_facility(&data[item], false)
-                    .unwrap();
-                let weight = 1.;
-                self.facilities
-                    .as_ref()
-                    .unwrap()
-                    .insert_point(facility, dist, weight);
-                match &self.point_facility_map {
-                    Some(f_map) => {
-                        let p_map = PointMap::new(facility, dist, 1.);
-                        let res = f_map.insert(data_id[item].clone(), p_map);
-                        if res.is_some() {
-                            log::error!("data_id {:?} is already present error", data_id[item]);
-                            std::panic!();
-                        }
-                        log::trace!(
-                            "inserted PointMap for data_id {:?} in facility map",
-                            data_id[item]
-                        );
-                    }
-                    _ => {
-                        std::panic!("no facility_map allocated, should not happen")
-                    }
-                }
-            };
-            // now we insert into pointmap if necessary
-            (0..data.len()).into_par_iter().for_each(dispatch_i);
-            //
-            Ok(())
-        }
+    /// It dispatches data to their nearest facility, accumulating weight and cost of facilities.
+    fn process_data(
+        &mut self,
+        data: &[Vec<T>],
+        weights: &[f64],
+        _data_id: &[DataId],
+    ) -> Result<(), CoresetError> {
+        //
+        self.nb_data += data.len();
+        let facilities_ref = self.facilities.as_mut().ok_or_else(|| {
+            CoresetError::NotComputed("Coreset1::process_data, no facilities".to_string())
+        })?;
+        //
+        // each chunk of data accumulates facility weights and costs on its own, facilities are updated once per block
+        facilities_ref.dispatch_block(data, |item| weights[item])?;
+        //
+        Ok(())
     } // end of process_data
 
-    /// declares end of streaming data first pass, and construct coreset by sampling
-    fn end_pass(&mut self) {
-        //
-        match self.phase {
-            0 => {
-                // we retrieve facilities
-                self.phase += 1;
-                // we have facilities
-                let contraction = false;
-                self.facilities = Some(self.bmor.end_data(contraction));
-                log::debug!("end of first pass, processed nb data : {:?}", self.nb_data);
-            }
-
-            1 => {
-                // we have every thing to compute sensitivity and do sampling
-                log::debug!("end of second pass, doing sensitivity and sampling computations");
-                let _ = self.facilities.as_mut().unwrap().compute_weight_cost();
-                self.facilities.as_ref().unwrap().log(0);
-            }
-
-            _ => {
-                std::panic!("should not occurr");
-            }
-        }
-    } //
-
-    /// returns a reference to (optional) facility_map
-    pub fn get_facility_map(&self) -> Option<&Arc<DashMap<DataId, PointMap>>> {
-        return self.point_facility_map.as_ref();
-    }
+    /// declares end of dispatching pass, before sensitivity computations
+    fn end_pass(&mut self) -> Result<(), CoresetError> {
+        // we have every thing to compute sensitivity and do sampling
+        log::debug!(
+            "end of second pass, processed nb data : {:?}, doing sensitivity and sampling computations",
+            self.nb_data
+        );
+        let facilities = self.facilities.as_mut().ok_or_else(|| {
+            CoresetError::NotComputed("Coreset1::end_pass, no facilities".to_string())
+        })?;
+        let _ = facilities.compute_weight_cost();
+        facilities.log(0);
+        Ok(())
+    } // end of end_pass
 
-    /// returns the number of data (know after end of first pass)
+    /// returns the number of data (know after end of dispatching pass)
     pub fn get_nb_data(&self) -> usize {
         self.nb_data
     }
 
-    // if needed (as in the case of sensitivity computations) allocate
-    fn init_facility_map(&mut self, capacity: usize) {
-        self.point_facility_map = Some(Arc::new(DashMap::with_capacity(capacity)))
-    }
-
-    //  initialize point_weights : Option<WeightedIndex<usize>>,
-    // The function receive an iterator over data.
-    // from rust 1.75 such an iterator can be obtained with the user implementing a trait providing the iterator on data
-    fn build_sampling_distribution(&mut self) -> PointSampler<DataId> {
-        // The 2 denonimators used in line 3 of algo 1 for Coreset in Braverman
-        let facilities_ref = self.facilities.as_ref().unwrap();
-        // denominator used in line 3  of algo 1 for Coreset in Braverman
-        let global_cost = facilities_ref.get_cost();
-        log::debug!(
-            "build_sampling_distribution got global cost : {:.3e}",
-            global_cost
-        );
-        let p_facility_map_ref = self.point_facility_map.as_ref().unwrap();
-        let nb_facilities = facilities_ref.len(); // This is |B| in line 3  of algo 1 for Coreset in Braverman
-        let mut cumul_proba = 0.;
-        // the fields to build PointSampler
-        let mut p_weights = Vec::<f64>::with_capacity(self.nb_data);
-        let mut w_index = HashMap::<usize, DataId>::with_capacity(self.nb_data);
-        // we iter on reviously built p_facility_map_ref
-        let pmap_iter = p_facility_map_ref.iter();
-        for iter_ref in pmap_iter {
-            let (dataid, pointmap) = iter_ref.pair();
-            //
-            let mut proba = pointmap.get_dist() as f64 * pointmap.get_weight() as f64 / global_cost;
-            // get weight of facility of point corresponding to data_id
-            let f_weight = facilities_ref.get_facility_weight(pointmap.get_facility());
-            proba += pointmap.get_weight() as f64 / (nb_facilities as f64 * f_weight.unwrap());
-            proba *= 0.5;
-            // now we can update
-            assert!(proba > 0.);
-            p_weights.push(proba);
-            // key is rank, value is id!!!
-            w_index.insert(p_weights.len() - 1, dataid.clone());
-            // for a check
-            cumul_proba += proba;
-        }
-        log::debug!("cumul_proba : {:.5e}", cumul_proba);
-        assert!((1. - cumul_proba).abs() < 1.0E-5);
-        PointSampler::new(&p_weights, w_index)
-    } // end of build_sampling_distribution
-
-    // build and init field coreset
-    fn sample_coreset(
-        &mut self,
-        sampler: &PointSampler<DataId>,
+    // The sampling pass.
+    // nb_sample points are drawn with replacement with probability (line 3 of algo 1 for Coreset in Braverman)
+    //     w(p) * 0.5 * (cost(p, B) / cost(B) + 1 / (|B| * w(B(p))))
+    // (w(p) is the weight of p, 1. for unweighted data) which can be computed for each point as soon as facility weights and global cost are known.
+    // The number of times each point is drawn follows a multinomial law, we sample it sequentially in data order with :
+    //     count(p_i) ~ Binomial(nb_sample - sum_{j<i} count(p_j), proba(p_i) / (1 - sum_{j<i} proba(p_j)))
+    // so we only need to store sampled points and we retrieve their data vector in the same pass.
+    #[allow(clippy::type_complexity)]
+    fn sample_coreset<IterGenerator>(
+        &self,
+        iter_generator: &IterGenerator,
         rate: f64,
-    ) -> HashMap<DataId, f64> {
+    ) -> Result<(HashMap<DataId, f64>, Vec<(DataId, Vec<T>)>), CoresetError>
+    where
+        IterGenerator: MakeIter<Item = (DataId, f64, Vec<T>)>,
+    {
         //
         log::info!("sample_coreset fraction : {:.2e}", rate);
         let nb_sample = (rate * self.nb_data as f64) as usize;
         //
-        let mut coreset = HashMap::<DataId, f64>::with_capacity(2 * nb_sample);
+        let facilities_ref = self.facilities.as_ref().ok_or_else(|| {
+            CoresetError::NotComputed("Coreset1::sample_coreset, no facilities".to_string())
+        })?;
+        // denominator used in line 3  of algo 1 for Coreset in Braverman
+        let global_cost = facilities_ref.get_cost();
+        log::debug!("sample_coreset got global cost : {:.3e}", global_cost);
+        let nb_facilities = facilities_ref.len(); // This is |B| in line 3  of algo 1 for Coreset in Braverman
+        let cost_fn = facilities_ref.get_cost_function();
+        let f_weights: Vec<f64> = (0..nb_facilities)
+            .map(|f| facilities_ref.get_facility_weight(f))
+            .collect::<Result<Vec<f64>, _>>()?;
+        // facility and dist are given by the nearest search of the dispatching pass, so facility has a positive weight
+        let proba_i = |weight: f64, (facility, dist): (usize, f32)| -> Result<f64, CoresetError> {
+            if weight <= 0. {
+                return Ok(0.);
+            }
+            if f_weights[facility] <= 0. {
+                return Err(CoresetError::AlgorithmFailure(format!(
+                    "sample_coreset, point dispatched to facility {} of null weight",
+                    facility
+                )));
+            }
+            let mut proba = weight / (nb_facilities as f64 * f_weights[facility]);
+            if global_cost > 0. {
+                proba = 0.5 * (proba + weight * cost_fn.eval(dist) as f64 / global_cost);
+            }
+            Ok(proba)
+        };
         //
-        let mut rng = Xoshiro256PlusPlus::seed_from_u64(14537);
+        let mut coreset = HashMap::<DataId, f64>::with_capacity(nb_sample);
+        let mut datas_wid = Vec::<(DataId, Vec<T>)>::with_capacity(nb_sample);
         //
-        for _ in 0..nb_sample {
-            let point = sampler.sample(&mut rng);
-            let weight = 1. / (point.proba * nb_sample as f64);
-            let id_weights = coreset.get_mut(&point.id);
-            match id_weights {
-                Some(old_weight) => {
-                    *old_weight += weight;
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(self.seed);
+        let mut nb_remaining = nb_sample as u64;
+        let mut proba_remaining = 1.0f64;
+        // TODO: adapt bufsize to memory/cpu
+        let bufsize: usize = 50000;
+        let mut buffer = Vec::<(DataId, f64, Vec<T>)>::with_capacity(bufsize);
+        let mut iter = iter_generator.makeiter();
+        while nb_remaining > 0 {
+            buffer.extend(iter.by_ref().take(bufsize));
+            if buffer.is_empty() {
+                break;
+            }
+            let points: Vec<&Vec<T>> = buffer.iter().map(|(_, _, data)| data).collect();
+            let nearest = facilities_ref.nearest_block(&points)?;
+            let probas: Vec<f64> = buffer
+                .par_iter()
+                .zip(nearest.into_par_iter())
+                .map(|((_, weight, _), nearest)| proba_i(*weight, nearest))
+                .collect::<Result<Vec<f64>, _>>()?;
+            for ((id, weight, data), proba) in buffer.drain(..).zip(probas) {
+                if nb_remaining == 0 {
+                    break;
                 }
-                None => {
-                    coreset.insert(point.id, weight);
+                let count = if proba >= proba_remaining {
+                    nb_remaining
+                } else {
+                    Binomial::new(nb_remaining, proba / proba_remaining)
+                        .map_err(|e| {
+                            CoresetError::AlgorithmFailure(format!(
+                                "sample_coreset, bad probability {:.3e} : {}",
+                                proba / proba_remaining,
+                                e
+                            ))
+                        })?
+                        .sample(&mut rng)
+                };
+                proba_remaining -= proba;
+                if count == 0 {
+                    continue;
+                }
+                nb_remaining -= count;
+                let weight = count as f64 * weight / (proba * nb_sample as f64);
+                if coreset.insert(id.clone(), weight).is_some() {
+                    log::error!("data_id {:?} is already present error", id);
+                    return Err(CoresetError::DuplicateId(format!(
+                        "data_id {:?} is present twice in data",
+                        id
+                    )));
                 }
+                datas_wid.push((id, data));
             }
-        } // end of for
-          //
+            buffer.clear();
+        }
+        if nb_remaining > 0 {
+            log::warn!(
+                "sample_coreset, {} samples not drawn (rounding errors on probabilities)",
+                nb_remaining
+            );
+        }
+        //
         log::info!(
             "sensitivity::sample_coreset coreset nb points :  {}",
             coreset.len()
         );
-        coreset
+        Ok((coreset, datas_wid))
     } // end of sample_coreset
 } // end of impl block
+
+//========================================================================================
+
+/// Sensitivity sampling (as in Algorithm 1 of Braverman et al.) of weighted points held in memory.  
+/// The (alfa, beta) approximation is obtained by running [Bmor] on the weighted points, then nb_sample points are sampled
+/// with replacement with probability
+/// 0.5 * (w(p) * cost(p, B) / cost(B) + w(p) / (|B| * w(B(p))))   
+/// where cost(p, B) is d(p, B)^z as given by cost_fn.
+/// and given the weight w(p) / (nb_sample * proba(p)). Points sampled many times have their weights added.
+///
+/// Data are given as triplets (weight, data vector, data id) as returned by [Facilities::into_weighted_data()].  
+/// If nb_sample is greater than the number of points, data are returned unchanged.
+pub(crate) fn weighted_sensitivity_sampling<DataId, T, Dist, R>(
+    data: &[(f64, Vec<T>, DataId)],
+    (k, beta, gamma): (usize, f64, f64),
+    distance: &Dist,
+    cost_fn: CostFunction,
+    nb_sample: usize,
+    rng: &mut R,
+) -> Result<Vec<(f64, Vec<T>, DataId)>, CoresetError>
+where
+    DataId: std::fmt::Debug + Clone + Send + Sync,
+    T: Send + Sync + Clone,
+    Dist: Distance<T> + Clone + Send + Sync,
+    R: Rng,
+{
+    //
+    if data.len() <= nb_sample {
+        return Ok(data.to_vec());
+    }
+    // get facilities
+    let mut bmor =
+        Bmor::<DataId, T, Dist>::new(k, data.len(), beta, gamma, distance.clone(), cost_fn);
+    bmor.set_seed(rng.gen());
+    let weighted_ref_data: Vec<(f64, &Vec<T>, DataId)> =
+        data.iter().map(|d| (d.0, &d.1, d.2.clone())).collect();
+    bmor.process_weighted_data(&weighted_ref_data)?;
+    let facilities = bmor.end_data(false)?;
+    let nb_facilities = facilities.len();
+    // dispatch points to facilities
+    let point_map: Vec<(usize, f32)> = data
+        .par_iter()
+        .map(|d| {
+            let (f, dist) = facilities.get_nearest_facility(&d.1, false)?;
+            Ok((f, cost_fn.eval(dist)))
+        })
+        .collect::<Result<Vec<(usize, f32)>, CoresetError>>()?;
+    let mut f_weights = vec![0.0f64; nb_facilities];
+    let mut global_cost = 0.0f64;
+    for (i, (f, cost)) in point_map.iter().enumerate() {
+        f_weights[*f] += data[i].0;
+        global_cost += data[i].0 * *cost as f64;
+    }
+    // build sampling distribution
+    let probas: Vec<f64> = point_map
+        .iter()
+        .enumerate()
+        .map(|(i, (f, cost))| {
+            let weight = data[i].0;
+            // a facility gets a null weight only if all its points have null weight, they are never sampled
+            if weight <= 0. || f_weights[*f] <= 0. {
+                return 0.;
+            }
+            let mut proba = weight / (nb_facilities as f64 * f_weights[*f]);
+            if global_cost > 0. {
+                proba = 0.5 * (proba + weight * *cost as f64 / global_cost);
+            }
+            proba
+        })
+        .collect();
+    let sampler = DiscreteProba::new(&probas)?;
+    // sample. DiscreteProba slots begin at 1
+    let mut sampled = HashMap::<usize, f64>::with_capacity(nb_sample);
+    for _ in 0..nb_sample {
+        let (slot, proba) = sampler.sample(rng);
+        let weight = data[slot - 1].0 / (proba * nb_sample as f64);
+        *sampled.entry(slot - 1).or_insert(0.) += weight;
+    }
+    // keep data order, so that results do not depend on HashMap iteration order
+    let mut sampled: Vec<(usize, f64)> = sampled.into_iter().collect();
+    sampled.sort_unstable_by_key(|(i, _)| *i);
+    Ok(sampled
+        .into_iter()
+        .map(|(i, w)| (w, data[i].1.clone(), data[i].2.clone()))
+        .collect())
+} // end of weighted_sensitivity_sampling
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn log_init_test() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn test_coreset_save_load() {
+        log_init_test();
+        //
+        let datas_wid: Vec<(usize, Vec<f32>)> = (0..100)
+            .map(|i| (3 * i, (0..5).map(|j| (i * j) as f32 / 7.).collect()))
+            .collect();
+        let id_weight_map: HashMap<usize, f64> = datas_wid
+            .iter()
+            .map(|(id, _)| (*id, 1. + *id as f64 / 11.))
+            .collect();
+        let coreset = CoreSet::new(id_weight_map, Some(datas_wid), DistL2, CostFunction::KMeans).unwrap();
+        //
+        let dir = std::env::temp_dir();
+        for (name, format) in [
+            ("coreset_test.bin", DumpFormat::Bincode),
+            ("coreset_test.csv", DumpFormat::Csv),
+            ("coreset_test.json", DumpFormat::Json),
+        ] {
+            let path = dir.join(format!("{}-{}", std::process::id(), name));
+            let nb_record = coreset.save(&path, format).unwrap();
+            assert_eq!(nb_record, coreset.get_nb_points());
+            let reloaded = CoreSet::<usize, f32, DistL2>::load(&path, DistL2).unwrap();
+            let _ = std::fs::remove_file(&path);
+            //
+            assert_eq!(reloaded.get_nb_points(), coreset.get_nb_points());
+            for (id, w) in coreset.get_items() {
+                assert_eq!(reloaded.get_weight(id), Some(*w));
+            }
+            assert_eq!(reloaded.get_data_points(), coreset.get_data_points());
+            if format != DumpFormat::Csv {
+                assert_eq!(reloaded.get_cost_function(), CostFunction::KMeans);
+            }
+        }
+    } // end of test_coreset_save_load
+
+    #[test]
+    fn test_coreset_union_compress() {
+        log_init_test();
+        //
+        let nbdata = 5000;
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(1454691);
+        let unif = rand::distributions::Uniform::<f32>::new(-20., 20.);
+        let centers: Vec<Vec<f32>> = (0..5)
+            .map(|_| (0..10).map(|_| unif.sample(&mut rng)).collect())
+            .collect();
+        let normal = rand_distr::Normal::<f32>::new(0., 1.).unwrap();
+        let datas: Vec<Vec<f32>> = (0..nbdata)
+            .map(|i| {
+                centers[i % 5]
+                    .iter()
+                    .map(|x| x + normal.sample(&mut rng))
+                    .collect()
+            })
+            .collect();
+        // 2 unit weight coresets sharing ids 2000..2500
+        let make_coreset = |range: std::ops::Range<usize>| {
+            let datas_wid: Vec<(usize, Vec<f32>)> = range.map(|i| (i, datas[i].clone())).collect();
+            let id_weight_map = datas_wid.iter().map(|(id, _)| (*id, 1.)).collect();
+            CoreSet::new(
+                id_weight_map,
+                Some(datas_wid),
+                DistL2,
+                CostFunction::KMedian,
+            )
+            .unwrap()
+        };
+        let coreset1 = make_coreset(0..2500);
+        let coreset2 = make_coreset(2000..nbdata);
+        let union = CoreSet::union(&[coreset1, coreset2]).unwrap();
+        assert_eq!(union.get_nb_points(), nbdata);
+        assert_eq!(union.get_weight(&2100), Some(2.));
+        let total_weight: f64 = union.get_items().map(|(_, w)| *w).sum();
+        assert_eq!(total_weight, 5500.);
+        // an id with 2 different data vectors is an error
+        let mut conflicting = make_coreset(0..10);
+        conflicting.datas_wid.as_mut().unwrap()[0].1[0] += 1.;
+        assert!(CoreSet::union(&[union, conflicting]).is_err());
+        //
+        let coreset = make_coreset(0..nbdata);
+        let compressed = coreset.compress(500, 5, BmorArg::default(), 14537).unwrap();
+        let total_weight: f64 = compressed.get_items().map(|(_, w)| *w).sum();
+        log::info!(
+            "compressed coreset size : {}, total weight : {:.3e}",
+            compressed.get_nb_points(),
+            total_weight
+        );
+        assert!(compressed.get_nb_points() <= 500);
+        assert!((total_weight / nbdata as f64 - 1.).abs() < 0.1);
+        // Bmor parameters are taken from BmorArg, its number of expected data is not used
+        let compressed = coreset.compress(500, 5, BmorArg::new(0, 3., 1.5), 14537).unwrap();
+        let total_weight: f64 = compressed.get_items().map(|(_, w)| *w).sum();
+        assert!(compressed.get_nb_points() <= 500);
+        assert!((total_weight / nbdata as f64 - 1.).abs() < 0.1);
+    } // end of test_coreset_union_compress
+
+    struct WeightedDataForIter {
+        datas: Vec<(usize, f64, Vec<f32>)>,
+    }
+
+    impl MakeIter for WeightedDataForIter {
+        type Item = (usize, f64, Vec<f32>);
+        //
+        fn makeiter(&self) -> impl Iterator<Item = Self::Item> {
+            self.datas.iter().cloned()
+        }
+    }
+
+    #[test]
+    fn test_coreset_weighted() {
+        log_init_test();
+        // deduplicated data : 2000 distinct points around 4 centers with counts between 1 and 10
+        let nbdata = 2000;
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(4576231);
+        let unif = rand::distributions::Uniform::<f32>::new(-20., 20.);
+        let centers: Vec<Vec<f32>> = (0..4)
+            .map(|_| (0..10).map(|_| unif.sample(&mut rng)).collect())
+            .collect();
+        let normal = rand_distr::Normal::<f32>::new(0., 1.).unwrap();
+        let datas: Vec<(usize, f64, Vec<f32>)> = (0..nbdata)
+            .map(|i| {
+                let count = rng.gen_range(1..=10) as f64;
+                let data = centers[i % 4]
+                    .iter()
+                    .map(|x| x + normal.sample(&mut rng))
+                    .collect();
+                (i, count, data)
+            })
+            .collect();
+        let total_count: f64 = datas.iter().map(|d| d.1).sum();
+        let iter_producer = WeightedDataForIter { datas };
+        //
+        let mut coreset1 = Coreset1::new(4, nbdata, 2., 2., DistL2, CostFunction::KMeans);
+        let coreset = coreset1.make_weighted_coreset(&iter_producer, 0.1).unwrap();
+        let total_weight: f64 = coreset.get_items().map(|(_, w)| *w).sum();
+        log::info!(
+            "weighted coreset size : {}, total weight : {:.3e}, total count : {:.3e}",
+            coreset.get_nb_points(),
+            total_weight,
+            total_count
+        );
+        assert_eq!(coreset1.get_nb_data(), nbdata);
+        assert!((total_weight / total_count - 1.).abs() < 0.1);
+    } // end of test_coreset_weighted
+
+    // one pass sequential binomial sampling : total weight estimates the number of data, the number of distinct points
+    // sampled is near nb_sample (only points of high sensitivity are sampled more than once), a seed gives one coreset
+    #[test]
+    fn test_sample_coreset() {
+        log_init_test();
+        //
+        let nbdata = 20000;
+        let fraction = 0.05;
+        let nb_sample = (fraction * nbdata as f64) as usize;
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(7341);
+        let unif = rand::distributions::Uniform::<f32>::new(-20., 20.);
+        let centers: Vec<Vec<f32>> = (0..5)
+            .map(|_| (0..10).map(|_| unif.sample(&mut rng)).collect())
+            .collect();
+        let normal = rand_distr::Normal::<f32>::new(0., 1.).unwrap();
+        let datas: Vec<(usize, f64, Vec<f32>)> = (0..nbdata)
+            .map(|i| {
+                let data = centers[i % 5]
+                    .iter()
+                    .map(|x| x + normal.sample(&mut rng))
+                    .collect();
+                (i, 1., data)
+            })
+            .collect();
+        let iter_producer = WeightedDataForIter { datas };
+        //
+        let make = |seed: u64| -> Vec<(usize, f64)> {
+            let mut coreset1 = Coreset1::new(5, nbdata, 2., 2., DistL2, CostFunction::KMedian);
+            coreset1.set_seed(seed);
+            let coreset = coreset1.make_weighted_coreset(&iter_producer, fraction).unwrap();
+            let mut items: Vec<(usize, f64)> = coreset.get_items().map(|(id, w)| (*id, *w)).collect();
+            items.sort_unstable_by_key(|(id, _)| *id);
+            items
+        };
+        let mut sizes = Vec::<usize>::new();
+        for seed in [1457, 2459, 3463] {
+            let items = make(seed);
+            let total_weight: f64 = items.iter().map(|(_, w)| *w).sum();
+            log::info!(
+                "seed : {}, coreset size : {}, total weight : {:.3e}",
+                seed,
+                items.len(),
+                total_weight
+            );
+            assert!((total_weight / nbdata as f64 - 1.).abs() < 0.1);
+            assert!(items.len() <= nb_sample);
+            assert!(items.len() as f64 >= 0.7 * nb_sample as f64);
+            sizes.push(items.len());
+        }
+        let mean_size = sizes.iter().sum::<usize>() as f64 / sizes.len() as f64;
+        assert!(mean_size >= 0.8 * nb_sample as f64);
+        // same seed, same coreset
+        assert_eq!(make(1457), make(1457));
+        assert_ne!(make(1457), make(2459));
+    } // end of test_sample_coreset
+
+    // with KMeans cost, facility costs used by sensitivities accumulate squared distances of points to their facility
+    #[test]
+    fn test_coreset_kmeans_cost() {
+        log_init_test();
+        //
+        let nbdata = 5000;
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(4577);
+        let unif = rand::distributions::Uniform::<f32>::new(-20., 20.);
+        let centers: Vec<Vec<f32>> = (0..5)
+            .map(|_| (0..10).map(|_| unif.sample(&mut rng)).collect())
+            .collect();
+        let normal = rand_distr::Normal::<f32>::new(0., 1.).unwrap();
+        let datas: Vec<(usize, f64, Vec<f32>)> = (0..nbdata)
+            .map(|i| {
+                let data = centers[i % 5]
+                    .iter()
+                    .map(|x| x + normal.sample(&mut rng))
+                    .collect();
+                (i, 1. + (i % 3) as f64, data)
+            })
+            .collect();
+        let iter_producer = WeightedDataForIter { datas };
+        //
+        let mut coreset1 = Coreset1::new(5, nbdata, 2., 2., DistL2, CostFunction::KMeans);
+        let coreset = coreset1.make_weighted_coreset(&iter_producer, 0.1).unwrap();
+        assert_eq!(coreset.get_cost_function(), CostFunction::KMeans);
+        let facilities = coreset1.facilities.as_ref().unwrap();
+        assert_eq!(facilities.get_cost_function(), CostFunction::KMeans);
+        let expected: f64 = iter_producer
+            .datas
+            .iter()
+            .map(|(_, w, data)| {
+                let dist = (0..facilities.len())
+                    .map(|f| DistL2.eval(data, facilities.get_center(f)))
+                    .fold(f32::MAX, f32::min) as f64;
+                w * dist * dist
+            })
+            .sum();
+        let total_weight: f64 = iter_producer.datas.iter().map(|(_, w, _)| *w).sum();
+        log::info!(
+            "facilities cost : {:.3e}, weighted sum of squared distances : {:.3e}",
+            facilities.get_cost(),
+            expected
+        );
+        assert!((facilities.get_cost() - expected).abs() <= 1.0e-3 * expected);
+        assert!((facilities.get_weight() - total_weight).abs() <= 1.0e-6 * total_weight);
+    } // end of test_coreset_kmeans_cost
+
+    // a cluster of points of null weight (far from others) gets its own facility of null weight
+    #[test]
+    fn test_sensitivity_sampling_null_weights() {
+        log_init_test();
+        //
+        let nb_null = 100;
+        let nbdata = 1000;
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(4579);
+        let normal = rand_distr::Normal::<f32>::new(0., 1.).unwrap();
+        let data: Vec<(f64, Vec<f32>, usize)> = (0..nb_null + nbdata)
+            .map(|i| {
+                let (weight, center) = if i < nb_null { (0., 100.) } else { (1., 0.) };
+                let v = (0..5).map(|_| center + normal.sample(&mut rng)).collect();
+                (weight, v, i)
+            })
+            .collect();
+        let sampled = weighted_sensitivity_sampling(&data, (2, 2., 2.), &DistL2, CostFunction::KMedian, 200, &mut rng).unwrap();
+        let total_weight: f64 = sampled.iter().map(|(w, _, _)| *w).sum();
+        log::info!("sampled {} points, total weight : {:.3e}", sampled.len(), total_weight);
+        assert!(sampled.iter().all(|(w, _, id)| *id >= nb_null && w.is_finite() && *w > 0.));
+        assert!((total_weight / nbdata as f64 - 1.).abs() < 0.1);
+        // compress goes through the same sampling
+        let id_weight_map: HashMap<usize, f64> = data.iter().map(|(w, _, id)| (*id, *w)).collect();
+        let datas_wid: Vec<(usize, Vec<f32>)> = data.iter().map(|(_, v, id)| (*id, v.clone())).collect();
+        let coreset = CoreSet::new(id_weight_map, Some(datas_wid), DistL2, CostFunction::KMedian).unwrap();
+        let compressed = coreset.compress(200, 2, BmorArg::default(), 4583).unwrap();
+        assert!(compressed.get_nb_points() <= 200);
+    } // end of test_sensitivity_sampling_null_weights
+} // end of mod tests
@@ -0,0 +1,271 @@
+//! Single pass coreset construction with the merge and reduce technique.
+//!
+//! [Coreset1](crate::sensitivity::Coreset1) needs 3 passes on data (and so a [MakeIter](crate::makeiter::MakeIter) implementation).
+//! When data can be read only once (sockets, stdin, logs...) the classic merge and reduce tree can be used:
+//!   - points are accumulated in buckets of fixed size,
+//!   - a full bucket is reduced to a coreset by sensitivity sampling (facilities are computed by [Bmor](crate::bmor::Bmor) on the bucket),
+//!   - two coresets of the same level are merged (union of coresets is a coreset of the union) and reduced again to give a coreset of the next level.
+//!
+//! At any moment only O(log(n/bucket_size)) coresets are kept in memory and the union of them (and of the current bucket) is a coreset of all data seen.
+//! The error of the reduction is multiplied at each level so the tree depth (log2 of the number of buckets) should be kept moderate by choosing a large bucket size.
+//!
+//!   - Har-Peled Mazumdar. On coresets for k-means and k-median clustering. STOC 2004
+//!
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use rand_xoshiro::rand_core::SeedableRng;
+use rand_xoshiro::Xoshiro256PlusPlus;
+
+use anndists::dist::*;
+
+use crate::error::CoresetError;
+use crate::facility::CostFunction;
+use crate::sensitivity::*;
+
+// a block of weighted points (weight, data vector, data id)
+type WeightedData<DataId, T> = Vec<(f64, Vec<T>, DataId)>;
+
+/// Merge and reduce streaming coreset. Data are pushed one by one with [push](Self::push()) and a coreset of all data pushed
+/// is available at any moment with [current_coreset](Self::current_coreset()).
+pub struct StreamingCoreset<DataId, T: Send + Sync + Clone, Dist: Distance<T> + Clone + Sync + Send>
+{
+    // expected number of facilities in Bmor used to compute sensitivities
+    k: usize,
+    // Bmor cost factor
+    beta: f64,
+    // Bmor slackness factor
+    gamma: f64,
+    // number of points in a leaf bucket
+    bucket_size: usize,
+    // number of points sampled at each reduction
+    coreset_size: usize,
+    //
+    distance: Dist,
+    //
+    cost_fn: CostFunction,
+    // current (leaf) bucket. unit weights
+    bucket: WeightedData<DataId, T>,
+    // levels[l] if not None, stores a coreset summarizing 2^l buckets
+    levels: Vec<Option<WeightedData<DataId, T>>>,
+    // nb data pushed
+    nb_data: usize,
+    //
+    rng: Xoshiro256PlusPlus,
+} // end of struct StreamingCoreset
+
+impl<DataId, T, Dist> StreamingCoreset<DataId, T, Dist>
+where
+    DataId: Eq + Hash + std::fmt::Debug + Clone + Send + Sync,
+    T: Send + Sync + Clone,
+    Dist: Distance<T> + Clone + Sync + Send,
+{
+    /// - k : expected number of facilities, as in [Bmor](crate::bmor::Bmor)
+    /// - bucket_size : number of points accumulated before a reduction.
+    /// - coreset_size : number of points sampled at each reduction, must be less than bucket_size.
+    /// - beta and gamma are arguments of Bmor (2. is a good default for both).
+    /// - cost_fn : cost function of the clustering problem.
+    ///
+    /// Returns an error if coreset_size is not less than bucket_size.
+    pub fn new(
+        k: usize,
+        bucket_size: usize,
+        coreset_size: usize,
+        beta: f64,
+        gamma: f64,
+        distance: Dist,
+        cost_fn: CostFunction,
+    ) -> Result<Self, CoresetError> {
+        if coreset_size >= bucket_size {
+            return Err(CoresetError::InvalidParameter(format!(
+                "StreamingCoreset::new, coreset_size {} must be less than bucket_size {}",
+                coreset_size, bucket_size
+            )));
+        }
+        Ok(StreamingCoreset {
+            k,
+            beta,
+            gamma,
+            bucket_size,
+            coreset_size,
+            distance,
+            cost_fn,
+            bucket: Vec::with_capacity(bucket_size),
+            levels: Vec::new(),
+            nb_data: 0,
+            rng: Xoshiro256PlusPlus::seed_from_u64(14537),
+        })
+    } // end of new
+
+    /// sets the seed of the sampling random generator (default is 14537). It must be called before pushing data.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Xoshiro256PlusPlus::seed_from_u64(seed);
+    }
+
+    /// returns number of data pushed
+    pub fn get_nb_data(&self) -> usize {
+        self.nb_data
+    }
+
+    /// returns the number of levels of the merge and reduce tree
+    pub fn get_nb_levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// inserts a data point. When current bucket is full it is reduced and merged into the tree.  
+    /// In case of error no data is lost, the reduction is retried at next push.
+    pub fn push(&mut self, id: DataId, data: Vec<T>) -> Result<(), CoresetError> {
+        self.bucket.push((1., data, id));
+        self.nb_data += 1;
+        if self.bucket.len() >= self.bucket_size {
+            let bucket = std::mem::replace(&mut self.bucket, Vec::with_capacity(self.bucket_size));
+            match self.reduce(&bucket) {
+                Ok(reduced) => self.insert_at_level(reduced, 0)?,
+                Err(e) => {
+                    self.bucket = bucket;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    } // end of push
+
+    /// returns a coreset of all data pushed up to now. It is the union of the coresets stored in the tree and of the current bucket.
+    pub fn current_coreset(&self) -> Result<CoreSet<DataId, T, Dist>, CoresetError> {
+        //
+        let mut id_weight_map = HashMap::<DataId, f64>::with_capacity(self.coreset_size);
+        let mut datas_wid = Vec::<(DataId, Vec<T>)>::with_capacity(self.coreset_size);
+        let levels = self.levels.iter().filter_map(|l| l.as_ref());
+        for (weight, data, id) in levels.flatten().chain(self.bucket.iter()) {
+            match id_weight_map.get_mut(id) {
+                Some(w) => {
+                    *w += weight;
+                }
+                None => {
+                    id_weight_map.insert(id.clone(), *weight);
+                    datas_wid.push((id.clone(), data.clone()));
+                }
+            }
+        }
+        log::debug!(
+            "StreamingCoreset::current_coreset nb data : {}, nb levels : {}, coreset size : {}",
+            self.nb_data,
+            self.levels.len(),
+            datas_wid.len()
+        );
+        CoreSet::new(
+            id_weight_map,
+            Some(datas_wid),
+            self.distance.clone(),
+            self.cost_fn,
+        )
+    } // end of current_coreset
+
+    // merge a coreset coming from level below into tree, reducing while level is occupied.
+    // If a reduction fails the unreduced union is kept at its level
+    fn insert_at_level(
+        &mut self,
+        coreset: WeightedData<DataId, T>,
+        level: usize,
+    ) -> Result<(), CoresetError> {
+        let mut carry = coreset;
+        let mut l = level;
+        loop {
+            if l == self.levels.len() {
+                self.levels.push(None);
+            }
+            match self.levels[l].take() {
+                Some(mut other) => {
+                    log::debug!("StreamingCoreset merging level {}", l);
+                    other.append(&mut carry);
+                    match self.reduce(&other) {
+                        Ok(reduced) => carry = reduced,
+                        Err(e) => {
+                            self.levels[l] = Some(other);
+                            return Err(e);
+                        }
+                    }
+                    l += 1;
+                }
+                None => {
+                    self.levels[l] = Some(carry);
+                    break;
+                }
+            }
+        }
+        Ok(())
+    } // end of insert_at_level
+
+    // sensitivity sampling of weighted points
+    fn reduce(
+        &mut self,
+        data: &[(f64, Vec<T>, DataId)],
+    ) -> Result<WeightedData<DataId, T>, CoresetError> {
+        weighted_sensitivity_sampling(
+            data,
+            (self.k, self.beta, self.gamma),
+            &self.distance,
+            self.cost_fn,
+            self.coreset_size,
+            &mut self.rng,
+        )
+    } // end of reduce
+} // end of impl block StreamingCoreset
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rand::distributions::{Distribution, Uniform};
+    use rand_distr::Normal;
+
+    fn log_init_test() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn test_streaming_coreset() {
+        log_init_test();
+        //
+        let nbdata = 20000;
+        let dim = 10;
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(1454691);
+        let unif = Uniform::<f32>::new(-20., 20.);
+        let centers: Vec<Vec<f32>> = (0..5)
+            .map(|_| (0..dim).map(|_| unif.sample(&mut rng)).collect())
+            .collect();
+        let normal = Normal::<f32>::new(0., 1.).unwrap();
+        // reduction must shrink buckets
+        assert!(StreamingCoreset::<usize, f32, DistL2>::new(5, 500, 500, 2., 2., DistL2, CostFunction::KMedian).is_err());
+        //
+        let mut streaming = StreamingCoreset::<usize, f32, DistL2>::new(
+            5,
+            2000,
+            500,
+            2.,
+            2.,
+            DistL2,
+            CostFunction::KMedian,
+        )
+        .unwrap();
+        for i in 0..nbdata {
+            let data: Vec<f32> = centers[i % 5]
+                .iter()
+                .map(|x| x + normal.sample(&mut rng))
+                .collect();
+            streaming.push(i, data).unwrap();
+        }
+        assert_eq!(streaming.get_nb_data(), nbdata);
+        let coreset = streaming.current_coreset().unwrap();
+        let total_weight: f64 = coreset.get_items().map(|(_, w)| *w).sum();
+        log::info!(
+            "coreset size : {}, total weight : {:.3e}, nb levels : {}",
+            coreset.get_nb_points(),
+            total_weight,
+            streaming.get_nb_levels()
+        );
+        assert!(coreset.get_nb_points() <= 4 * 500);
+        assert!((total_weight / nbdata as f64 - 1.).abs() < 0.1);
+    } // end of test_streaming_coreset
+} // end of mod tests
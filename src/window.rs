@@ -0,0 +1,498 @@
+//! Sliding window summary of a stream with [Bmor] : facilities (and a weighted summary) of the most recent data only.
+//!
+//! The window is either the last N points or the points received in the last T seconds, see [Window].
+//! As in exponential histograms (Datar Gionis Indyk Motwani. Maintaining stream statistics over sliding windows. SIAM J. Comput. 2002)
+//! the stream is cut in buckets, each summarized by its own [Bmor]:
+//!   - points are inserted in an open bucket of bucket_size points,
+//!   - when more than nb_per_level closed buckets summarize the same number of points, the two oldest are merged with [Bmor::merge()]
+//!     so a bucket of level l summarizes $2^l$ bucket_size consecutive points and the number of buckets is logarithmic in the window size,
+//!   - a bucket is dropped when its most recent point is out of the window.
+//!
+//! The union of kept buckets covers the window, it can include older points only from the oldest bucket, i.e at most
+//! a fraction of order 1/nb_per_level of the window.
+//! Facilities of the window are obtained at any time by merging the buckets, see [SlidingBmor::get_window_bmor()].
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use anndists::dist::*;
+
+use crate::bmor::Bmor;
+use crate::error::CoresetError;
+use crate::facility::{CostFunction, Facilities};
+use crate::pivot::PivotIndexArg;
+use crate::seeding::derive_seed;
+use crate::sensitivity::CoreSet;
+
+/// the window of a [SlidingBmor]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Window {
+    /// last N points
+    Count(usize),
+    /// points received during the last duration
+    Time(Duration),
+}
+
+// a Bmor summary of consecutive points
+struct Bucket<DataId, T: Send + Sync + Clone, Dist: Distance<T>> {
+    bmor: Bmor<DataId, T, Dist>,
+    // the bucket summarizes 2^level * bucket_size points once closed
+    level: usize,
+    // number of points summarized
+    nb_points: usize,
+    // rank in stream of most recent point
+    last_rank: usize,
+    // time of most recent point
+    last_time: Duration,
+}
+
+/// Sliding window [Bmor]. Data are processed by blocks with [process_data](Self::process_data()) and the facilities
+/// of the data in the window are available at any time with [end_window](Self::end_window()).
+pub struct SlidingBmor<DataId, T: Send + Sync + Clone, Dist: Distance<T>> {
+    // base number of centers expected
+    k: usize,
+    // number of points expected in window, given to Bmor of buckets
+    nbdata_expected: usize,
+    // Bmor cost factor
+    beta: f64,
+    // Bmor slackness factor
+    gamma: f64,
+    //
+    distance: Dist,
+    //
+    cost_fn: CostFunction,
+    //
+    window: Window,
+    // number of points of a bucket of level 0
+    bucket_size: usize,
+    // max number of closed buckets of each level
+    nb_per_level: usize,
+    //
+    seed: u64,
+    //
+    index_arg: Option<PivotIndexArg>,
+    // buckets, oldest first. The last one can be open (level 0 and less than bucket_size points)
+    buckets: VecDeque<Bucket<DataId, T, Dist>>,
+    // true if last bucket is open
+    open: bool,
+    // number of data processed
+    nb_data: usize,
+    // number of buckets created, used to derive their seeds
+    nb_created: u64,
+    // time of last block
+    last_time: Duration,
+    // time origin of process_data
+    start: Instant,
+} // end of struct SlidingBmor
+
+impl<DataId, T: Send + Sync + Clone, Dist> SlidingBmor<DataId, T, Dist>
+where
+    Dist: Distance<T> + Clone + Sync + Send,
+    DataId: std::fmt::Debug + Clone + Send + Sync,
+{
+    /// - k, beta, gamma, cost_fn : arguments of [Bmor::new()]
+    /// - window : the last points or the duration to summarize
+    /// - nbdata_expected : number of points expected in window (used as Bmor expected number of data). Ignored for [Window::Count].
+    ///
+    /// Buckets of level 0 have nbdata_expected/32 points (see [set_bucket_size](Self::set_bucket_size())) and 2 buckets by level are kept.
+    pub fn new(
+        k: usize,
+        window: Window,
+        nbdata_expected: usize,
+        beta: f64,
+        gamma: f64,
+        distance: Dist,
+        cost_fn: CostFunction,
+    ) -> Result<Self, CoresetError> {
+        let nbdata_expected = match window {
+            Window::Count(n) => n,
+            Window::Time(_) => nbdata_expected,
+        };
+        if nbdata_expected < 2 {
+            return Err(CoresetError::InvalidParameter(format!(
+                "SlidingBmor::new, window of {} points is too small",
+                nbdata_expected
+            )));
+        }
+        if let Window::Time(d) = window {
+            if d.is_zero() {
+                return Err(CoresetError::InvalidParameter(
+                    "SlidingBmor::new, null window duration".to_string(),
+                ));
+            }
+        }
+        Ok(SlidingBmor {
+            k,
+            nbdata_expected,
+            beta,
+            gamma,
+            distance,
+            cost_fn,
+            window,
+            bucket_size: (nbdata_expected / 32).max(1),
+            nb_per_level: 2,
+            seed: 1454691,
+            index_arg: None,
+            buckets: VecDeque::new(),
+            open: false,
+            nb_data: 0,
+            nb_created: 0,
+            last_time: Duration::ZERO,
+            start: Instant::now(),
+        })
+    } // end of new
+
+    /// sets the number of points of buckets of level 0 and the max number of buckets by level (at least 1).
+    /// The oldest bucket overlaps the window start, more buckets by level reduce the overlap at the expense of memory.
+    /// It must be called before processing data.
+    pub fn set_bucket_size(&mut self, bucket_size: usize, nb_per_level: usize) -> Result<(), CoresetError> {
+        if self.nb_data > 0 {
+            return Err(CoresetError::InvalidParameter(
+                "SlidingBmor::set_bucket_size must be called before processing data".to_string(),
+            ));
+        }
+        self.bucket_size = bucket_size.max(1);
+        self.nb_per_level = nb_per_level.max(1);
+        Ok(())
+    }
+
+    /// sets the seed from which the seeds of bucket Bmor are derived (default is 1454691).
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    /// sets a pivot index on facilities of buckets, see [Bmor::set_facility_index()]
+    pub fn set_facility_index(&mut self, arg: Option<PivotIndexArg>) {
+        self.index_arg = arg;
+    }
+
+    /// returns the window
+    pub fn get_window(&self) -> Window {
+        self.window
+    }
+
+    /// returns number of data processed since creation
+    pub fn get_nb_data(&self) -> usize {
+        self.nb_data
+    }
+
+    /// returns number of buckets kept
+    pub fn get_nb_buckets(&self) -> usize {
+        self.buckets.len()
+    }
+
+    /// returns number of points summarized by the buckets kept. It is at least the number of points in window (up to bucket granularity)
+    pub fn get_nb_summarized(&self) -> usize {
+        self.buckets.iter().map(|b| b.nb_points).sum()
+    }
+
+    /// processes a block of data received now (time elapsed since creation of the structure).
+    /// See [process_data_at](Self::process_data_at()).
+    pub fn process_data(&mut self, data: &[Vec<T>], id: &[DataId]) -> Result<usize, CoresetError> {
+        let time = self.start.elapsed();
+        self.process_data_at(data, id, time)
+    }
+
+    /// processes a block of data received at time (from any origin, times must not decrease across calls), then drops expired buckets.
+    /// It returns the number of buckets kept.
+    pub fn process_data_at(
+        &mut self,
+        data: &[Vec<T>],
+        id: &[DataId],
+        time: Duration,
+    ) -> Result<usize, CoresetError> {
+        if data.len() != id.len() {
+            return Err(CoresetError::InvalidParameter(format!(
+                "SlidingBmor::process_data got {} data and {} ids",
+                data.len(),
+                id.len()
+            )));
+        }
+        if time < self.last_time {
+            return Err(CoresetError::InvalidParameter(format!(
+                "SlidingBmor::process_data, time {:?} before last time {:?}",
+                time, self.last_time
+            )));
+        }
+        self.last_time = time;
+        // a long silence can expire the open bucket
+        self.expire();
+        let mut first = 0;
+        while first < data.len() {
+            if !self.open {
+                self.open_bucket();
+            }
+            let bucket = self.buckets.back_mut().unwrap();
+            let last = data.len().min(first + self.bucket_size - bucket.nb_points);
+            bucket.bmor.process_data(&data[first..last], &id[first..last])?;
+            bucket.nb_points += last - first;
+            bucket.last_rank = self.nb_data + last - 1;
+            bucket.last_time = time;
+            self.nb_data += last - first;
+            first = last;
+            if bucket.nb_points >= self.bucket_size {
+                self.open = false;
+                self.cascade()?;
+            }
+        }
+        self.expire();
+        log::debug!(
+            "SlidingBmor nb data : {}, nb buckets : {}, nb summarized : {}",
+            self.nb_data,
+            self.buckets.len(),
+            self.get_nb_summarized()
+        );
+        Ok(self.buckets.len())
+    } // end of process_data_at
+
+    /// returns a Bmor summarizing the window, obtained by merging buckets kept (see [Bmor::merge()]).
+    /// It can be ended with [Bmor::end_data()] or [Bmor::end_data_k()].
+    pub fn get_window_bmor(&self) -> Result<Bmor<DataId, T, Dist>, CoresetError> {
+        if self.buckets.is_empty() {
+            return Err(CoresetError::NotEnoughData(
+                "SlidingBmor, no data in window".to_string(),
+            ));
+        }
+        let window_bmor = self.new_bmor(self.seed);
+        for bucket in &self.buckets {
+            window_bmor.merge(&bucket.bmor)?;
+        }
+        Ok(window_bmor)
+    } // end of get_window_bmor
+
+    /// returns the facilities of the window, see [Bmor::end_data()]
+    pub fn end_window(&self, contraction: bool) -> Result<Facilities<DataId, T, Dist>, CoresetError> {
+        self.get_window_bmor()?.end_data(contraction)
+    }
+
+    fn new_bmor(&self, seed: u64) -> Bmor<DataId, T, Dist> {
+        let mut bmor = Bmor::new(
+            self.k,
+            self.nbdata_expected,
+            self.beta,
+            self.gamma,
+            self.distance.clone(),
+            self.cost_fn,
+        );
+        bmor.set_seed(seed);
+        bmor.set_facility_index(self.index_arg);
+        bmor
+    }
+
+    fn open_bucket(&mut self) {
+        self.nb_created += 1;
+        let bmor = self.new_bmor(derive_seed(self.seed, self.nb_created));
+        self.buckets.push_back(Bucket {
+            bmor,
+            level: 0,
+            nb_points: 0,
+            last_rank: self.nb_data,
+            last_time: self.last_time,
+        });
+        self.open = true;
+    }
+
+    // merges the two oldest buckets of a level while the level has too many buckets.
+    // Buckets are ordered by decreasing level so buckets of a level are contiguous.
+    fn cascade(&mut self) -> Result<(), CoresetError> {
+        let mut level = 0;
+        loop {
+            let ranks: Vec<usize> = (0..self.buckets.len())
+                .filter(|i| self.buckets[*i].level == level)
+                .collect();
+            if ranks.len() <= self.nb_per_level {
+                return Ok(());
+            }
+            let (older, newer) = (ranks[0], ranks[1]);
+            log::debug!("SlidingBmor merging 2 buckets of level {}", level);
+            let bucket = self.buckets.remove(newer).unwrap();
+            let merged = &mut self.buckets[older];
+            merged.bmor.merge(&bucket.bmor)?;
+            merged.level += 1;
+            merged.nb_points += bucket.nb_points;
+            merged.last_rank = bucket.last_rank;
+            merged.last_time = bucket.last_time;
+            level += 1;
+        }
+    } // end of cascade
+
+    // drops buckets whose most recent point is out of window
+    fn expire(&mut self) {
+        while let Some(bucket) = self.buckets.front() {
+            let expired = match self.window {
+                Window::Count(n) => bucket.last_rank + n < self.nb_data,
+                Window::Time(d) => bucket.last_time + d < self.last_time,
+            };
+            if !expired {
+                break;
+            }
+            log::debug!(
+                "SlidingBmor dropping bucket of level {} with {} points",
+                bucket.level,
+                bucket.nb_points
+            );
+            self.buckets.pop_front();
+            if self.buckets.is_empty() {
+                self.open = false;
+            }
+        }
+    } // end of expire
+} // end of impl block SlidingBmor
+
+impl<DataId, T: Send + Sync + Clone, Dist> SlidingBmor<DataId, T, Dist>
+where
+    Dist: Distance<T> + Clone + Sync + Send,
+    DataId: std::fmt::Debug + Clone + Send + Sync + Eq + Hash,
+{
+    /// returns a weighted summary of the window : the facilities of the window with their weights.
+    /// It can be clustered as a coreset (see [Kmedoid](crate::wkmedian::Kmedoid)).
+    pub fn get_window_coreset(&self) -> Result<CoreSet<DataId, T, Dist>, CoresetError> {
+        let facilities = self.end_window(false)?;
+        let weighted_data = facilities.into_weighted_data();
+        let mut id_weight_map = HashMap::<DataId, f64>::with_capacity(weighted_data.len());
+        let mut datas_wid = Vec::<(DataId, Vec<T>)>::with_capacity(weighted_data.len());
+        for (weight, data, id) in weighted_data {
+            match id_weight_map.get_mut(&id) {
+                Some(w) => {
+                    *w += weight;
+                }
+                None => {
+                    id_weight_map.insert(id.clone(), weight);
+                    datas_wid.push((id, data));
+                }
+            }
+        }
+        CoreSet::new(
+            id_weight_map,
+            Some(datas_wid),
+            self.distance.clone(),
+            self.cost_fn,
+        )
+    } // end of get_window_coreset
+} // end of impl block SlidingBmor
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rand::distributions::{Distribution, Uniform};
+    use rand_distr::Normal;
+    use rand_xoshiro::rand_core::SeedableRng;
+    use rand_xoshiro::Xoshiro256PlusPlus;
+
+    fn log_init_test() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    // nbdata points around nbcluster centers drawn with seed
+    fn generate_clustered_data(nbdata: usize, nbcluster: usize, seed: u64) -> Vec<Vec<f32>> {
+        let dim = 10;
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(seed);
+        let unif = Uniform::<f32>::new(-20., 20.);
+        let centers: Vec<Vec<f32>> = (0..nbcluster)
+            .map(|_| (0..dim).map(|_| unif.sample(&mut rng)).collect())
+            .collect();
+        let normal = Normal::<f32>::new(0., 1.).unwrap();
+        (0..nbdata)
+            .map(|i| {
+                let c = &centers[i % nbcluster];
+                c.iter().map(|x| x + normal.sample(&mut rng)).collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_window_count() {
+        log_init_test();
+        //
+        let nbdata = 8000;
+        let window = 3000;
+        // the stream drifts: old data and recent data come from different clusters
+        let old_data = generate_clustered_data(nbdata, 5, 1454691);
+        let new_data = generate_clustered_data(nbdata, 5, 4537);
+        let mut sliding = SlidingBmor::<usize, f32, DistL2>::new(
+            5,
+            Window::Count(window),
+            0,
+            2.,
+            2.,
+            DistL2,
+            CostFunction::KMedian,
+        )
+        .unwrap();
+        for (block, data) in [&old_data, &new_data].iter().enumerate() {
+            for (i, chunk) in data.chunks(500).enumerate() {
+                let ids: Vec<usize> = (0..chunk.len())
+                    .map(|j| block * nbdata + i * 500 + j)
+                    .collect();
+                sliding.process_data(chunk, &ids).unwrap();
+            }
+        }
+        assert_eq!(sliding.get_nb_data(), 2 * nbdata);
+        let nb_summarized = sliding.get_nb_summarized();
+        log::info!(
+            "nb buckets : {}, nb summarized : {}",
+            sliding.get_nb_buckets(),
+            nb_summarized
+        );
+        assert!(nb_summarized >= window);
+        assert!(nb_summarized <= 2 * window + sliding.bucket_size);
+        assert!(sliding.get_nb_buckets() <= 2 * (2 + (window / sliding.bucket_size).ilog2() as usize));
+        // window facilities keep the weight of summarized points
+        let mut facilities = sliding.end_window(false).unwrap();
+        assert!((facilities.get_weight() - nb_summarized as f64).abs() < 1.0e-5);
+        // recent data are well summarized, old data are not
+        let recent: Vec<&Vec<f32>> = new_data[nbdata - window..].iter().collect();
+        let ids: Vec<usize> = (0..recent.len()).collect();
+        let recent_cost = facilities.dispatch_data(&recent, &ids, None).unwrap() / window as f64;
+        let old: Vec<&Vec<f32>> = old_data[..window].iter().collect();
+        let old_cost = facilities.dispatch_data(&old, &ids, None).unwrap() / window as f64;
+        log::info!("mean cost recent : {:.3e}, old : {:.3e}", recent_cost, old_cost);
+        assert!(recent_cost < 2. * (10f64).sqrt());
+        assert!(old_cost > 2. * recent_cost);
+        //
+        let coreset = sliding.get_window_coreset().unwrap();
+        let total_weight: f64 = coreset.get_items().map(|(_, w)| *w).sum();
+        assert!((total_weight - nb_summarized as f64).abs() < 1.0e-5);
+    } // end of test_window_count
+
+    #[test]
+    fn test_window_time() {
+        log_init_test();
+        //
+        let data = generate_clustered_data(6000, 5, 1454691);
+        let mut sliding = SlidingBmor::<usize, f32, DistL2>::new(
+            5,
+            Window::Time(Duration::from_secs(10)),
+            2000,
+            2.,
+            2.,
+            DistL2,
+            CostFunction::KMedian,
+        )
+        .unwrap();
+        // a block of 200 points each second
+        for (i, chunk) in data.chunks(200).enumerate() {
+            let ids: Vec<usize> = (0..chunk.len()).map(|j| i * 200 + j).collect();
+            sliding
+                .process_data_at(chunk, &ids, Duration::from_secs(i as u64))
+                .unwrap();
+        }
+        let nb_summarized = sliding.get_nb_summarized();
+        log::info!("nb buckets : {}, nb summarized : {}", sliding.get_nb_buckets(), nb_summarized);
+        // 11 blocks are in window [19s, 29s]
+        assert!(nb_summarized >= 11 * 200);
+        assert!(nb_summarized <= 2 * 12 * 200);
+        // times must not decrease
+        let ids: Vec<usize> = (0..10).collect();
+        assert!(sliding
+            .process_data_at(&data[..10], &ids, Duration::from_secs(1))
+            .is_err());
+        // a long silence empties the window
+        sliding
+            .process_data_at(&data[..10], &ids, Duration::from_secs(100))
+            .unwrap();
+        assert_eq!(sliding.get_nb_summarized(), 10);
+    } // end of test_window_time
+} // end of mod tests
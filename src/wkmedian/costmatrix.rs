@@ -0,0 +1,370 @@
+//! Storage of the cost matrix between coreset points used by [Kmedoid](super::Kmedoid).
+//!
+//! The dense matrix needs n² f32 and limits the coreset size to a few tens of thousands points.
+//! See [MatrixStorage] for the other layouts :
+//! - a condensed upper triangular matrix halves memory.
+//! - the condensed matrix with values stored as bf16 (the 16 high bits of a f32) divides memory by 4, with a relative error of about 0.4%.
+//! - costs can be recomputed from point vectors, rows accessed often being cached and the least recently used row evicted.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use ndarray::Array2;
+use parking_lot::RwLock;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use anndists::dist::*;
+
+use crate::facility::CostFunction;
+use crate::sensitivity::CoreSet;
+
+/// layout of the cost matrix between coreset points stored in [Kmedoid](super::Kmedoid)
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum MatrixStorage {
+    /// full n x n matrix of f32
+    #[default]
+    Dense,
+    /// upper triangle (without diagonal) of f32, half the memory of Dense
+    Condensed,
+    /// upper triangle (without diagonal) stored in bf16, a quarter of the memory of Dense
+    CondensedBf16,
+    /// costs are recomputed from point vectors, the argument is the maximum number of rows kept in cache.
+    /// A row is computed and cached when it is accessed often, the least recently used row is then evicted.
+    OnTheFly(usize),
+}
+
+// rounds to nearest bf16
+fn to_bf16(x: f32) -> u16 {
+    let bits = x.to_bits();
+    let rounding = 0x7FFF + ((bits >> 16) & 1);
+    (bits.wrapping_add(rounding) >> 16) as u16
+}
+
+fn from_bf16(b: u16) -> f32 {
+    f32::from_bits((b as u32) << 16)
+}
+
+// rank of (i,j), i < j, in condensed upper triangle of a n x n matrix
+#[inline]
+fn condensed_index(n: usize, i: usize, j: usize) -> usize {
+    i * (2 * n - i - 1) / 2 + (j - i - 1)
+}
+
+// fills in parallel the condensed upper triangle of a n x n matrix with value(i,j), i < j.
+// values must have length n(n-1)/2, rows are disjoint slices of it so no other buffer is allocated.
+fn fill_condensed<V, F>(n: usize, values: &mut [V], value: F)
+where
+    V: Send,
+    F: Fn(usize, usize) -> V + Sync,
+{
+    let mut rows = Vec::<(usize, &mut [V])>::with_capacity(n);
+    let mut remaining = values;
+    for i in 0..n.saturating_sub(1) {
+        let (row, rest) = std::mem::take(&mut remaining).split_at_mut(n - 1 - i);
+        rows.push((i, row));
+        remaining = rest;
+    }
+    rows.into_par_iter().for_each(|(i, row)| {
+        for (k, v) in row.iter_mut().enumerate() {
+            *v = value(i, i + 1 + k);
+        }
+    });
+} // end of fill_condensed
+
+// number of misses on a row before it is computed and cached
+const ROW_LOAD_THRESHOLD: u32 = 8;
+
+type CostEval<T> = Box<dyn Fn(&[T], &[T]) -> f32 + Send + Sync>;
+
+// a cached row and the time of its last access
+struct CachedRow {
+    costs: Vec<f32>,
+    last_use: AtomicU64,
+}
+
+// costs recomputed from points, with a cache of rows
+pub(crate) struct RowCache<T> {
+    points: Vec<Vec<T>>,
+    // returns cost between 2 points
+    eval: CostEval<T>,
+    // max number of cached rows
+    nb_rows: usize,
+    // cached rows. Hits only take the read lock and update last_use
+    rows: RwLock<HashMap<usize, CachedRow>>,
+    // incremented at each hit, gives last_use of rows
+    clock: AtomicU64,
+    // number of misses by row since last load
+    misses: Vec<AtomicU32>,
+}
+
+impl<T: Send + Sync> RowCache<T> {
+    fn new(points: Vec<Vec<T>>, eval: CostEval<T>, nb_rows: usize) -> Self {
+        let misses = (0..points.len()).map(|_| AtomicU32::new(0)).collect();
+        RowCache {
+            points,
+            eval,
+            nb_rows,
+            rows: RwLock::new(HashMap::with_capacity(nb_rows)),
+            clock: AtomicU64::new(0),
+            misses,
+        }
+    }
+
+    // returns cost (row, col) if row is cached
+    fn cached(&self, row: usize, col: usize) -> Option<f32> {
+        let rows = self.rows.read();
+        let cached = rows.get(&row)?;
+        let now = self.clock.fetch_add(1, Ordering::Relaxed);
+        cached.last_use.store(now, Ordering::Relaxed);
+        Some(cached.costs[col])
+    }
+
+    fn load_row(&self, i: usize) {
+        let p_i = &self.points[i];
+        let row: Vec<f32> = self
+            .points
+            .iter()
+            .enumerate()
+            .map(|(j, p_j)| if j == i { 0. } else { (self.eval)(p_i, p_j) })
+            .collect();
+        let mut rows = self.rows.write();
+        if rows.contains_key(&i) {
+            return;
+        }
+        // evict least recently used row. Loads are rare so a scan of cached rows is cheap
+        if rows.len() >= self.nb_rows {
+            let lru = rows
+                .iter()
+                .min_by_key(|(_, r)| r.last_use.load(Ordering::Relaxed))
+                .map(|(k, _)| *k);
+            if let Some(lru) = lru {
+                rows.remove(&lru);
+            }
+        }
+        let now = self.clock.fetch_add(1, Ordering::Relaxed);
+        rows.insert(
+            i,
+            CachedRow {
+                costs: row,
+                last_use: AtomicU64::new(now),
+            },
+        );
+    } // end of load_row
+
+    fn get(&self, i: usize, j: usize) -> f32 {
+        if i == j {
+            return 0.;
+        }
+        if let Some(cost) = self.cached(i, j) {
+            return cost;
+        }
+        if let Some(cost) = self.cached(j, i) {
+            return cost;
+        }
+        if self.nb_rows > 0 {
+            let miss_i = self.misses[i].fetch_add(1, Ordering::Relaxed) + 1;
+            let miss_j = self.misses[j].fetch_add(1, Ordering::Relaxed) + 1;
+            let (hot, miss) = if miss_i >= miss_j { (i, miss_i) } else { (j, miss_j) };
+            if miss >= ROW_LOAD_THRESHOLD {
+                self.misses[hot].store(0, Ordering::Relaxed);
+                self.load_row(hot);
+            }
+        }
+        (self.eval)(&self.points[i], &self.points[j])
+    } // end of get
+} // end of impl RowCache
+
+/// The cost matrix (distances transformed by the cost function) between coreset points.
+pub(crate) enum CostMatrix<T> {
+    Dense(Array2<f32>),
+    Condensed(usize, Vec<f32>),
+    CondensedBf16(usize, Vec<u16>),
+    OnTheFly(RowCache<T>),
+}
+
+impl<T: Send + Sync + Clone> CostMatrix<T> {
+    /// computes the dense matrix, returns ids of points in matrix order
+    pub(crate) fn dense<DataId, Dist>(coreset: &CoreSet<DataId, T, Dist>) -> Option<(Vec<DataId>, Self)>
+    where
+        DataId: Eq + std::hash::Hash + Send + Sync + Clone + std::fmt::Debug,
+        Dist: Distance<T> + Send + Sync + Clone,
+    {
+        let (ids, mut distance) = coreset.compute_distances()?;
+        // as cost is increasing with distance, nearest center for distance is also nearest for cost,
+        // so all computations can be done on cost matrix
+        let cost_fn = coreset.get_cost_function();
+        if cost_fn != CostFunction::KMedian {
+            distance.mapv_inplace(|d| cost_fn.eval(d));
+        }
+        Some((ids, CostMatrix::Dense(distance)))
+    } // end of dense
+
+    /// computes the matrix with layout storage, returns ids of points in matrix order
+    pub(crate) fn new<DataId, Dist>(
+        coreset: &CoreSet<DataId, T, Dist>,
+        storage: MatrixStorage,
+    ) -> Option<(Vec<DataId>, Self)>
+    where
+        DataId: Eq + std::hash::Hash + Send + Sync + Clone + std::fmt::Debug,
+        Dist: Distance<T> + Send + Sync + Clone + 'static,
+        T: 'static,
+    {
+        let points = coreset.get_data_points()?;
+        let ids: Vec<DataId> = points.iter().map(|(id, _)| id.clone()).collect();
+        let n = points.len();
+        let cost_fn = coreset.get_cost_function();
+        let distance = coreset.get_distance();
+        let cost = |i: usize, j: usize| -> f32 { cost_fn.eval(distance.eval(&points[i].1, &points[j].1)) };
+        let nb_values = n * n.saturating_sub(1) / 2;
+        let matrix = match storage {
+            MatrixStorage::Dense => return CostMatrix::dense(coreset),
+            MatrixStorage::Condensed => {
+                let mut values = vec![0f32; nb_values];
+                fill_condensed(n, &mut values, cost);
+                CostMatrix::Condensed(n, values)
+            }
+            MatrixStorage::CondensedBf16 => {
+                let mut values = vec![0u16; nb_values];
+                fill_condensed(n, &mut values, |i, j| to_bf16(cost(i, j)));
+                CostMatrix::CondensedBf16(n, values)
+            }
+            MatrixStorage::OnTheFly(nb_rows) => {
+                let distance = distance.clone();
+                let eval: CostEval<T> = Box::new(move |a, b| cost_fn.eval(distance.eval(a, b)));
+                let vectors = points.iter().map(|(_, p)| p.clone()).collect();
+                CostMatrix::OnTheFly(RowCache::new(vectors, eval, nb_rows))
+            }
+        };
+        Some((ids, matrix))
+    } // end of new
+
+    /// returns number of rows (and columns)
+    pub(crate) fn dim(&self) -> usize {
+        match self {
+            CostMatrix::Dense(m) => m.nrows(),
+            CostMatrix::Condensed(n, _) | CostMatrix::CondensedBf16(n, _) => *n,
+            CostMatrix::OnTheFly(cache) => cache.points.len(),
+        }
+    }
+
+    /// returns cost between points of rank i and j
+    #[inline]
+    pub(crate) fn get(&self, i: usize, j: usize) -> f32 {
+        match self {
+            CostMatrix::Dense(m) => m[[i, j]],
+            CostMatrix::Condensed(n, values) => match i.cmp(&j) {
+                std::cmp::Ordering::Less => values[condensed_index(*n, i, j)],
+                std::cmp::Ordering::Greater => values[condensed_index(*n, j, i)],
+                std::cmp::Ordering::Equal => 0.,
+            },
+            CostMatrix::CondensedBf16(n, values) => match i.cmp(&j) {
+                std::cmp::Ordering::Less => from_bf16(values[condensed_index(*n, i, j)]),
+                std::cmp::Ordering::Greater => from_bf16(values[condensed_index(*n, j, i)]),
+                std::cmp::Ordering::Equal => 0.,
+            },
+            CostMatrix::OnTheFly(cache) => cache.get(i, j),
+        }
+    } // end of get
+
+    /// returns memory used by stored costs in bytes
+    pub(crate) fn memory_size(&self) -> usize {
+        match self {
+            CostMatrix::Dense(m) => m.len() * std::mem::size_of::<f32>(),
+            CostMatrix::Condensed(_, values) => values.len() * std::mem::size_of::<f32>(),
+            CostMatrix::CondensedBf16(_, values) => values.len() * std::mem::size_of::<u16>(),
+            CostMatrix::OnTheFly(cache) => cache.nb_rows * cache.points.len() * std::mem::size_of::<f32>(),
+        }
+    }
+} // end of impl CostMatrix
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    use rand::distributions::{Distribution, Uniform};
+    use rand_xoshiro::rand_core::SeedableRng;
+    use rand_xoshiro::Xoshiro256PlusPlus;
+
+    fn log_init_test() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn test_condensed_index() {
+        let n = 7;
+        let mut k = 0;
+        for i in 0..n {
+            for j in (i + 1)..n {
+                assert_eq!(condensed_index(n, i, j), k);
+                k += 1;
+            }
+        }
+        assert_eq!(k, n * (n - 1) / 2);
+    }
+
+    #[test]
+    fn test_bf16() {
+        for x in [0., 1., 0.1, 3.7e-5, 12345.678, 2.5e10] {
+            let y = from_bf16(to_bf16(x));
+            assert!((x - y).abs() <= 4.0e-3 * x);
+        }
+    }
+
+    #[test]
+    fn test_storages() {
+        log_init_test();
+        //
+        let nbdata = 300;
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(1457);
+        let unif = Uniform::<f32>::new(-10., 10.);
+        let datas_wid: Vec<(usize, Vec<f32>)> = (0..nbdata)
+            .map(|i| (i, (0..10).map(|_| unif.sample(&mut rng)).collect()))
+            .collect();
+        let id_weight_map: HashMap<usize, f64> = (0..nbdata).map(|i| (i, 1.)).collect();
+        let coreset = CoreSet::new(id_weight_map, Some(datas_wid), DistL2, CostFunction::KMeans).unwrap();
+        //
+        let (ids, dense) = CostMatrix::dense(&coreset).unwrap();
+        assert_eq!(dense.dim(), nbdata);
+        for storage in [
+            MatrixStorage::Condensed,
+            MatrixStorage::CondensedBf16,
+            MatrixStorage::OnTheFly(10),
+            MatrixStorage::OnTheFly(0),
+        ] {
+            let (ids_s, matrix) = CostMatrix::new(&coreset, storage).unwrap();
+            assert_eq!(ids, ids_s);
+            assert_eq!(matrix.dim(), nbdata);
+            log::info!("storage {:?}, memory : {}", storage, matrix.memory_size());
+            let tolerance = if storage == MatrixStorage::CondensedBf16 { 4.0e-3 } else { 1.0e-6 };
+            // twice so that rows get cached
+            for _ in 0..2 {
+                for i in 0..nbdata {
+                    for j in 0..nbdata {
+                        let expected = dense.get(i, j);
+                        assert!((matrix.get(i, j) - expected).abs() <= tolerance * expected);
+                    }
+                }
+            }
+        }
+    } // end of test_storages
+
+    #[test]
+    fn test_row_cache_lru() {
+        log_init_test();
+        //
+        let points: Vec<Vec<f32>> = (0..10).map(|i| vec![i as f32]).collect();
+        let eval: CostEval<f32> = Box::new(|a, b| (a[0] - b[0]).abs());
+        let cache = RowCache::new(points, eval, 2);
+        cache.load_row(0);
+        cache.load_row(1);
+        // row 0 is used again, so row 1 is the least recently used and must be evicted
+        assert_eq!(cache.get(0, 5), 5.);
+        cache.load_row(2);
+        let rows = cache.rows.read();
+        assert!(rows.contains_key(&0));
+        assert!(!rows.contains_key(&1));
+        assert!(rows.contains_key(&2));
+        assert_eq!(rows.len(), 2);
+    } // end of test_row_cache_lru
+} // end of mod tests
@@ -3,18 +3,20 @@
 //!     with :
 //!       - introduction of weights attached to data beccause coreset data have a weight
 //!       - cost a point is its weight multiplied by distance to centers
-//!       - initialization of medoids is done by decreasing costs  
+//!       - initialization of medoids is done by decreasing costs or by weighted k-medoids++ sampling (see [InitMethod])
 //!
 //!  This mimics the kmean algo. The weights attached to points alleviates the problem of local minima.
 //!  See also Friedmann Hastie Tibshirani, The Elements Of Statistical Learning 2001 (Clustering chapter)
 //!
+//!  The alternate iterations can be followed (or replaced) by the eager swap phase of FasterPAM, see [KmedoidOptimization] and
+//!     - Schubert Rousseeuw. Fast and eager k-medoids clustering: O(k) runtime improvement of the PAM, CLARA, and CLARANS algorithms 2021
+//!
 //!
 //!
 
-use ndarray::Array2;
 
 use rand::{
-    distributions::{Distribution, Uniform},
+    distributions::{Distribution, Uniform, WeightedIndex},
     Rng,
 };
 use rand_xoshiro::rand_core::SeedableRng;
@@ -29,15 +31,54 @@ use rayon::iter::{IntoParallelIterator, ParallelIterator}; // we could use also
 
 use anndists::dist::*;
 
+use crate::error::CoresetError;
+use crate::facility::CostFunction;
 use crate::makeiter::*;
 use crate::sensitivity::*;
 
+use super::costmatrix::*;
+
 // maintain center and cost of each cluster
 struct CenterCost(Vec<(usize, f32)>);
 
 // maintain membership and distance to its center for each point
 struct MemberDist(Vec<(u32, f32)>);
 
+// a state of the algorithm: medoids and membership of each point (rank in medoids array)
+#[derive(Clone)]
+struct KmedoidState<DataId> {
+    medoids: Vec<Medoid<DataId>>,
+    membership: Vec<u32>,
+}
+
+// maintain for each point rank of nearest medoid, distance to it and distance to second nearest medoid
+struct NearestTwo(Vec<(u32, f32, f32)>);
+
+/// The optimization run by [Kmedoid::compute_medians()]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum KmedoidOptimization {
+    /// Park-Jun alternate iterations: dispatch points to nearest medoid then recompute the medoid of each cluster
+    #[default]
+    Alternate,
+    /// weighted eager swap of FasterPAM, starting from initial medoids
+    Swap,
+    /// alternate iterations followed by eager swap
+    AlternateThenSwap,
+}
+
+/// Initialization of medoids in [Kmedoid::compute_medians()]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum InitMethod {
+    /// deterministic: point of maximal weight, then points of maximal weighted cost to already chosen centers
+    MaxCost,
+    /// deterministic: point of maximal weight, then points of maximal weighted cost to one of already chosen centers
+    MaxDist,
+    /// uniform sampling of distinct points with rng initialized by seed
+    Random(u64),
+    /// weighted D^z sampling (k-medoids++) with rng initialized by seed
+    KMedoidsPlusPlus(u64),
+}
+
 #[derive(Copy, Clone)]
 pub struct Medoid<DataId> {
     /// id as given by coreset
@@ -88,26 +129,31 @@ where
 }
 
 //TODO: add field from id to rank
-/// This algorithm stores the whole matrix distance between points as coreset must have reduced the number of points to a few thousands.
+/// This algorithm stores the whole matrix distance between points as coreset must have reduced the number of points to a few thousands.  
+/// For larger coresets see [MatrixStorage] and [Kmedoid::with_storage].
 pub struct Kmedoid<DataId, T> {
     //
     nb_cluster: usize,
     // orginal ids of data to cluster i.e those in the coreset (!!) by line of matrix
     ids: Vec<DataId>,
-    // distance matrix between points in the coreset. (Same size as ids!)
-    distance: Array2<f32>,
+    // cost matrix between points in the coreset i.e cost_fn(distance). (Same size as ids!)
+    distance: CostMatrix<T>,
+    // the cost function of the coreset, already applied to distance matrix
+    cost_fn: CostFunction,
+    // the optimization run by compute_medians
+    optimization: KmedoidOptimization,
     // weights of points in coreset in order corresponding to lines of distance matrix
     weights: Vec<f64>,
-    // current affectation of each coreset point. For each point returns rank in medoids array.
-    membership: Vec<u32>,
-    // medoid : a Vector containing the nb_cluster medoids
-    medoids: Vec<Medoid<DataId>>,
+    // current medoids (a Vector containing the nb_cluster medoids) and affectation of each coreset point
+    state: KmedoidState<DataId>,
     // at end end of computations we keep just the data of the Medoid centers.
     // It is not stored in each medoid for dispatching computing efficacitty
     // Storing is in the same order as in field medoids!!
     centers: Option<Vec<Vec<T>>>,
     //
     d_quantiles: CKMS<f32>,
+    // seed of the random generator used in center perturbations
+    seed: u64,
 } // end of struct Kmedoid
 
 impl<DataId, T> Kmedoid<DataId, T>
@@ -115,20 +161,64 @@ where
     DataId: Eq + std::hash::Hash + Send + Sync + Clone + Default + std::fmt::Debug,
     T: Send + Sync + Clone + std::fmt::Debug,
 {
-    pub fn new<Dist>(coreset: &CoreSet<DataId, T, Dist>, nb_cluster_arg: usize) -> Self
+    /// computes the cost matrix of coreset points. The coreset must store its data vectors.  
+    /// If nb_cluster_arg is not less than the number of points, it is reset to a tenth of the number of points.  
+    /// The matrix is stored dense, see [with_storage](Self::with_storage()) for larger coresets.
+    pub fn new<Dist>(coreset: &CoreSet<DataId, T, Dist>, nb_cluster_arg: usize) -> Result<Self, CoresetError>
     where
         Dist: Distance<T> + Send + Sync + Clone,
     {
         //
         let cpu_start = ProcessTime::now();
         let sys_now = SystemTime::now();
-        let (ids, distance) = coreset.compute_distances().unwrap();
+        let (ids, distance) = CostMatrix::dense(coreset).ok_or_else(|| {
+            CoresetError::MissingData("Kmedoid::new, coreset has no data vectors".to_string())
+        })?;
         log::debug!(
             "kmedoids  distance matrix init sys time(ms) {:?} cpu time(ms) {:?} ",
             sys_now.elapsed().unwrap().as_millis(),
             cpu_start.elapsed().as_millis()
         );
-        //
+        Self::from_matrix(coreset, nb_cluster_arg, ids, distance)
+    } // end of new
+
+    /// as [new](Self::new()) but the cost matrix is stored as specified by storage.  
+    /// Condensed storages reduce memory by 2 or 4 (bf16 has a relative precision of about 0.4%),
+    /// [MatrixStorage::OnTheFly] stores only the rows most accessed and recomputes the other costs from the data vectors.
+    pub fn with_storage<Dist>(
+        coreset: &CoreSet<DataId, T, Dist>,
+        nb_cluster_arg: usize,
+        storage: MatrixStorage,
+    ) -> Result<Self, CoresetError>
+    where
+        Dist: Distance<T> + Send + Sync + Clone + 'static,
+        T: 'static,
+    {
+        let cpu_start = ProcessTime::now();
+        let sys_now = SystemTime::now();
+        let (ids, distance) = CostMatrix::new(coreset, storage).ok_or_else(|| {
+            CoresetError::MissingData("Kmedoid::with_storage, coreset has no data vectors".to_string())
+        })?;
+        log::debug!(
+            "kmedoids  distance matrix init ({:?}, {} bytes) sys time(ms) {:?} cpu time(ms) {:?} ",
+            storage,
+            distance.memory_size(),
+            sys_now.elapsed().unwrap().as_millis(),
+            cpu_start.elapsed().as_millis()
+        );
+        Self::from_matrix(coreset, nb_cluster_arg, ids, distance)
+    } // end of with_storage
+
+    fn from_matrix<Dist>(
+        coreset: &CoreSet<DataId, T, Dist>,
+        nb_cluster_arg: usize,
+        ids: Vec<DataId>,
+        distance: CostMatrix<T>,
+    ) -> Result<Self, CoresetError>
+    where
+        Dist: Distance<T> + Send + Sync + Clone,
+    {
+        let cost_fn = coreset.get_cost_function();
         let nbpoints = coreset.get_nb_points();
         log::info!("Kmedoid received coreset of size : {}", nbpoints);
         // must check for that!
@@ -140,46 +230,213 @@ where
         else {
             nb_cluster_arg
         };
+        if nb_cluster == 0 {
+            return Err(CoresetError::NotEnoughData(format!(
+                "Kmedoid::new, cannot make {} clusters with {} points",
+                nb_cluster_arg, nbpoints
+            )));
+        }
         // weight[i] corresponds to row[i] in distance matrix. From now on all computation use center as raks and no ids.
         let mut weights = Vec::<f64>::with_capacity(nbpoints);
         for id in &ids {
-            let weight = coreset.get_weight(id).unwrap();
+            let weight = coreset.get_weight(id).ok_or_else(|| {
+                CoresetError::MissingData(format!("Kmedoid::new, no weight for id {:?}", id))
+            })?;
             weights.push(weight);
         }
         //
-        assert_eq!(weights.len(), nbpoints);
-        //
         let membership = (0..nbpoints).map(|_| u32::MAX).collect();
         let medoids = (0..nb_cluster)
             .map(|_| Medoid::default())
             .collect();
         //
         //
-        Kmedoid {
+        Ok(Kmedoid {
             nb_cluster,
             ids,
             distance,
+            cost_fn,
+            optimization: KmedoidOptimization::default(),
             weights,
-            membership,
-            medoids,
+            state: KmedoidState { medoids, membership },
             centers: None,
             d_quantiles: CKMS::<f32>::new(0.01),
+            seed: 4537,
+        })
+    } // end of from_matrix
+
+    /// changes the number of clusters, reusing the distance matrix. Previous results are discarded.  
+    /// nb_cluster must be at least 1 and less than the number of points.
+    pub fn set_nb_cluster(&mut self, nb_cluster: usize) -> Result<(), CoresetError> {
+        if nb_cluster == 0 || nb_cluster >= self.get_nb_points() {
+            return Err(CoresetError::InvalidParameter(format!(
+                "Kmedoid::set_nb_cluster : nb_cluster {} must be in 1..{}",
+                nb_cluster,
+                self.get_nb_points()
+            )));
         }
-    } // end of new
+        self.nb_cluster = nb_cluster;
+        self.state = KmedoidState {
+            medoids: (0..nb_cluster).map(|_| Medoid::default()).collect(),
+            membership: vec![u32::MAX; self.get_nb_points()],
+        };
+        self.centers = None;
+        Ok(())
+    } // end of set_nb_cluster
+
+    /// returns the number of clusters
+    pub fn get_nb_cluster(&self) -> usize {
+        self.nb_cluster
+    }
+
+    /// sets the optimization run by [compute_medians](Self::compute_medians()). Default is [KmedoidOptimization::Alternate]
+    pub fn set_optimization(&mut self, optimization: KmedoidOptimization) {
+        self.optimization = optimization;
+    }
+
+    /// returns the optimization run by [compute_medians](Self::compute_medians())
+    pub fn get_optimization(&self) -> KmedoidOptimization {
+        self.optimization
+    }
+
+    /// sets the seed of the random generator used by [compute_medians](Self::compute_medians()) in center perturbations (default is 4537).  
+    /// Initialization seeds are given by [InitMethod].
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    /// returns the seed of the random generator used by [compute_medians](Self::compute_medians())
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    // weights must give a valid sampling distribution and finite costs
+    fn check_total_weight(&self, caller: &str) -> Result<(), CoresetError> {
+        let total_weight: f64 = self.weights.iter().sum();
+        if !(total_weight > 0. && total_weight.is_finite()) {
+            return Err(CoresetError::InvalidParameter(format!(
+                "Kmedoid::{}, total weight must be positive and finite, got {:.3e}",
+                caller, total_weight
+            )));
+        }
+        Ok(())
+    }
 
-    /// nb_iter is maximal number of iterations  
+    /// nb_iter is maximal number of iterations (or of passes on points for the swap phase)  
+    /// init is the initialization method of medoids.  
     /// returns best result as couple (iteration, cost)
-    pub fn compute_medians(&mut self, nb_iter : usize) -> (usize, f32) {
+    pub fn compute_medians(&mut self, nb_iter : usize, init: InitMethod) -> Result<(usize, f32), CoresetError> {
         //
         log::info!("\n\nentering Kmedoid::Kmedoid");
+        self.check_total_weight("compute_medians")?;
+        self.d_quantiles = self.quantile_estimator();
+        let cpu_start = ProcessTime::now();
+        let sys_now = SystemTime::now();
+        //
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(self.seed);
+        let (state, best_iter) = self.run(nb_iter, init, &mut rng)?;
+        self.state = state;
+        //
+        log::info!("======================================================");
+        log::info!("best iter : {}, cost {:.3e}", best_iter.0, best_iter.1);
+        log::info!("======================================================");
+        let cpu_time: Duration = cpu_start.elapsed();
+        println!(
+            "kmedoid compute medians total time sys time(ms) {:?} cpu time(ms) {:?}",
+            sys_now.elapsed().unwrap().as_millis(),
+            cpu_time.as_millis()
+        );
+        //
+        self.quality_summary(&self.state, &[], true);
+        //
+        Ok(best_iter)
+    } // end of compute_medians
+
+    /// runs nb_restarts independent optimizations (see [compute_medians](Self::compute_medians())) in parallel
+    /// and keeps the solution of lowest cost.  
+    /// Each restart is initialized by [InitMethod::KMedoidsPlusPlus] with a seed drawn from a rng initialized with seed,
+    /// so results are reproducible.  
+    /// returns the rank of the restart kept and for each restart the couple (iteration, cost) of its best result
+    pub fn compute_medians_restarts(
+        &mut self,
+        nb_restarts: usize,
+        nb_iter: usize,
+        seed: u64,
+    ) -> Result<(usize, Vec<(usize, f32)>), CoresetError> {
+        //
+        log::info!("\n\nentering Kmedoid::compute_medians_restarts nb restarts : {}", nb_restarts);
+        if nb_restarts == 0 {
+            return Err(CoresetError::InvalidParameter(
+                "Kmedoid::compute_medians_restarts, nb_restarts must be at least 1".to_string(),
+            ));
+        }
+        self.check_total_weight("compute_medians_restarts")?;
         self.d_quantiles = self.quantile_estimator();
         let cpu_start = ProcessTime::now();
         let sys_now = SystemTime::now();
+        //
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(seed);
+        let seeds: Vec<u64> = (0..nb_restarts).map(|_| rng.gen::<u64>()).collect();
+        let results: Vec<(KmedoidState<DataId>, (usize, f32))> = seeds
+            .into_par_iter()
+            .map(|s| {
+                // perturbation rng is jumped to get a stream different from the initialization one
+                let mut rng = Xoshiro256PlusPlus::seed_from_u64(s);
+                rng.jump();
+                self.run(nb_iter, InitMethod::KMedoidsPlusPlus(s), &mut rng)
+            })
+            .collect::<Result<Vec<_>, CoresetError>>()?;
+        //
+        let costs: Vec<(usize, f32)> = results.iter().map(|r| r.1).collect();
+        let mut best = 0;
+        for (r, (_, cost)) in costs.iter().enumerate() {
+            log::info!("restart : {}, cost : {:.3e}", r, cost);
+            if *cost < costs[best].1 {
+                best = r;
+            }
+        }
+        self.state = results.into_iter().nth(best).ok_or_else(|| {
+            CoresetError::AlgorithmFailure("Kmedoid::compute_medians_restarts, no restart result".to_string())
+        })?.0;
+        //
+        log::info!("======================================================");
+        log::info!("best restart : {}, cost {:.3e}", best, costs[best].1);
+        log::info!("======================================================");
+        let cpu_time: Duration = cpu_start.elapsed();
+        println!(
+            "kmedoid compute medians restarts total time sys time(ms) {:?} cpu time(ms) {:?}",
+            sys_now.elapsed().unwrap().as_millis(),
+            cpu_time.as_millis()
+        );
+        //
+        self.quality_summary(&self.state, &[], true);
+        //
+        Ok((best, costs))
+    } // end of compute_medians_restarts
+
+    // one optimization from initialization to end, does not modify self so runs can be done in parallel.
+    // rng is used for perturbation of centers.
+    // returns best state and its (iteration, cost)
+    fn run(
+        &self,
+        nb_iter: usize,
+        init: InitMethod,
+        rng: &mut Xoshiro256PlusPlus,
+    ) -> Result<(KmedoidState<DataId>, (usize, f32)), CoresetError> {
+        //
+        let cpu_start = ProcessTime::now();
+        let sys_now = SystemTime::now();
         let mut perturbation_set = Vec::<(usize, usize)>::new();
         //
-        // initialize: random selection of centers, dispatch points to nearest centers
+        // initialize: selection of centers, dispatch points to nearest centers
         //
-        let mut centers = self.max_cost_init(); // select nb_cluster different points
+        log::info!("kmedoid initialization method : {:?}", init);
+        let mut centers = match init {
+            InitMethod::MaxCost => self.max_cost_init()?, // select nb_cluster different points
+            InitMethod::MaxDist => self.max_dist_init()?,
+            InitMethod::Random(seed) => self.random_centers_init(seed),
+            InitMethod::KMedoidsPlusPlus(seed) => self.kmedoids_pp_init(seed),
+        };
         log::debug!(
             "   kmedoids  center init done sys time(ms) {:?} cpu time(ms) {:?}\n ",
             sys_now.elapsed().unwrap().as_millis(),
@@ -190,20 +447,29 @@ where
             .iter()
             .map(|i| Medoid::new(self.ids[*i as usize].clone(), *i, f32::MAX))
             .collect();
-        self.medoids.clone_from(&medoids);
+        let mut state = KmedoidState {
+            medoids: medoids.clone(),
+            membership: vec![u32::MAX; self.get_nb_points()],
+        };
         //
         // we set initial state
         // dispatch each point to nearest center, i.e set membership.
         //
         let mut membership_and_dist = self.dispatch_to_medoids(&centers);
-        assert_eq!(self.ids.len(), membership_and_dist.0.len());
+        if membership_and_dist.0.len() != self.ids.len() {
+            return Err(CoresetError::AlgorithmFailure(format!(
+                "Kmedoid::run, {} points dispatched out of {}",
+                membership_and_dist.0.len(),
+                self.ids.len()
+            )));
+        }
         for i in 0..membership_and_dist.0.len() {
-            self.membership[i] = membership_and_dist.0[i].0;
+            state.membership[i] = membership_and_dist.0[i].0;
         }
         let costs = self.compute_medoids_cost(&membership_and_dist); // compute_medoid_cost not called any more after that
-        for i in 0..self.medoids.len() {
-            self.medoids[i].set_cost(costs[i]);
-            self.medoids[i].center = centers[i];
+        for i in 0..state.medoids.len() {
+            state.medoids[i].set_cost(costs[i]);
+            state.medoids[i].center = centers[i];
         }
         // we have centers and cost
         let mut monitoring: Vec<(usize, f32)> = Vec::with_capacity(25);
@@ -220,82 +486,89 @@ where
         //
         // iterate
         //
-        let mut perturbation = false;
-        let mut iteration = 0;
-        loop {
-            // recompute centers from membership and update clusters costs:  Cpu cost is here
-            let centers_and_costs = self.membership_to_centers(&membership_and_dist);
-            assert_eq!(self.nb_cluster, centers_and_costs.0.len());
-            for (i,cc) in centers_and_costs.0.iter().enumerate() {
-                centers[i] = cc.0 as u32
-            }
-            // compute global cost
-            let iter_cost: f32 = centers_and_costs.0.iter().map(|x| x.1).sum();
-            //
-            if iter_cost >= last_cost && !perturbation {
-                log::debug!("iteration got a local minimum : {}", iteration);
-                let res = self.quality_summary(&perturbation_set, false);
-                if res.is_some() {
-                    let couple = res.unwrap();
-                    if couple.0 < couple.1 {
-                        perturbation_set.push(couple);
-                    }
-                    else {
-                        perturbation_set.push((couple.1, couple.0));
-                    }
-                    let size = medoids.len();
-                    medoids.clone_from_slice(&self.medoids[..size]);
-                    perturbation = self.center_perturbation(couple, &mut medoids);
-                    if perturbation {
-                        log::debug!("perturbated couple : {:?}", couple);
-                        for i in 0..medoids.len() {
-                            centers[i] = medoids[i].get_center();
+        if self.optimization != KmedoidOptimization::Swap {
+            let mut perturbation = false;
+            let mut iteration = 0;
+            loop {
+                // recompute centers from membership and update clusters costs:  Cpu cost is here
+                let centers_and_costs = self.membership_to_centers(&membership_and_dist)?;
+                if centers_and_costs.0.len() != self.nb_cluster {
+                    return Err(CoresetError::AlgorithmFailure(format!(
+                        "Kmedoid::run, got {} centers for {} clusters",
+                        centers_and_costs.0.len(),
+                        self.nb_cluster
+                    )));
+                }
+                for (i,cc) in centers_and_costs.0.iter().enumerate() {
+                    centers[i] = cc.0 as u32
+                }
+                // compute global cost
+                let iter_cost: f32 = centers_and_costs.0.iter().map(|x| x.1).sum();
+                //
+                if iter_cost >= last_cost && !perturbation {
+                    log::debug!("iteration got a local minimum : {}", iteration);
+                    let res = self.quality_summary(&state, &perturbation_set, false);
+                    if let Some(couple) = res {
+                        if couple.0 < couple.1 {
+                            perturbation_set.push(couple);
+                        }
+                        else {
+                            perturbation_set.push((couple.1, couple.0));
+                        }
+                        let size = medoids.len();
+                        medoids.clone_from_slice(&state.medoids[..size]);
+                        perturbation = self.center_perturbation(&state.membership, couple, &mut medoids, rng);
+                        if perturbation {
+                            log::debug!("perturbated couple : {:?}", couple);
+                            for i in 0..medoids.len() {
+                                centers[i] = medoids[i].get_center();
+                            }
+                            membership_and_dist = self.dispatch_to_medoids(&centers);
+                        } else {
+                            break;
                         }
-                        membership_and_dist = self.dispatch_to_medoids(&centers);
                     } else {
                         break;
                     }
                 } else {
-                    break;
-                }
-            } else {
-                perturbation = false;
-                last_cost = iter_cost;
-                log::debug!("medoid iteration {}, global cost : {:.3e}", iteration, last_cost);
-                // we must store our best state
-                if iter_cost < best_iter.1 {
-                    log::info!("medoid iteration best : {}, global cost : {:.3e}", iteration, last_cost);
-                    best_iter = (iteration, iter_cost);
-                    self.store_state(&centers_and_costs, &membership_and_dist);
-                }
-                // we must compute distance to new centers and reassign membership
-                membership_and_dist = self.dispatch_to_medoids(&centers);
-                assert_eq!(membership_and_dist.0.len(), self.membership.len());
-                iteration += 1;
-                if iteration >= nb_iter {
-                    log::info!("exiting after nb iteration : {}", iteration);
-                    break;
+                    perturbation = false;
+                    last_cost = iter_cost;
+                    log::debug!("medoid iteration {}, global cost : {:.3e}", iteration, last_cost);
+                    // we must store our best state
+                    if iter_cost < best_iter.1 {
+                        log::info!("medoid iteration best : {}, global cost : {:.3e}", iteration, last_cost);
+                        best_iter = (iteration, iter_cost);
+                        self.store_state(&mut state, &centers_and_costs, &membership_and_dist)?;
+                    }
+                    // we must compute distance to new centers and reassign membership
+                    membership_and_dist = self.dispatch_to_medoids(&centers);
+                    if membership_and_dist.0.len() != state.membership.len() {
+                        return Err(CoresetError::AlgorithmFailure(format!(
+                            "Kmedoid::run, {} points dispatched out of {}",
+                            membership_and_dist.0.len(),
+                            state.membership.len()
+                        )));
+                    }
+                    iteration += 1;
+                    if iteration >= nb_iter {
+                        log::info!("exiting after nb iteration : {}", iteration);
+                        break;
+                    }
                 }
             }
         }
+        if self.optimization != KmedoidOptimization::Alternate {
+            // eager swap starts from stored best state and can only decrease cost
+            let (nb_pass, cost) = self.eager_swap(&mut state, nb_iter);
+            best_iter = (best_iter.0 + nb_pass, cost);
+        }
+        log::debug!("kmedoid run best iter : {}, cost {:.3e}", best_iter.0, best_iter.1);
         //
-        log::info!("======================================================");
-        log::info!("best iter : {}, cost {:.3e}", best_iter.0, best_iter.1);
-        log::info!("======================================================");
-        let cpu_time: Duration = cpu_start.elapsed();
-        println!(
-            "kmedoid compute medians total time sys time(ms) {:?} cpu time(ms) {:?}",
-            sys_now.elapsed().unwrap().as_millis(),
-            cpu_time.as_millis()
-        );
-        //
-        self.quality_summary(&perturbation_set, true);
-        //
-        best_iter
-    } // end of compute_medians
+        Ok((state, best_iter))
+    } // end of run
 
     /// stores data vectors for each cluster
-    pub(crate) fn retrieve_cluster_centers<IterProducer>(&mut self, iter_producer: &IterProducer)
+    pub(crate) fn retrieve_cluster_centers<IterProducer>(&mut self, iter_producer: &IterProducer) -> Result<(), CoresetError>
     where
         IterProducer: MakeIter<Item = (DataId,Vec<T>)>,
     {
@@ -304,10 +577,10 @@ where
         //
         if self.centers.is_some() {
             log::error!("Kmedoid::retrieve_cluster_centers : centers have already been retrived");
-            return;
+            return Ok(());
         }
         let data_iter = iter_producer.makeiter();
-        let centers_ids: Vec<DataId> = self.medoids.iter().map(|m| m.get_center_id()).collect();
+        let centers_ids: Vec<DataId> = self.state.medoids.iter().map(|m| m.get_center_id()).collect();
         let mut centers_data = vec![Vec::<T>::new(); centers_ids.len()];
         //
         let mut nb_found = 0;
@@ -325,33 +598,47 @@ where
             }
         } // end on data
           //
-        assert!(nb_found == centers_ids.len());
+        if nb_found < centers_ids.len() {
+            return Err(CoresetError::MissingData(format!(
+                "Kmedoid::retrieve_cluster_centers found {} centers out of {} in data",
+                nb_found,
+                centers_ids.len()
+            )));
+        }
         //
         self.centers = Some(centers_data);
-    } // end of
+        Ok(())
+    } // end of retrieve_cluster_centers
 
-    fn store_state(&mut self, centers_and_costs: &CenterCost, membership_and_dist: &MemberDist) {
+    fn store_state(&self, state: &mut KmedoidState<DataId>, centers_and_costs: &CenterCost, membership_and_dist: &MemberDist) -> Result<(), CoresetError> {
         //
-        assert_eq!(centers_and_costs.0.len(), self.medoids.len());
+        if centers_and_costs.0.len() != state.medoids.len() {
+            return Err(CoresetError::AlgorithmFailure(format!(
+                "Kmedoid::store_state, got {} centers for {} medoids",
+                centers_and_costs.0.len(),
+                state.medoids.len()
+            )));
+        }
         //
-        for i in 0..self.medoids.len() {
+        for i in 0..state.medoids.len() {
             // we do not update center_id we do not use it, we update only at end
-            self.medoids[i].center = centers_and_costs.0[i].0 as u32;
-            self.medoids[i].center_id = self.ids[centers_and_costs.0[i].0].clone();
-            self.medoids[i].cost = centers_and_costs.0[i].1;
+            state.medoids[i].center = centers_and_costs.0[i].0 as u32;
+            state.medoids[i].center_id = self.ids[centers_and_costs.0[i].0].clone();
+            state.medoids[i].cost = centers_and_costs.0[i].1;
         }
         for i in 0..membership_and_dist.0.len() {
-            self.membership[i] = membership_and_dist.0[i].0;
+            state.membership[i] = membership_and_dist.0[i].0;
         }
+        Ok(())
     } // end of store_state
 
     /// return Medoids
     pub fn get_clusters(&self) -> &Vec<Medoid<DataId>> {
-        &self.medoids
+        &self.state.medoids
     }
 
-    /// returns a reference to center of Medoid of rank if centers have already been calculated, None otherwise.
-    /// Aborts if rank above len
+    /// returns a reference to center of Medoid of rank if centers have already been calculated and rank is less than
+    /// the number of clusters, None otherwise.
     pub fn get_cluster_center(&self, rank: usize) -> Option<&Vec<T>> {
         match &self.centers {
             Some(centers) => {
@@ -362,7 +649,7 @@ where
                         "Kmedois::get_cluster_center rank asked exceeds len, nb cluster is : {}",
                         centers.len()
                     );
-                    std::panic!();
+                    None
                 }
             }
             None => {
@@ -384,30 +671,83 @@ where
 
     /// returns for each points the rank of its cluster
     pub fn get_membership(&self) -> &Vec<u32> {
-        &self.membership
+        &self.state.membership
+    }
+
+    /// returns the cost function used
+    pub fn get_cost_function(&self) -> CostFunction {
+        self.cost_fn
     }
 
     /// return global partition cost
     pub fn get_global_cost(&self) -> f32 {
-        self.medoids.iter().map(|m| m.get_cost()).sum::<f32>()
+        self.state.medoids.iter().map(|m| m.get_cost()).sum::<f32>()
     }
 
     /// return the data id of cluster of rank
-    pub fn get_center_id(&self, k : usize) -> Result<DataId, u8> {
-        if k < self.medoids.len() {
-            Ok(self.medoids[k].get_center_id())
+    pub fn get_center_id(&self, k : usize) -> Result<DataId, CoresetError> {
+        if k < self.state.medoids.len() {
+            Ok(self.state.medoids[k].get_center_id())
         }
         else {
-            Err(1)
+            Err(CoresetError::InvalidParameter(format!("Kmedoid::get_center_id, no cluster of rank {}", k)))
         }
     }
 
+    /// returns the weighted mean silhouette of the current clustering, computed on the cost matrix.  
+    /// For a point i, a(i) is the weighted mean cost from i to the other points of its cluster, b(i) the minimum over other clusters
+    /// of the weighted mean cost from i to points of the cluster and s(i) = (b(i) - a(i)) / max(a(i), b(i)) (0. for a point alone in its cluster).  
+    /// The silhouettes s(i) are averaged with weights of points. Returns 0. if there is only one cluster.  
+    /// Must be called after [compute_medians](Self::compute_medians()), returns [CoresetError::NotComputed] if
+    /// the clustering has not been computed (or was reset by [set_nb_cluster](Self::set_nb_cluster())).
+    pub fn silhouette(&self) -> Result<f64, CoresetError> {
+        let membership = &self.state.membership;
+        if membership.len() != self.get_nb_points() || membership.iter().any(|m| (*m as usize) >= self.nb_cluster) {
+            return Err(CoresetError::NotComputed(
+                "Kmedoid::silhouette, clusters must be computed first".to_string(),
+            ));
+        }
+        if self.nb_cluster < 2 {
+            return Ok(0.);
+        }
+        //
+        let silhouette_i = |i: usize| -> f64 {
+            let mut costs = vec![0.0f64; self.nb_cluster];
+            let mut weights = vec![0.0f64; self.nb_cluster];
+            for (j, m) in membership.iter().enumerate() {
+                if j != i {
+                    costs[*m as usize] += self.weights[j] * self.distance.get(i, j) as f64;
+                    weights[*m as usize] += self.weights[j];
+                }
+            }
+            let c_i = membership[i] as usize;
+            if weights[c_i] <= 0. {
+                return 0.;
+            }
+            let a = costs[c_i] / weights[c_i];
+            let mut b = f64::MAX;
+            for m in 0..self.nb_cluster {
+                if m != c_i && weights[m] > 0. {
+                    b = b.min(costs[m] / weights[m]);
+                }
+            }
+            if b == f64::MAX || a.max(b) <= 0. {
+                return 0.;
+            }
+            (b - a) / a.max(b)
+        };
+        let weighted: Vec<f64> = (0..self.get_nb_points())
+            .into_par_iter()
+            .map(|i| self.weights[i] * silhouette_i(i))
+            .collect();
+        Ok(weighted.iter().sum::<f64>() / self.weights.iter().sum::<f64>())
+    } // end of silhouette
+
     // random initial choice of medoids
-    #[allow(unused)]
-    fn random_centers_init(&mut self) -> Vec<u32> {
+    fn random_centers_init(&self, seed: u64) -> Vec<u32> {
         // we must iterate until we have k different medoids.
         let mut already = vec![false; self.get_nb_points()];
-        let mut rng = Xoshiro256PlusPlus::seed_from_u64(117);
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(seed);
         let between = Uniform::new::<usize, usize>(0, self.get_nb_points());
         let mut centers = Vec::<u32>::with_capacity(self.nb_cluster);
         // get k different centers
@@ -421,8 +761,59 @@ where
         centers
     } // end of random_init
 
+    // weighted k-medoids++ : first center is sampled proportionally to weights, then each new center is sampled
+    // with probability proportional to weight * cost to nearest center already chosen. (Costs in distance matrix are already d^z)
+    fn kmedoids_pp_init(&self, seed: u64) -> Vec<u32> {
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(seed);
+        let nbpoints = self.get_nb_points();
+        let mut centers = Vec::<u32>::with_capacity(self.nb_cluster);
+        //
+        // if weights cannot be used for sampling (all null) we fall back to uniform sampling
+        let first = match WeightedIndex::new(&self.weights) {
+            Ok(sampler) => sampler.sample(&mut rng),
+            Err(_) => Uniform::new::<usize, usize>(0, nbpoints).sample(&mut rng),
+        };
+        centers.push(first as u32);
+        let mut costs_to_centers: Vec<f64> = (0..nbpoints)
+            .map(|i| self.weights[i] * self.distance.get(first, i) as f64)
+            .collect();
+        costs_to_centers[first] = 0.;
+        //
+        while centers.len() < self.nb_cluster {
+            let new = match WeightedIndex::new(&costs_to_centers) {
+                Ok(sampler) => sampler.sample(&mut rng),
+                Err(_) => {
+                    // all remaining points are at null cost of centers, we take the first not yet chosen
+                    let mut already = vec![false; nbpoints];
+                    for c in &centers {
+                        already[*c as usize] = true;
+                    }
+                    match already.iter().position(|a| !a) {
+                        Some(i) => i,
+                        None => break,
+                    }
+                }
+            };
+            log::debug!(
+                "new center; i : {:6}, cost : {:.3e} dist to previous centers : {:.3e}",
+                new,
+                costs_to_centers[new],
+                costs_to_centers[new] / self.weights[new]
+            );
+            centers.push(new as u32);
+            for (i, cost) in costs_to_centers.iter_mut().enumerate() {
+                *cost = cost.min(self.weights[i] * self.distance.get(new, i) as f64);
+            }
+            costs_to_centers[new] = 0.;
+        }
+        //
+        log::info!("number of medoid center initilized : {}", centers.len());
+        //
+        centers
+    } // end of kmedoids_pp_init
+
     // returns center of each cluster
-    fn max_cost_init(&mut self) -> Vec<u32> {
+    fn max_cost_init(&self) -> Result<Vec<u32>, CoresetError> {
         let mut already = vec![false; self.ids.len()];
         let mut centers = Vec::<u32>::with_capacity(self.nb_cluster);
         //
@@ -449,7 +840,7 @@ where
             if i == max_item.0 {
                 costs_to_centers[i] = 0.;
             } else {
-                costs_to_centers[i] = self.distance[[max_item.0, i]] * (*w as f32);
+                costs_to_centers[i] = self.distance.get(max_item.0, i) * (*w as f32);
             }
         }
         // now we create others centers
@@ -466,7 +857,12 @@ where
                 // means we have more clusters than center points
                 break;
             }
-            assert!(!already[max_item.0]);
+            if already[max_item.0] {
+                return Err(CoresetError::AlgorithmFailure(format!(
+                    "Kmedoid::max_cost_init, point {} chosen twice as center",
+                    max_item.0
+                )));
+            }
             already[max_item.0] = true;
             centers.push(max_item.0 as u32);
             log::debug!(
@@ -484,27 +880,23 @@ where
                 if already[i] {
                     costs_to_centers[i] = 0.;
                 } else {
-                    costs_to_centers[i] = costs_to_centers[i].max(self.distance[[max_item.0, i]] * (self.weights[i] as f32));
+                    costs_to_centers[i] = costs_to_centers[i].max(self.distance.get(max_item.0, i) * (self.weights[i] as f32));
                 };
             }
         }
         //
         log::info!("number of medoid center initilized : {}", centers.len());
         //
-        centers
+        Ok(centers)
     } // end of max_cost_init
 
-    #[allow(unused)]
-    fn max_dist_init(&mut self) -> Vec<u32> {
+    fn max_dist_init(&self) -> Result<Vec<u32>, CoresetError> {
         //
         let mut already = vec![false; self.ids.len()];
         let mut centers = Vec::<u32>::with_capacity(self.nb_cluster);
         //
         // We use a weight rescaling to avoid weight being much larger than distances
         //
-        let mut rng = Xoshiro256PlusPlus::seed_from_u64(117);
-        let between = Uniform::new::<u32, u32>(0, self.get_nb_points() as u32);
-        let first = between.sample(&mut rng);
         // choose point of maximal weight
         let mut max_item = (0, self.weights[0]);
         for i in 1..self.weights.len() {
@@ -521,18 +913,23 @@ where
             0.
         );
         // now search a center for each other cluster
-        assert_eq!(already.len(), self.distance.ncols());
+        if already.len() != self.distance.dim() {
+            return Err(CoresetError::AlgorithmFailure(format!(
+                "Kmedoid::max_dist_init, {} points and cost matrix of dimension {}",
+                already.len(),
+                self.distance.dim()
+            )));
+        }
         // 
-        for c in 1..self.nb_cluster {
-            let dmax: f32 = 0.;
+        for _ in 1..self.nb_cluster {
             // search element furthest away from already chosen centers
             let mut cost_item: (usize, f32, f32) = (usize::MAX, -1., -1.);
-            for (i,before) in already.iter().enumerate().take(self.distance.ncols()) {
+            for (i,before) in already.iter().enumerate().take(self.distance.dim()) {
                 if *before {
                     continue;
                 }
                 for c in &centers {
-                    let dist = self.distance[[i, *c as usize]];
+                    let dist = self.distance.get(i, *c as usize);
                     let cost = dist * (self.weights[i] as f32);
                     if cost > cost_item.1 {
                         cost_item = (i, cost, dist);
@@ -540,8 +937,13 @@ where
                 }
             }
             // we furthest point from previous censter, we take it as a new center
+            if cost_item.0 == usize::MAX || already[cost_item.0] {
+                return Err(CoresetError::AlgorithmFailure(format!(
+                    "Kmedoid::max_dist_init, no new center found after {} centers",
+                    centers.len()
+                )));
+            }
             centers.push(cost_item.0 as u32);
-            assert!(!already[cost_item.0]);
             already[cost_item.0] = true;
             log::info!(
                 "new center; i : {:6}, cost : {:.3e} dist to previous centers : {:.3e}",
@@ -551,12 +953,149 @@ where
             );
         }
         //
-        centers
+        Ok(centers)
+    }
+
+    // Weighted eager swap phase of FasterPAM, starting from current medoids.
+    // Points are scanned cyclically, each non medoid point x is tried as replacement of the medoid m minimizing
+    // the change of cost and the swap is done as soon as it decreases the global cost.
+    // For each point j of weight w(j), with dn(j) (resp. ds(j)) cost to nearest (resp. second nearest) medoid, the change
+    // of cost when swapping m with x is :
+    //     sum_{j, d(j,x) < dn(j)} w(j) * (d(j,x) - dn(j)) + sum_{j in m, d(j,x) >= dn(j)} w(j) * (min(d(j,x), ds(j)) - dn(j))
+    // The first term does not depend on m so one scan of points gives the best medoid to remove.
+    // Stops after a whole cycle without swap or after nb_pass passes. Returns (nb passes, global cost)
+    fn eager_swap(&self, state: &mut KmedoidState<DataId>, nb_pass: usize) -> (usize, f32) {
+        //
+        let cpu_start = ProcessTime::now();
+        let sys_now = SystemTime::now();
+        //
+        let nbpoints = self.get_nb_points();
+        let mut centers: Vec<u32> = state.medoids.iter().map(|m| m.get_center()).collect();
+        let mut is_center = vec![false; nbpoints];
+        for c in &centers {
+            is_center[*c as usize] = true;
+        }
+        let mut nearest = self.dispatch_to_two_medoids(&centers);
+        let mut cost = self.nearest_cost(&nearest);
+        log::info!("kmedoid eager swap, initial global cost : {:.3e}", cost);
+        //
+        let mut delta = vec![0.0f64; centers.len()];
+        // last point swapped, nbpoints means no swap yet
+        let mut last_swap = nbpoints;
+        let mut nb_swap = 0usize;
+        let mut pass = 0;
+        'passes: while pass < nb_pass {
+            pass += 1;
+            for x in 0..nbpoints {
+                if x == last_swap {
+                    // a whole cycle without improvement
+                    break 'passes;
+                }
+                if is_center[x] {
+                    continue;
+                }
+                // scan points
+                delta.fill(0.);
+                let mut acc = 0.0f64;
+                for (j, (m, dn, ds)) in nearest.0.iter().enumerate() {
+                    let djx = self.distance.get(j, x);
+                    if djx < *dn {
+                        acc += self.weights[j] * (djx - *dn) as f64;
+                    } else {
+                        delta[*m as usize] += self.weights[j] * (djx.min(*ds) - *dn) as f64;
+                    }
+                }
+                let mut best = (0usize, delta[0]);
+                for (m, d) in delta.iter().enumerate().skip(1) {
+                    if *d < best.1 {
+                        best = (m, *d);
+                    }
+                }
+                let change = best.1 + acc;
+                // avoid swapping on rounding errors
+                if change < -1.0e-10 * cost {
+                    log::trace!(
+                        "swapping medoid {} center {} with point {}, cost change : {:.3e}",
+                        best.0,
+                        centers[best.0],
+                        x,
+                        change
+                    );
+                    is_center[centers[best.0] as usize] = false;
+                    is_center[x] = true;
+                    centers[best.0] = x as u32;
+                    nearest = self.dispatch_to_two_medoids(&centers);
+                    cost = self.nearest_cost(&nearest);
+                    last_swap = x;
+                    nb_swap += 1;
+                }
+            }
+            log::debug!("eager swap pass {}, nb swap : {}, global cost : {:.3e}", pass, nb_swap, cost);
+            if nb_swap == 0 {
+                break;
+            }
+        }
+        //
+        // store state
+        let mut costs = vec![0.0f64; centers.len()];
+        for (j, (m, dn, _)) in nearest.0.iter().enumerate() {
+            state.membership[j] = *m;
+            costs[*m as usize] += self.weights[j] * *dn as f64;
+        }
+        for (m, c) in centers.iter().enumerate() {
+            state.medoids[m].center = *c;
+            state.medoids[m].center_id = self.ids[*c as usize].clone();
+            state.medoids[m].cost = costs[m] as f32;
+        }
+        log::info!(
+            "kmedoid eager swap, nb pass : {}, nb swap : {}, global cost : {:.3e}",
+            pass,
+            nb_swap,
+            cost
+        );
+        log::info!(
+            "kmedoid eager swap sys time(ms) {:?} cpu time(ms) {:?}\n ",
+            sys_now.elapsed().unwrap().as_millis(),
+            cpu_start.elapsed().as_millis()
+        );
+        //
+        (pass, cost as f32)
+    } // end of eager_swap
+
+    // dispatch each point to nearest center, returns for each point rank of nearest center, distance to it and distance to second nearest center
+    // (infinite if there is only one center)
+    fn dispatch_to_two_medoids(&self, centers: &[u32]) -> NearestTwo {
+        let nearest: Vec<(u32, f32, f32)> = (0..self.get_nb_points())
+            .into_par_iter()
+            .map(|i| {
+                let mut best = (0u32, f32::INFINITY, f32::INFINITY);
+                for (m, c) in centers.iter().enumerate() {
+                    let d = self.distance.get(i, *c as usize);
+                    if d < best.1 {
+                        best = (m as u32, d, best.1);
+                    } else if d < best.2 {
+                        best.2 = d;
+                    }
+                }
+                best
+            })
+            .collect();
+        NearestTwo(nearest)
+    } // end of dispatch_to_two_medoids
+
+    // global cost of a dispatching
+    fn nearest_cost(&self, nearest: &NearestTwo) -> f64 {
+        nearest
+            .0
+            .iter()
+            .zip(self.weights.iter())
+            .map(|((_, dn, _), w)| w * *dn as f64)
+            .sum()
     }
 
     // given centers at given iteration, dispach each point to nearest center.
     // dispatch data to medoids. Returns for each data point cluster number and distance to center of the cluster
-    fn dispatch_to_medoids(&mut self, centers: &[u32]) -> MemberDist {
+    fn dispatch_to_medoids(&self, centers: &[u32]) -> MemberDist {
         //
         let membership_dist: Vec<(u32, f32)> = (0..self.get_nb_points())
             .into_par_iter()
@@ -570,15 +1109,14 @@ where
     // find medoid for point i, returns rank of (cluster) center nearest to i and distance to center
     fn find_medoid_for_i(&self, i: usize, centers: &[u32]) -> (u32, f32) {
         //
-        let rowi = self.distance.row(i);
         let mut best_m = 0u32;
-        let mut best_dist = rowi[centers[0] as usize];
+        let mut best_dist = self.distance.get(i, centers[0] as usize);
         for (m,c) in centers.iter().enumerate() {
-            let test_m = *c;
-            if rowi[test_m as usize] < best_dist {
+            let d = self.distance.get(i, *c as usize);
+            if d < best_dist {
                 // affect to best medoid index
                 best_m = m as u32;
-                best_dist = rowi[test_m as usize];
+                best_dist = d;
             }
         }
         (best_m, best_dist)
@@ -611,7 +1149,12 @@ where
         println!(" statistics on cluster size and cost by element");
         println!(" proba        size     cost/size ");
         for r in ratio {
-            println!(" {:.2e}       {:^6}      {:^6.2e}", r, q_size.query(r).unwrap().1, q_cost_size.query(r).unwrap().1);
+            println!(
+                " {:.2e}       {:^6}      {:^6.2e}",
+                r,
+                q_size.query(r).map_or(0, |q| q.1),
+                q_cost_size.query(r).map_or(f64::NAN, |q| q.1)
+            );
         }
         //
         if detailed {
@@ -635,7 +1178,7 @@ where
     // Cost for center is defined by minimizing cost :  Sum{i in m} w(i) * dist(i,c)
     //
     // This function returns for each cluster a vector (of length nbcluster) of 2-uple containing (center, cluster cost)
-    fn membership_to_centers(&self, membership: &MemberDist) -> CenterCost {
+    fn membership_to_centers(&self, membership: &MemberDist) -> Result<CenterCost, CoresetError> {
         //
         // each point i belongs to a cluster, we update the j term contribution inside the same cluster.
         // at end of loop we have contributions of each term to its cluster
@@ -647,17 +1190,23 @@ where
             for j in 0..self.get_nb_points() {
                 // if same medoid, update cost
                 if j != i && membership.0[j].0 == i_cluster {
-                    cost += (self.distance[[i, j]] as f64) * self.weights[j];
+                    cost += (self.distance.get(i, j) as f64) * self.weights[j];
                 }
             }
             cost
         };
         // TODO: iterate and collect!
-        assert_eq!(self.get_nb_points(), self.distance.nrows());
+        if self.get_nb_points() != self.distance.dim() {
+            return Err(CoresetError::AlgorithmFailure(format!(
+                "Kmedoid::membership_to_centers, {} points and cost matrix of dimension {}",
+                self.get_nb_points(),
+                self.distance.dim()
+            )));
+        }
         let cost : Vec<f32> = if self.get_nb_points() <= 1000 {
-            (0..self.distance.nrows()).map(| i | cost_i(i) as f32).collect()
+            (0..self.distance.dim()).map(| i | cost_i(i) as f32).collect()
         } else {
-            (0..self.distance.nrows())
+            (0..self.distance.dim())
                 .into_par_iter()
                 .map(|i| cost_i(i) as f32)
                 .collect()
@@ -668,14 +1217,21 @@ where
             .collect();
         for (i,member) in membership.0.iter().enumerate() {
             let c = member.0;
-            assert!((c as usize) < centers.len());
+            if c as usize >= centers.len() {
+                return Err(CoresetError::AlgorithmFailure(format!(
+                    "Kmedoid::membership_to_centers, point {} in cluster {} out of {}",
+                    i,
+                    c,
+                    centers.len()
+                )));
+            }
             if cost[i] < centers[c as usize].1 {
                 centers[c as usize].1 = cost[i];
                 centers[c as usize].0 = i;
             }
         }
         //
-        CenterCost(centers)
+        Ok(CenterCost(centers))
     } // end of from_membership_to_centers
 
 
@@ -683,37 +1239,38 @@ where
     // computes statistics (quantiles) of distances to their centers
     // At this final time we can access internal fields membership and medoids state
     // Returns possibly a candidate 2-uple of medoid center to be perturbated.
-    fn quality_summary(&self, perturbation_set : &[(usize, usize)], end : bool) -> Option<(usize, usize)> {
+    fn quality_summary(&self, state: &KmedoidState<DataId>, perturbation_set : &[(usize, usize)], end : bool) -> Option<(usize, usize)> {
         log::debug!("\n in quality_summary");
         //
         // We compute distance of items to center of their centroid.
         //
         if end {
             let mut q_dist = CKMS::<f32>::new(0.01);
-            for i in 0..self.distance.nrows() {
-                let m = self.membership[i];
-                let c = self.medoids[m as usize].center as usize;
-                q_dist.insert(self.distance[[i, c]]);
+            for i in 0..self.distance.dim() {
+                let m = state.membership[i];
+                let c = state.medoids[m as usize].center as usize;
+                q_dist.insert(self.distance.get(i, c));
             }
+            // an empty estimator has no quantile
+            let at = |r: f64| q_dist.query(r).map_or(f32::NAN, |q| q.1);
             println!("\n distance to centroid quantiles at 0.01 :  {:.2e} , 0.025 : {:.2e}, 0.25 : {:.2e}, 0.5 : {:.2e}, 0.75 : {:.2e}   0.99 : {:.2e}\n", 
-                q_dist.query(0.01).unwrap().1,  q_dist.query(0.025).unwrap().1,  q_dist.query(0.25).unwrap().1,
-                q_dist.query(0.5).unwrap().1, q_dist.query(0.75).unwrap().1, q_dist.query(0.99).unwrap().1);
+                at(0.01), at(0.025), at(0.25), at(0.5), at(0.75), at(0.99));
         }
         //
         let mut medoids_size = vec![0u32; self.nb_cluster];
         let mut medoids_dist_mean = vec![0.0f32; self.nb_cluster];
-        for i in 0..self.membership.len() {
-            let m = self.membership[i] as usize;
+        for i in 0..state.membership.len() {
+            let m = state.membership[i] as usize;
             medoids_size[m] += 1;
-            medoids_dist_mean[m] += self.distance[[i, self.medoids[m].center as usize]];
+            medoids_dist_mean[m] += self.distance.get(i, state.medoids[m].center as usize);
         } 
-        for m in 0..self.medoids.len() {
+        for m in 0..state.medoids.len() {
             medoids_dist_mean[m] /= medoids_size[m] as f32;
             log::debug!(
                 "medoid : {}, size : {:5} , cost {:.2e} , mean dist : {:.2e}",
                 m,
                 medoids_size[m],
-                self.medoids[m].cost,
+                state.medoids[m].cost,
                 medoids_dist_mean[m]
             )
         }
@@ -729,12 +1286,12 @@ where
         let mut dmin = f32::MAX;
         //
         for i in 0..self.nb_cluster {
-            let i_center = self.medoids[i].get_center() as usize;
+            let i_center = state.medoids[i].get_center() as usize;
             for j in 0..i {
-                if perturbation_set.last().is_some() &&  *perturbation_set.last().unwrap() == (j,i) {
+                if perturbation_set.last() == Some(&(j, i)) {
                     continue;
                 }
-                let d = self.distance[[i_center, self.medoids[j].get_center() as usize]];
+                let d = self.distance.get(i_center, state.medoids[j].get_center() as usize);
                 let crit = 2. * d / (medoids_dist_mean[i] + medoids_dist_mean[j]);
                 if crit < dmin  {
                     log::debug!(
@@ -760,31 +1317,30 @@ where
             quantiles.insert(*w);
         }
         println!("statistics on items weights to cluster");
+        // an empty estimator has no quantile
+        let at = |r: f64| quantiles.query(r).map_or(f64::NAN, |q| q.1);
         println!("\n weights quantiles at  0.01 :  {:.2e} , 0.025 : {:.2e}, 0.05 : {:.2e}, 0.5 : {:.2e}, 0.75 : {:.2e}   0.99 : {:.2e}\n", 
-                quantiles.query(0.01).unwrap().1,  quantiles.query(0.025).unwrap().1,  quantiles.query(0.05).unwrap().1,
-                quantiles.query(0.5).unwrap().1, quantiles.query(0.75).unwrap().1, quantiles.query(0.99).unwrap().1);
+                at(0.01), at(0.025), at(0.05), at(0.5), at(0.75), at(0.99));
         //
         let mut rng = Xoshiro256PlusPlus::seed_from_u64(2833);
-        let nbrow = self.distance.nrows();
-        let between = Uniform::new::<usize, usize>(0, nbrow);
-        let to_sample = 10000.min(nbrow * (nbrow - 1) / 2);
+        let nbrow = self.distance.dim();
+        let to_sample = 10000.min(nbrow * nbrow.saturating_sub(1) / 2);
         let mut nb_sampled = 0;
         let mut quantiles = CKMS::<f32>::new(0.01);
-        loop {
+        // less than 2 points, there is no couple to sample
+        let between = Uniform::new::<usize, usize>(0, nbrow.max(1));
+        while nb_sampled < to_sample {
             let i = between.sample(&mut rng);
             let j = between.sample(&mut rng);
             if i != j {
-                quantiles.insert(self.distance[[i, j]]);
+                quantiles.insert(self.distance.get(i, j));
                 nb_sampled += 1;
-                if nb_sampled >= to_sample {
-                    break;
-                }
             }
         }
         println!("statistics on distances between points to cluster");
+        let at = |r: f64| quantiles.query(r).map_or(f32::NAN, |q| q.1);
         println!("\n distance quantiles at 0.0001 : {:.2e} , 0.001 : {:.2e}, 0.01 :  {:.2e} , 0.025 : {:.2e}, 0.05 : {:.2e},, 0.5 : {:.2e}, 0.75 : {:.2e}   0.99 : {:.2e}\n", 
-            quantiles.query(0.0001).unwrap().1, quantiles.query(0.001).unwrap().1,  quantiles.query(0.01).unwrap().1,  quantiles.query(0.025).unwrap().1, 
-            quantiles.query(0.05).unwrap().1, quantiles.query(0.5).unwrap().1, quantiles.query(0.75).unwrap().1, quantiles.query(0.99).unwrap().1);
+            at(0.0001), at(0.001), at(0.01), at(0.025), at(0.05), at(0.5), at(0.75), at(0.99));
         //
         quantiles
     }
@@ -793,14 +1349,15 @@ where
     // perturbation of centers of medoids i and j , call dispatch_to_medoids and return new assignment
     // centers i and j are chosen are abnormally close
     fn center_perturbation(
-        &mut self,
+        &self,
+        membership: &[u32],
         (m1, m2): (usize, usize),
         medoids: &mut [Medoid<DataId>],
+        rng: &mut Xoshiro256PlusPlus,
     ) -> bool {
         //
         log::debug!("in center_perturbation m1 = {}  m2 = {}", m1, m2);
         //
-        let mut rng = rand::thread_rng();
         let unif = rand::distributions::Uniform::new(0., 1.);
         //
         //
@@ -813,9 +1370,9 @@ where
         let mut max_d = 0.0f32;
         let mut max_i = usize::MAX;
         let old_center = medoids[changed].get_center() as usize;
-        for i in 0..self.membership.len() {
-            if self.membership[i] as usize == changed {
-                let d = self.distance[[i, old_center]];
+        for (i, m) in membership.iter().enumerate() {
+            if *m as usize == changed {
+                let d = self.distance.get(i, old_center);
                 if d > max_d {
                     max_d = d;
                     max_i = i;
@@ -832,3 +1389,186 @@ where
         //
     } // end of center_perturbation
 } // end of impl Kmedoid
+
+//========================================================================================
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::collections::HashMap;
+
+    fn log_init_test() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    // a weighted coreset of nbdata points around nb_center centers
+    fn make_coreset(nbdata: usize, nb_center: usize, seed: u64) -> CoreSet<usize, f32, DistL2> {
+        make_coreset_cost(nbdata, nb_center, seed, CostFunction::KMedian)
+    }
+
+    fn make_coreset_cost(nbdata: usize, nb_center: usize, seed: u64, cost_fn: CostFunction) -> CoreSet<usize, f32, DistL2> {
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(seed);
+        let unif = Uniform::<f32>::new(-10., 10.);
+        let centers: Vec<Vec<f32>> = (0..nb_center)
+            .map(|_| (0..5).map(|_| unif.sample(&mut rng)).collect())
+            .collect();
+        let normal = rand_distr::Normal::<f32>::new(0., 2.).unwrap();
+        let datas_wid: Vec<(usize, Vec<f32>)> = (0..nbdata)
+            .map(|i| (i, centers[i % nb_center].iter().map(|x| x + normal.sample(&mut rng)).collect()))
+            .collect();
+        let id_weight_map: HashMap<usize, f64> = (0..nbdata).map(|i| (i, rng.gen_range(1. ..10.))).collect();
+        CoreSet::new(id_weight_map, Some(datas_wid), DistL2, cost_fn).unwrap()
+    }
+
+    #[test]
+    fn test_kmedoid_eager_swap() {
+        log_init_test();
+        //
+        let coreset = make_coreset(1000, 8, 4371);
+        let mut costs = Vec::<f32>::new();
+        for optimization in [KmedoidOptimization::Alternate, KmedoidOptimization::Swap, KmedoidOptimization::AlternateThenSwap] {
+            let mut kmedoids = Kmedoid::new(&coreset, 8).unwrap();
+            kmedoids.set_optimization(optimization);
+            let (_, cost) = kmedoids.compute_medians(20, InitMethod::MaxCost).unwrap();
+            log::info!("optimization : {:?}, cost : {:.3e}", optimization, cost);
+            // returned cost is the cost of stored medoids
+            assert!((kmedoids.get_global_cost() - cost).abs() <= 1.0e-4 * cost);
+            costs.push(cost);
+        }
+        // swap phase can only improve alternate result
+        assert!(costs[2] <= costs[0]);
+    } // end of test_kmedoid_eager_swap
+
+    #[test]
+    fn test_kmedoid_init() {
+        log_init_test();
+        //
+        let coreset = make_coreset(1000, 8, 4371);
+        for init in [InitMethod::MaxCost, InitMethod::MaxDist, InitMethod::Random(117), InitMethod::KMedoidsPlusPlus(117)] {
+            let mut kmedoids = Kmedoid::new(&coreset, 8).unwrap();
+            let (_, cost) = kmedoids.compute_medians(20, init).unwrap();
+            log::info!("init : {:?}, cost : {:.3e}", init, cost);
+            // medoids must be distinct points
+            let mut centers: Vec<u32> = kmedoids.get_clusters().iter().map(|m| m.get_center()).collect();
+            centers.sort_unstable();
+            centers.dedup();
+            assert_eq!(centers.len(), 8);
+        }
+        // seeded initialization is reproducible (swap phase is deterministic)
+        let mut costs = Vec::<f32>::new();
+        for _ in 0..2 {
+            let mut kmedoids = Kmedoid::new(&coreset, 8).unwrap();
+            kmedoids.set_optimization(KmedoidOptimization::Swap);
+            costs.push(kmedoids.compute_medians(20, InitMethod::KMedoidsPlusPlus(4577)).unwrap().1);
+        }
+        assert_eq!(costs[0], costs[1]);
+        // alternate optimization uses perturbations, reproducible with a given seed
+        let mut costs = Vec::<f32>::new();
+        for _ in 0..2 {
+            let mut kmedoids = Kmedoid::new(&coreset, 8).unwrap();
+            kmedoids.set_seed(7121);
+            costs.push(kmedoids.compute_medians(20, InitMethod::KMedoidsPlusPlus(4577)).unwrap().1);
+        }
+        assert_eq!(costs[0], costs[1]);
+    } // end of test_kmedoid_init
+
+    #[test]
+    fn test_kmedoid_restarts() {
+        log_init_test();
+        //
+        let coreset = make_coreset(1000, 8, 4371);
+        let mut kmedoids = Kmedoid::new(&coreset, 8).unwrap();
+        kmedoids.set_optimization(KmedoidOptimization::AlternateThenSwap);
+        let (best, costs) = kmedoids.compute_medians_restarts(6, 20, 4577).unwrap();
+        assert_eq!(costs.len(), 6);
+        log::info!("restarts costs : {:?}", costs);
+        // the kept solution is the best one and is stored
+        assert!(costs.iter().all(|c| costs[best].1 <= c.1));
+        assert!((kmedoids.get_global_cost() - costs[best].1).abs() <= 1.0e-4 * costs[best].1);
+        // restarts are reproducible
+        let mut kmedoids = Kmedoid::new(&coreset, 8).unwrap();
+        kmedoids.set_optimization(KmedoidOptimization::AlternateThenSwap);
+        let (best2, costs2) = kmedoids.compute_medians_restarts(6, 20, 4577).unwrap();
+        assert_eq!(best, best2);
+        assert_eq!(costs, costs2);
+    } // end of test_kmedoid_restarts
+
+    #[test]
+    fn test_kmedoid_null_weights() {
+        log_init_test();
+        //
+        let datas_wid: Vec<(usize, Vec<f32>)> = (0..50).map(|i| (i, vec![i as f32, 0.])).collect();
+        let id_weight_map: HashMap<usize, f64> = (0..50).map(|i| (i, 0.)).collect();
+        let coreset = CoreSet::new(id_weight_map, Some(datas_wid), DistL2, CostFunction::KMedian).unwrap();
+        let mut kmedoids = Kmedoid::new(&coreset, 3).unwrap();
+        assert!(matches!(
+            kmedoids.compute_medians(10, InitMethod::MaxCost),
+            Err(CoresetError::InvalidParameter(_))
+        ));
+        assert!(matches!(
+            kmedoids.compute_medians_restarts(2, 10, 4577),
+            Err(CoresetError::InvalidParameter(_))
+        ));
+    } // end of test_kmedoid_null_weights
+
+    #[test]
+    fn test_kmedoid_silhouette() {
+        log_init_test();
+        //
+        let coreset = make_coreset(1000, 8, 4371);
+        let mut kmedoids = Kmedoid::new(&coreset, 8).unwrap();
+        // no clustering yet
+        assert!(matches!(kmedoids.silhouette(), Err(CoresetError::NotComputed(_))));
+        kmedoids.compute_medians(20, InitMethod::KMedoidsPlusPlus(4577)).unwrap();
+        let silhouette = kmedoids.silhouette().unwrap();
+        log::info!("silhouette : {:.3e}", silhouette);
+        assert!((-1. ..=1.).contains(&silhouette));
+        // clustering is reset by a change of number of clusters
+        kmedoids.set_nb_cluster(6).unwrap();
+        assert!(matches!(kmedoids.silhouette(), Err(CoresetError::NotComputed(_))));
+    } // end of test_kmedoid_silhouette
+
+    #[test]
+    fn test_kmedoid_storage() {
+        log_init_test();
+        //
+        let coreset = make_coreset(1000, 8, 4371);
+        let mut dense = Kmedoid::new(&coreset, 8).unwrap();
+        let (_, cost) = dense.compute_medians(20, InitMethod::KMedoidsPlusPlus(4577)).unwrap();
+        for storage in [MatrixStorage::Condensed, MatrixStorage::OnTheFly(100)] {
+            let mut kmedoids = Kmedoid::with_storage(&coreset, 8, storage).unwrap();
+            let (_, cost_s) = kmedoids.compute_medians(20, InitMethod::KMedoidsPlusPlus(4577)).unwrap();
+            log::info!("storage : {:?}, cost : {:.3e}", storage, cost_s);
+            assert_eq!(cost, cost_s);
+        }
+        // bf16 rounding can change some choices, cost must stay close
+        let mut kmedoids = Kmedoid::with_storage(&coreset, 8, MatrixStorage::CondensedBf16).unwrap();
+        let (_, cost_s) = kmedoids.compute_medians(20, InitMethod::KMedoidsPlusPlus(4577)).unwrap();
+        log::info!("storage : CondensedBf16, cost : {:.3e}", cost_s);
+        assert!((cost - cost_s).abs() <= 0.05 * cost);
+    } // end of test_kmedoid_storage
+
+    // with KMeans cost the cost of the partition is the weighted sum of squared distances to medoids
+    #[test]
+    fn test_kmedoid_kmeans_cost() {
+        log_init_test();
+        //
+        let coreset = make_coreset_cost(1000, 8, 4371, CostFunction::KMeans);
+        let mut kmedoids = Kmedoid::new(&coreset, 8).unwrap();
+        assert_eq!(kmedoids.get_cost_function(), CostFunction::KMeans);
+        let (_, cost) = kmedoids.compute_medians(20, InitMethod::KMedoidsPlusPlus(4577)).unwrap();
+        //
+        let points: HashMap<usize, &Vec<f32>> = coreset.get_data_points().unwrap().iter().map(|(id, v)| (*id, v)).collect();
+        let membership = kmedoids.get_membership();
+        let mut expected = 0f64;
+        for (rank, id) in kmedoids.ids.iter().enumerate() {
+            let center = kmedoids.get_cluster_center(membership[rank] as usize).unwrap();
+            let dist = DistL2.eval(points[id], center) as f64;
+            expected += coreset.get_weight(id).unwrap() * dist * dist;
+        }
+        log::info!("kmeans cost : {:.3e}, weighted sum of squared distances : {:.3e}", cost, expected);
+        assert!((kmedoids.get_global_cost() as f64 - expected).abs() <= 1.0e-3 * expected);
+        assert!((cost as f64 - expected).abs() <= 1.0e-3 * expected);
+    } // end of test_kmedoid_kmeans_cost
+} // end of mod tests
@@ -1,5 +1,9 @@
 //! A tiny module to have k-median with weighted data dedicated to coreset postprocessing
 //!
+mod costmatrix;
 mod kmedoid;
+mod selection;
 
+pub use costmatrix::*;
 pub use kmedoid::*;
+pub use selection::*;
@@ -0,0 +1,361 @@
+//! Choice of the number of clusters on a coreset.
+//!
+//! A range of k is swept with [Kmedoid] on a single coreset, the distance matrix being computed once.
+//! For each k we compute :
+//!     - the cost of the clustering, used to find the elbow of the cost curve
+//!     - the weighted silhouette, see [Kmedoid::silhouette()]
+//!     - the gap statistic (Tibshirani Walther Hastie. Estimating the number of clusters in a data set via the gap statistic 2001).
+//!       Reference data sets are obtained by permuting independently each coordinate of the coreset points, which keeps
+//!       marginal distributions and destroys cluster structure.
+//!
+//! As the coreset has a few thousands points the sweep is cheap.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+use rand_xoshiro::rand_core::SeedableRng;
+use rand_xoshiro::Xoshiro256PlusPlus;
+
+use cpu_time::ProcessTime;
+use std::time::SystemTime;
+
+use anndists::dist::*;
+
+use super::kmedoid::*;
+use crate::error::CoresetError;
+use crate::sensitivity::*;
+
+/// scores of a clustering with k clusters
+#[derive(Copy, Clone, Debug)]
+pub struct KScore {
+    k: usize,
+    cost: f64,
+    silhouette: f64,
+    // gap statistic and its standard deviation (corrected for simulation error)
+    gap: Option<(f64, f64)>,
+}
+
+impl KScore {
+    /// number of clusters
+    pub fn get_k(&self) -> usize {
+        self.k
+    }
+
+    /// cost of the clustering on the coreset
+    pub fn get_cost(&self) -> f64 {
+        self.cost
+    }
+
+    /// weighted silhouette of the clustering
+    pub fn get_silhouette(&self) -> f64 {
+        self.silhouette
+    }
+
+    /// gap statistic and its standard deviation, None if no reference data set was asked
+    pub fn get_gap(&self) -> Option<(f64, f64)> {
+        self.gap
+    }
+} // end of impl KScore
+
+/// Result of [select_nb_cluster()]: a table of scores by k and the k chosen by each criterion.
+pub struct KSelection {
+    scores: Vec<KScore>,
+    // k of maximal silhouette
+    silhouette_k: usize,
+    // k at elbow of cost curve
+    elbow_k: usize,
+    // smallest k such that gap(k) >= gap(k+1) - sd(k+1)
+    gap_k: Option<usize>,
+}
+
+impl KSelection {
+    /// returns the table of scores, in increasing order of k
+    pub fn get_scores(&self) -> &Vec<KScore> {
+        &self.scores
+    }
+
+    /// returns the recommended number of clusters, the one maximizing silhouette
+    pub fn get_recommended_k(&self) -> usize {
+        self.silhouette_k
+    }
+
+    /// returns the k maximizing weighted silhouette
+    pub fn get_silhouette_k(&self) -> usize {
+        self.silhouette_k
+    }
+
+    /// returns the k at the elbow of the cost curve
+    pub fn get_elbow_k(&self) -> usize {
+        self.elbow_k
+    }
+
+    /// returns the k chosen by gap statistic, None if no reference data set was asked
+    pub fn get_gap_k(&self) -> Option<usize> {
+        self.gap_k
+    }
+
+    /// prints the table of scores
+    pub fn summary(&self) {
+        println!("\n      k        cost      silhouette       gap      gap sd");
+        for score in &self.scores {
+            let (gap, sd) = score.gap.unwrap_or((f64::NAN, f64::NAN));
+            println!(
+                " {:6}   {:.3e}     {:.3e}     {:.3e}   {:.3e}",
+                score.k, score.cost, score.silhouette, gap, sd
+            );
+        }
+        println!(
+            "\n k by silhouette : {}, k by elbow : {}, k by gap : {:?}",
+            self.silhouette_k, self.elbow_k, self.gap_k
+        );
+    }
+} // end of impl KSelection
+
+/// Sweeps k in k_min..=k_max on a coreset and computes for each k cost, weighted silhouette and gap statistic.
+/// - nb_restarts : number of restarts of kmedoid for each k, see [Kmedoid::compute_medians_restarts()]
+/// - nb_iter : maximal number of iterations of kmedoid
+/// - nb_ref : number of reference data sets for gap statistic. 0 disables the gap statistic. (10 is a usual value)
+/// - seed : initializes all random generators, so results are reproducible.
+///
+/// The optimization of [Kmedoid] used is [KmedoidOptimization::AlternateThenSwap].
+pub fn select_nb_cluster<DataId, T, Dist>(
+    coreset: &CoreSet<DataId, T, Dist>,
+    (k_min, k_max): (usize, usize),
+    nb_restarts: usize,
+    nb_iter: usize,
+    nb_ref: usize,
+    seed: u64,
+) -> Result<KSelection, CoresetError>
+where
+    DataId: Eq + Hash + Send + Sync + Clone + Default + std::fmt::Debug,
+    T: Send + Sync + Clone + std::fmt::Debug,
+    Dist: Distance<T> + Send + Sync + Clone,
+{
+    //
+    let cpu_start = ProcessTime::now();
+    let sys_now = SystemTime::now();
+    //
+    if k_min == 0 || k_min > k_max || k_max >= coreset.get_nb_points() {
+        return Err(CoresetError::InvalidParameter(format!(
+            "select_nb_cluster: bad k range {}..={} for a coreset of {} points",
+            k_min,
+            k_max,
+            coreset.get_nb_points()
+        )));
+    }
+    let mut rng = Xoshiro256PlusPlus::seed_from_u64(seed);
+    //
+    let mut kmedoids = Kmedoid::new(coreset, k_min)?;
+    kmedoids.set_optimization(KmedoidOptimization::AlternateThenSwap);
+    let mut scores = Vec::<KScore>::with_capacity(k_max - k_min + 1);
+    for k in k_min..=k_max {
+        kmedoids.set_nb_cluster(k)?;
+        let (best, costs) = kmedoids.compute_medians_restarts(nb_restarts, nb_iter, rng.gen())?;
+        let silhouette = kmedoids.silhouette()?;
+        log::info!(
+            "select_nb_cluster k : {}, cost : {:.3e}, silhouette : {:.3e}",
+            k,
+            costs[best].1,
+            silhouette
+        );
+        scores.push(KScore {
+            k,
+            cost: costs[best].1 as f64,
+            silhouette,
+            gap: None,
+        });
+    }
+    //
+    // gap statistic, log of costs on reference data sets
+    //
+    let gap_k = if nb_ref > 0 {
+        let mut ref_log_costs = vec![Vec::<f64>::with_capacity(nb_ref); scores.len()];
+        for b in 0..nb_ref {
+            let ref_coreset = permuted_coreset(coreset, &mut rng)?;
+            let mut ref_kmedoids = Kmedoid::new(&ref_coreset, k_min)?;
+            ref_kmedoids.set_optimization(KmedoidOptimization::AlternateThenSwap);
+            for (r, k) in (k_min..=k_max).enumerate() {
+                ref_kmedoids.set_nb_cluster(k)?;
+                let (best, costs) =
+                    ref_kmedoids.compute_medians_restarts(nb_restarts, nb_iter, rng.gen())?;
+                log::debug!("reference {}, k : {}, cost : {:.3e}", b, k, costs[best].1);
+                ref_log_costs[r].push((costs[best].1 as f64).ln());
+            }
+        }
+        for (score, log_costs) in scores.iter_mut().zip(ref_log_costs.iter()) {
+            let mean = log_costs.iter().sum::<f64>() / nb_ref as f64;
+            let var = log_costs
+                .iter()
+                .map(|l| (l - mean) * (l - mean))
+                .sum::<f64>()
+                / nb_ref as f64;
+            let sd = var.sqrt() * (1. + 1. / nb_ref as f64).sqrt();
+            score.gap = Some((mean - score.cost.ln(), sd));
+        }
+        Some(gap_choice(&scores))
+    } else {
+        None
+    };
+    //
+    let mut silhouette_k = scores[0];
+    for score in &scores {
+        if score.silhouette > silhouette_k.silhouette {
+            silhouette_k = *score;
+        }
+    }
+    let selection = KSelection {
+        elbow_k: elbow(&scores),
+        silhouette_k: silhouette_k.k,
+        scores,
+        gap_k,
+    };
+    selection.summary();
+    //
+    println!(
+        "\n select_nb_cluster sys time(ms) {:?} cpu time(ms) {:?}",
+        sys_now.elapsed().unwrap().as_millis(),
+        cpu_start.elapsed().as_millis()
+    );
+    //
+    Ok(selection)
+} // end of select_nb_cluster
+
+// builds a reference coreset by permuting independently each coordinate of points. Weights stay attached to ids.
+fn permuted_coreset<DataId, T, Dist>(
+    coreset: &CoreSet<DataId, T, Dist>,
+    rng: &mut Xoshiro256PlusPlus,
+) -> Result<CoreSet<DataId, T, Dist>, CoresetError>
+where
+    DataId: Eq + Hash + Send + Sync + Clone + std::fmt::Debug,
+    T: Send + Sync + Clone,
+    Dist: Distance<T> + Send + Sync + Clone,
+{
+    let datas_wid = coreset.get_data_points().ok_or_else(|| {
+        CoresetError::MissingData("gap statistic needs data vectors of the coreset".to_string())
+    })?;
+    let dim = datas_wid[0].1.len();
+    if datas_wid.iter().any(|(_, v)| v.len() != dim) {
+        return Err(CoresetError::InvalidParameter(
+            "gap statistic needs data vectors of the same dimension".to_string(),
+        ));
+    }
+    let mut permuted: Vec<(DataId, Vec<T>)> = datas_wid.clone();
+    let mut column = Vec::<T>::with_capacity(permuted.len());
+    for c in 0..dim {
+        column.clear();
+        column.extend(datas_wid.iter().map(|(_, v)| v[c].clone()));
+        column.shuffle(rng);
+        for (p, x) in permuted.iter_mut().zip(column.iter()) {
+            p.1[c] = x.clone();
+        }
+    }
+    let id_weight_map: HashMap<DataId, f64> = coreset
+        .get_items()
+        .map(|(id, w)| (id.clone(), *w))
+        .collect();
+    CoreSet::new(
+        id_weight_map,
+        Some(permuted),
+        coreset.get_distance().clone(),
+        coreset.get_cost_function(),
+    )
+} // end of permuted_coreset
+
+// elbow of cost curve : the point of the (normalized) curve farthest below the chord joining its extremities
+fn elbow(scores: &[KScore]) -> usize {
+    if scores.len() < 3 {
+        return scores[0].k;
+    }
+    let first = scores.first().unwrap();
+    let last = scores.last().unwrap();
+    let (cost_min, cost_max) = scores.iter().fold((f64::MAX, f64::MIN), |acc, s| {
+        (acc.0.min(s.cost), acc.1.max(s.cost))
+    });
+    if cost_max <= cost_min {
+        return first.k;
+    }
+    let x = |s: &KScore| (s.k - first.k) as f64 / (last.k - first.k) as f64;
+    let y = |s: &KScore| (s.cost - cost_min) / (cost_max - cost_min);
+    // chord from (0, y(first)) to (1, y(last))
+    let mut best = (first.k, 0.);
+    for s in scores {
+        let chord = y(first) + (y(last) - y(first)) * x(s);
+        let below = chord - y(s);
+        if below > best.1 {
+            best = (s.k, below);
+        }
+    }
+    best.0
+} // end of elbow
+
+// smallest k such that gap(k) >= gap(k+1) - sd(k+1), k of maximal gap if there is none
+fn gap_choice(scores: &[KScore]) -> usize {
+    for w in scores.windows(2) {
+        let (gap, _) = w[0].gap.unwrap();
+        let (gap_next, sd_next) = w[1].gap.unwrap();
+        if gap >= gap_next - sd_next {
+            return w[0].k;
+        }
+    }
+    let mut best = (scores[0].k, f64::MIN);
+    for s in scores {
+        let (gap, _) = s.gap.unwrap();
+        if gap > best.1 {
+            best = (s.k, gap);
+        }
+    }
+    best.0
+} // end of gap_choice
+
+//========================================================================================
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::facility::CostFunction;
+    use rand::distributions::{Distribution, Uniform};
+
+    fn log_init_test() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn test_select_nb_cluster() {
+        log_init_test();
+        // 5 well separated clusters
+        let nbdata = 500;
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(7451);
+        let unif = Uniform::<f32>::new(-50., 50.);
+        let centers: Vec<Vec<f32>> = (0..5)
+            .map(|_| (0..4).map(|_| unif.sample(&mut rng)).collect())
+            .collect();
+        let normal = rand_distr::Normal::<f32>::new(0., 1.).unwrap();
+        let datas_wid: Vec<(usize, Vec<f32>)> = (0..nbdata)
+            .map(|i| {
+                let v = centers[i % 5]
+                    .iter()
+                    .map(|x| x + normal.sample(&mut rng))
+                    .collect();
+                (i, v)
+            })
+            .collect();
+        let id_weight_map: HashMap<usize, f64> =
+            (0..nbdata).map(|i| (i, rng.gen_range(1. ..5.))).collect();
+        let coreset = CoreSet::new(
+            id_weight_map,
+            Some(datas_wid),
+            DistL2,
+            CostFunction::KMedian,
+        )
+        .unwrap();
+        //
+        let selection = select_nb_cluster(&coreset, (2, 8), 4, 20, 5, 4577).unwrap();
+        assert_eq!(selection.get_scores().len(), 7);
+        assert_eq!(selection.get_recommended_k(), 5);
+        assert_eq!(selection.get_elbow_k(), 5);
+        assert_eq!(selection.get_gap_k(), Some(5));
+    } // end of test_select_nb_cluster
+} // end of mod tests